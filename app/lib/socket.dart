@@ -7,9 +7,19 @@ import 'package:network_info_plus/network_info_plus.dart';
 
 import 'exception/not_connected_exception.dart';
 
+// Latency measurement framing, see client/src/protocol.rs
+const _magic = [0x46, 0x4D, 0x4C, 0x54]; // "FMLT"
+const _version = 1;
+const _frameAudio = 1;
+const _framePong = 2;
+const _framePing = 1;
+const _sampleRate = 48000;
+
 class Sender {
   RawServerSocket? _socket;
   RawSocket? _connection;
+  bool _framed = false;
+  final _incoming = BytesBuilder();
 
   Stream<String> start() {
     final controller = StreamController<String>();
@@ -29,7 +39,16 @@ class Sender {
                   controller
                       .add("Client ${event.remoteAddress.address} connected");
                   _connection = event;
-                  event.listen((event) {}, onDone: () {
+                  _framed = false;
+                  _incoming.clear();
+                  event.listen((socketEvent) {
+                    if (socketEvent == RawSocketEvent.read) {
+                      final data = event.read();
+                      if (data != null) {
+                        _handleIncoming(data);
+                      }
+                    }
+                  }, onDone: () {
                     _connection = null;
                     sendStart(controller, ip);
                     debugPrint("Connection closed");
@@ -60,6 +79,61 @@ class Sender {
   }
 
   void sendData(Int16List data) {
-    _connection?.write(data.buffer.asUint8List());
+    final payload = data.buffer.asUint8List();
+    if (!_framed) {
+      _connection?.write(payload);
+      return;
+    }
+    final capturedAt = DateTime.now().microsecondsSinceEpoch -
+        data.length * Duration.microsecondsPerSecond ~/ _sampleRate;
+    final header = ByteData(13)
+      ..setUint8(0, _frameAudio)
+      ..setInt64(1, capturedAt, Endian.little)
+      ..setUint32(9, payload.length, Endian.little);
+    final frame = BytesBuilder(copy: false)
+      ..add(header.buffer.asUint8List())
+      ..add(payload);
+    _connection?.write(frame.takeBytes());
+  }
+
+  void _handleIncoming(Uint8List data) {
+    _incoming.add(data);
+    var bytes = _incoming.toBytes();
+    var offset = 0;
+    if (!_framed) {
+      if (bytes.length < _magic.length + 1) {
+        return;
+      }
+      for (var i = 0; i < _magic.length; i++) {
+        if (bytes[i] != _magic[i]) {
+          _incoming.clear();
+          return;
+        }
+      }
+      if (bytes[_magic.length] != _version) {
+        _incoming.clear();
+        return;
+      }
+      _framed = true;
+      _connection?.write(Uint8List.fromList(_magic));
+      offset = _magic.length + 1;
+    }
+    while (bytes.length - offset >= 9) {
+      final view = ByteData.sublistView(bytes, offset, offset + 9);
+      if (view.getUint8(0) != _framePing) {
+        debugPrint("Unknown client message ${view.getUint8(0)}");
+        offset = bytes.length;
+        break;
+      }
+      final pong = ByteData(17)
+        ..setUint8(0, _framePong)
+        ..setInt64(1, view.getInt64(1, Endian.little), Endian.little)
+        ..setInt64(
+            9, DateTime.now().microsecondsSinceEpoch, Endian.little);
+      _connection?.write(pong.buffer.asUint8List());
+      offset += 9;
+    }
+    _incoming.clear();
+    _incoming.add(bytes.sublist(offset));
   }
 }
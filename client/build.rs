@@ -1,5 +1,6 @@
 fn main() {
-    if cfg!(target_os = "windows") {
+    #[cfg(windows)]
+    {
         let mut res = winres::WindowsResource::new();
         res.set_icon("assets/icon.ico")
             .set("InternalName", "FAST-MIC.EXE")
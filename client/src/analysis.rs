@@ -0,0 +1,280 @@
+//! The waveform, spectrum and levels of the received audio, for the scope
+//! view of the GUI.
+//!
+//! The socket thread only copies the decoded samples to an [`AnalysisTap`],
+//! the [`Analyzer`] works on its own thread and keeps a decimated
+//! [`ScopeSnapshot`] for each [`Stage`].
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::audio::INPUT_SAMPLE_RATE;
+
+/// Samples of a spectrum, about 43ms at 48kHz.
+pub const FFT_SIZE: usize = 2048;
+/// Of the spectrum, logarithmically spaced.
+pub const SPECTRUM_BANDS: usize = 96;
+pub const LOWEST_FREQUENCY: f32 = 20.0;
+/// Samples summed up by a point of the waveform, 5ms at 48kHz.
+pub const WAVEFORM_DECIMATION: usize = 240;
+/// Points of the waveform, 2s at 48kHz.
+pub const WAVEFORM_POINTS: usize = 400;
+/// The level of silence in the spectrum.
+pub const FLOOR_DB: f32 = -120.0;
+
+/// Chunks waiting for the analyzer, beyond that they are dropped.
+const QUEUE: usize = 64;
+/// Weight of the previous spectrum, so it doesn't flicker.
+const SMOOTHING: f32 = 0.5;
+
+/// Where in the processing the samples were taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// As received from the phone.
+    Input,
+    /// After the averaging filter and muting, as played.
+    Processed,
+}
+
+/// What [`Scope`] shows at one moment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScopeSnapshot {
+    /// Oldest first, the lowest and highest sample of each point, between -1
+    /// and 1.
+    pub waveform: Vec<(f32, f32)>,
+    /// Level in dBFS of each band, from [`LOWEST_FREQUENCY`] to half the
+    /// sample rate, or empty before the first [`FFT_SIZE`] samples.
+    pub spectrum: Vec<f32>,
+    /// Of the last [`FFT_SIZE`] samples, between 0 and 1.
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// Turns samples into a [`ScopeSnapshot`].
+pub struct Scope {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// The last [`FFT_SIZE`] samples, between -1 and 1.
+    history: VecDeque<f32>,
+    /// Since the last spectrum.
+    new_samples: usize,
+    /// Lowest, highest and count of the point being filled.
+    point: (f32, f32, usize),
+    snapshot: ScopeSnapshot,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        // Hann, as hum sits next to strong low frequencies
+        let window = (0..FFT_SIZE)
+            .map(|index| {
+                let phase = 2.0 * std::f32::consts::PI * index as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Scope {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            history: VecDeque::with_capacity(FFT_SIZE),
+            new_samples: 0,
+            point: (0.0, 0.0, 0),
+            snapshot: ScopeSnapshot::default(),
+        }
+    }
+
+    /// Returns whether there is a new spectrum.
+    pub fn push(&mut self, samples: &[i16]) -> bool {
+        let mut analyzed = false;
+        for &sample in samples {
+            let sample = sample as f32 / 32768.0;
+            let (low, high, count) = &mut self.point;
+            if *count == 0 {
+                (*low, *high) = (sample, sample);
+            } else {
+                *low = low.min(sample);
+                *high = high.max(sample);
+            }
+            *count += 1;
+            if *count == WAVEFORM_DECIMATION {
+                if self.snapshot.waveform.len() == WAVEFORM_POINTS {
+                    self.snapshot.waveform.remove(0);
+                }
+                self.snapshot.waveform.push((*low, *high));
+                *count = 0;
+            }
+
+            if self.history.len() == FFT_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(sample);
+            self.new_samples += 1;
+            // half overlapping, so short sounds show up
+            if self.history.len() == FFT_SIZE && self.new_samples >= FFT_SIZE / 2 {
+                self.new_samples = 0;
+                self.analyze();
+                analyzed = true;
+            }
+        }
+        analyzed
+    }
+
+    pub fn snapshot(&self) -> &ScopeSnapshot {
+        &self.snapshot
+    }
+
+    fn analyze(&mut self) {
+        let mut peak = 0.0_f32;
+        let mut energy = 0.0_f32;
+        let mut buffer: Vec<Complex<f32>> = self
+            .history
+            .iter()
+            .zip(self.window.iter())
+            .map(|(&sample, &weight)| {
+                peak = peak.max(sample.abs());
+                energy += sample * sample;
+                Complex::new(sample * weight, 0.0)
+            })
+            .collect();
+        self.snapshot.peak = peak;
+        self.snapshot.rms = (energy / FFT_SIZE as f32).sqrt();
+        self.fft.process(&mut buffer);
+
+        // a full scale sine reads 0dB
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        let magnitudes: Vec<f32> = buffer[..FFT_SIZE / 2]
+            .iter()
+            .map(|bin| bin.norm() * scale)
+            .collect();
+        let spectrum: Vec<f32> = band_edges()
+            .windows(2)
+            .map(|edges| {
+                // the bins centered in the band, or the nearest for narrow
+                // low bands
+                let (low, high) = (bin_of(edges[0]).ceil(), bin_of(edges[1]).ceil());
+                let bins = if low < high {
+                    &magnitudes[low as usize..(high as usize).min(magnitudes.len())]
+                } else {
+                    let nearest = bin_of((edges[0] * edges[1]).sqrt()).round() as usize;
+                    &magnitudes[nearest..=nearest]
+                };
+                let magnitude = bins.iter().copied().fold(0.0, f32::max);
+                (20.0 * magnitude.log10()).max(FLOOR_DB)
+            })
+            .collect();
+        self.snapshot.spectrum = if self.snapshot.spectrum.is_empty() {
+            spectrum
+        } else {
+            self.snapshot
+                .spectrum
+                .iter()
+                .zip(spectrum)
+                .map(|(&old, new)| old * SMOOTHING + new * (1.0 - SMOOTHING))
+                .collect()
+        };
+    }
+}
+
+/// The frequencies between the bands of the spectrum, [`SPECTRUM_BANDS`] + 1
+/// of them.
+pub fn band_edges() -> Vec<f32> {
+    let nyquist = INPUT_SAMPLE_RATE as f32 / 2.0;
+    let ratio = (nyquist / LOWEST_FREQUENCY).powf(1.0 / SPECTRUM_BANDS as f32);
+    (0..=SPECTRUM_BANDS)
+        .map(|index| LOWEST_FREQUENCY * ratio.powi(index as i32))
+        .collect()
+}
+
+fn bin_of(frequency: f32) -> f32 {
+    frequency * FFT_SIZE as f32 / INPUT_SAMPLE_RATE as f32
+}
+
+/// Analyzes what its [`AnalyzerInput`]s get on its own thread, which ends
+/// once they and the analyzer are dropped.
+pub struct Analyzer {
+    input: AnalyzerInput,
+    scopes: Arc<Mutex<[Scope; 2]>>,
+}
+
+/// Where an [`AnalysisTap`] sends the samples.
+#[derive(Debug, Clone)]
+pub struct AnalyzerInput {
+    sender: Sender<(Stage, Vec<i16>)>,
+}
+
+impl Analyzer {
+    /// `on_update` is called after every new spectrum, e.g. to repaint.
+    pub fn start(on_update: impl Fn() + Send + 'static) -> Self {
+        let (sender, chunks) = crossbeam_channel::bounded(QUEUE);
+        let scopes = Arc::new(Mutex::new([Scope::new(), Scope::new()]));
+        let analyzed = scopes.clone();
+        thread::spawn(move || analyze(chunks, analyzed, on_update));
+        Analyzer {
+            input: AnalyzerInput { sender },
+            scopes,
+        }
+    }
+
+    pub fn input(&self) -> AnalyzerInput {
+        self.input.clone()
+    }
+
+    pub fn snapshot(&self, stage: Stage) -> ScopeSnapshot {
+        self.scopes.lock().unwrap()[stage as usize]
+            .snapshot()
+            .clone()
+    }
+}
+
+fn analyze(
+    chunks: Receiver<(Stage, Vec<i16>)>,
+    scopes: Arc<Mutex<[Scope; 2]>>,
+    on_update: impl Fn(),
+) {
+    for (stage, samples) in chunks {
+        let analyzed = scopes.lock().unwrap()[stage as usize].push(&samples);
+        if analyzed {
+            on_update();
+        }
+    }
+}
+
+/// Copies the decoded samples to an [`Analyzer`], if one is attached. Shared
+/// by the loop with the socket of every session.
+#[derive(Clone, Default)]
+pub struct AnalysisTap {
+    input: Arc<Mutex<Option<AnalyzerInput>>>,
+}
+
+impl AnalysisTap {
+    pub fn attach(&self, input: AnalyzerInput) {
+        *self.input.lock().unwrap() = Some(input);
+    }
+
+    pub fn detach(&self) {
+        *self.input.lock().unwrap() = None;
+    }
+
+    /// Called with every decoded chunk. Chunks the analyzer has no room for
+    /// are dropped, the socket never waits for it.
+    pub fn push(&self, stage: Stage, samples: &[i16]) {
+        let input = self.input.lock().unwrap();
+        let input = match input.as_ref() {
+            Some(input) => input,
+            None => return,
+        };
+        // a stopped analyzer has nobody left to show it to
+        input.sender.try_send((stage, samples.to_vec())).ok();
+    }
+}
@@ -1,21 +1,249 @@
-use anyhow::{format_err, Result};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Stream,
 };
-use ringbuf::Consumer;
+use crossbeam_channel::Sender;
+use ringbuf::{Consumer, Producer};
+
+use crate::{error::SessionError, resampler::Resampler, stats::SessionStats};
+
+pub const CABLE_PREFIX: &str = "CABLE Input";
+/// Rate of the samples sent by the phone.
+pub const INPUT_SAMPLE_RATE: u32 = 48000;
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Which output device a stream is opened on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The first device whose name starts with the prefix, or the default
+    /// device if there is none.
+    Prefix(String),
+    Named(String),
+    Default,
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        DeviceSelector::Prefix(CABLE_PREFIX.to_owned())
+    }
+}
+
+/// Sent by a running stream when the backend reports an error, usually
+/// because the device went away.
+#[derive(Debug, Clone)]
+pub struct StreamFailure {
+    pub stream_id: u64,
+    pub error: String,
+}
+
+/// How the event loop reacts when the output stream dies mid-session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioRecoveryPolicy {
+    /// Attempts to reopen the same device.
+    pub attempts: u32,
+    pub retry_delay: Duration,
+    /// Plays on the default device if the lost one doesn't come back.
+    pub fall_back_to_default: bool,
+}
+
+impl Default for AudioRecoveryPolicy {
+    fn default() -> Self {
+        AudioRecoveryPolicy {
+            attempts: 5,
+            retry_delay: Duration::from_secs(1),
+            // the default device is usually the speakers, which would feed
+            // back into the phone
+            fall_back_to_default: false,
+        }
+    }
+}
+
+/// Where the event loop plays the received samples.
+pub trait AudioSink: Send {
+    /// Plays `consumer` on the device picked by `selector` until the
+    /// returned state is stopped or dropped. Errors while playing are sent to
+    /// `failures`.
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError>;
+}
+
+/// Plays on the output devices of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpalSink;
+
+impl AudioSink for CpalSink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        start_output_stream(consumer, stats, selector, failures)
+    }
+}
+
+/// The virtual microphone where the system has one, the output devices
+/// otherwise. On Linux it is made on PipeWire when built with the feature,
+/// through `pactl` otherwise.
+pub fn default_sink() -> Box<dyn AudioSink> {
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    match crate::pipewire_sink::PipeWireSink::connect() {
+        Ok(sink) => return Box::new(sink),
+        Err(err) => tracing::info!("No PipeWire, trying PulseAudio: {}", err),
+    }
+    #[cfg(target_os = "linux")]
+    match crate::pulse::PulseSink::create() {
+        Ok(sink) => return Box::new(sink),
+        Err(err) => tracing::warn!("No virtual microphone, playing on output devices: {}", err),
+    }
+    Box::new(CpalSink)
+}
+
+/// A running output, as far as the event loop is concerned.
+pub trait Playback {
+    fn pause(&self) -> Result<(), SessionError>;
+}
+
+impl Playback for Stream {
+    fn pause(&self) -> Result<(), SessionError> {
+        StreamTrait::pause(self).map_err(|err| SessionError::audio("pausing stream", err))
+    }
+}
+
+/// Hands a copy of the decoded samples to the monitor output, converted to
+/// its rate and with its own volume.
+#[derive(Clone, Default)]
+pub struct MonitorTap {
+    output: Arc<Mutex<Option<MonitorOutput>>>,
+    /// An `f32`, as bits.
+    volume: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+}
+
+struct MonitorOutput {
+    producer: Producer<i16>,
+    resampler: Resampler,
+    buffer: Vec<i16>,
+}
 
-pub fn start_output_stream(consumer: Consumer<i16>) -> Result<AudioState> {
+impl MonitorTap {
+    pub fn new() -> Self {
+        let tap = MonitorTap::default();
+        tap.set_volume(1.0);
+        tap
+    }
+
+    /// Sends the samples pushed from now on to `producer`, which is played at
+    /// `sample_rate`.
+    pub fn attach(&self, producer: Producer<i16>, sample_rate: u32) {
+        *self.output.lock().unwrap() = Some(MonitorOutput {
+            producer,
+            resampler: Resampler::new(INPUT_SAMPLE_RATE, sample_rate),
+            buffer: Vec::new(),
+        });
+    }
+
+    pub fn detach(&self) {
+        *self.output.lock().unwrap() = None;
+    }
+
+    /// 1.0 plays the signal unchanged.
+    pub fn set_volume(&self, volume: f32) {
+        self.volume
+            .store(volume.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Called with every decoded chunk. Samples the monitor has no room for
+    /// are dropped, the main output never waits for it.
+    pub fn push(&self, samples: &[i16]) {
+        let mut output = self.output.lock().unwrap();
+        let output = match output.as_mut() {
+            Some(output) => output,
+            None => return,
+        };
+        let gain = match self.muted.load(Ordering::Relaxed) {
+            true => 0.0,
+            false => f32::from_bits(self.volume.load(Ordering::Relaxed)),
+        };
+        output.buffer.clear();
+        output.resampler.process(samples, &mut output.buffer);
+        for &sample in output.buffer.iter() {
+            let sample = (sample as f32 * gain)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            if output.producer.push(sample).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Names of the output devices, to pick one for the monitor.
+pub fn output_device_names() -> Vec<String> {
+    cpal::default_host()
+        .output_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Identifies a new output in [`StreamFailure`]s.
+pub fn next_stream_id() -> u64 {
+    NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn find_device(selector: &DeviceSelector) -> Result<cpal::Device, SessionError> {
     let host = cpal::default_host();
-    let device = host
-        .output_devices()?
-        .find(|x| x.name().unwrap().starts_with("CABLE Input"))
-        .unwrap_or(host.default_output_device().ok_or(core::fmt::Error)?);
-    let config = device.default_output_config()?;
+    let mut devices = host
+        .output_devices()
+        .map_err(|err| SessionError::audio("listing output devices", err))?;
+    let name = |device: &cpal::Device| device.name().unwrap_or_default();
+    let found = match selector {
+        DeviceSelector::Prefix(prefix) => devices.find(|x| name(x).starts_with(prefix.as_str())),
+        DeviceSelector::Named(device_name) => devices.find(|x| name(x) == *device_name),
+        DeviceSelector::Default => None,
+    };
+    match (found, selector) {
+        (Some(device), _) => Ok(device),
+        (None, DeviceSelector::Named(_)) => Err(SessionError::AudioDeviceNotFound),
+        (None, _) => host
+            .default_output_device()
+            .ok_or(SessionError::AudioDeviceNotFound),
+    }
+}
+
+pub fn start_output_stream(
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    selector: &DeviceSelector,
+    failures: Sender<StreamFailure>,
+) -> Result<AudioState, SessionError> {
+    let device = find_device(selector)?;
+    let config = device
+        .default_output_config()
+        .map_err(|err| SessionError::audio("reading device config", err))?;
     match config.sample_format() {
-        cpal::SampleFormat::F32 => (run::<f32>(device, config.into(), consumer)),
-        cpal::SampleFormat::I16 => (run::<i16>(device, config.into(), consumer)),
-        cpal::SampleFormat::U16 => (run::<u16>(device, config.into(), consumer)),
+        cpal::SampleFormat::F32 => run::<f32>(device, config.into(), consumer, stats, failures),
+        cpal::SampleFormat::I16 => run::<i16>(device, config.into(), consumer, stats, failures),
+        cpal::SampleFormat::U16 => run::<u16>(device, config.into(), consumer, stats, failures),
     }
 }
 
@@ -23,28 +251,58 @@ pub fn run<T>(
     device: cpal::Device,
     config: cpal::StreamConfig,
     mut consumer: Consumer<i16>,
-) -> Result<AudioState>
+    stats: SessionStats,
+    failures: Sender<StreamFailure>,
+) -> Result<AudioState, SessionError>
 where
     T: cpal::Sample,
 {
     let channels = config.channels as usize;
-    let mut next_value = move || consumer.pop().unwrap_or(0);
+    stats.set_output_sample_rate(config.sample_rate.0);
+    stats.set_buffer_capacity(consumer.capacity());
 
-    let err_fn = move |err| {
-        eprintln!("an error occurred on stream: {}", err);
+    let id = next_stream_id();
+    let device_name = device.name().unwrap_or_default();
+    let failed_device = device_name.clone();
+    let err_fn = move |err: cpal::StreamError| {
+        tracing::error!(device = %failed_device, "Output stream failed: {}", err);
+        failures
+            .send(StreamFailure {
+                stream_id: id,
+                error: err.to_string(),
+            })
+            .ok();
     };
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &mut next_value)
-        },
-        err_fn,
-    )?;
-    stream.play()?;
-    Ok(AudioState { stream })
-}
-
-fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> i16)
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                let timestamp = info.timestamp();
+                if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                    stats.set_device_latency(latency);
+                }
+                let mut underrun = false;
+                write_data(data, channels, &mut || {
+                    consumer.pop().unwrap_or_else(|| {
+                        underrun = true;
+                        0
+                    })
+                });
+                if underrun {
+                    stats.record_underrun();
+                }
+                stats.set_buffer_fill(consumer.len());
+            },
+            err_fn,
+        )
+        .map_err(|err| SessionError::audio("opening output stream", err))?;
+    stream
+        .play()
+        .map_err(|err| SessionError::audio("starting output stream", err))?;
+    Ok(AudioState::new(id, device_name, stream))
+}
+
+pub(crate) fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> i16)
 where
     T: cpal::Sample,
 {
@@ -56,14 +314,59 @@ where
     }
 }
 
+/// Fills `output` with 16-bit little-endian frames of `channels` from
+/// `consumer`, with silence once it runs dry, for outputs that take bytes.
+/// Returns the number of frames written.
+pub fn write_pcm(
+    output: &mut [u8],
+    channels: usize,
+    consumer: &mut Consumer<i16>,
+    stats: &SessionStats,
+) -> usize {
+    let frame_size = 2 * channels.max(1);
+    let mut underrun = false;
+    let mut frames = 0;
+    for frame in output.chunks_exact_mut(frame_size) {
+        let sample = consumer.pop().unwrap_or_else(|| {
+            underrun = true;
+            0
+        });
+        for bytes in frame.chunks_exact_mut(2) {
+            bytes.copy_from_slice(&sample.to_le_bytes());
+        }
+        frames += 1;
+    }
+    if underrun {
+        stats.record_underrun();
+    }
+    stats.set_buffer_fill(consumer.len());
+    frames
+}
+
 pub struct AudioState {
-    stream: Stream,
+    playback: Box<dyn Playback>,
+    id: u64,
+    device_name: String,
 }
 
 impl AudioState {
-    pub fn stop(&self) -> Result<()> {
-        self.stream
-            .pause()
-            .map_err(|err| format_err!("Error pausing stream: {}", err))
+    pub fn new(id: u64, device_name: String, playback: impl Playback + 'static) -> Self {
+        AudioState {
+            playback: Box::new(playback),
+            id,
+            device_name,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    pub fn stop(&self) -> Result<(), SessionError> {
+        self.playback.pause()
     }
 }
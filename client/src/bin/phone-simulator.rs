@@ -0,0 +1,90 @@
+//! Behaves like the phone app so the client can be run without a phone.
+
+use std::{path::PathBuf, process, time::Duration};
+
+use clap::Parser;
+use fast_mic::{
+    simulator::{Faults, Simulator, SimulatorConfig, Source, Stall},
+    socket::DEFAULT_PORT,
+};
+
+#[derive(Parser)]
+#[command(about = "Streams audio like the Fast Mic app does")]
+struct Args {
+    /// Address to listen on.
+    #[arg(long, default_value_t = format!("0.0.0.0:{}", DEFAULT_PORT))]
+    address: String,
+    /// Streams a 48kHz 16-bit WAV file in a loop instead of a sine sweep.
+    #[arg(long, conflicts_with = "silence")]
+    wav: Option<PathBuf>,
+    /// Streams silence instead of a sine sweep.
+    #[arg(long)]
+    silence: bool,
+    /// Ignores the client's hello, like app versions without latency
+    /// measurement.
+    #[arg(long)]
+    raw_only: bool,
+    /// Stops sending every this many seconds...
+    #[arg(long, requires = "stall_ms", value_parser = seconds)]
+    stall_every: Option<Duration>,
+    /// ...for this many milliseconds.
+    #[arg(long, requires = "stall_every")]
+    stall_ms: Option<u64>,
+    /// Closes the connection after streaming this many seconds.
+    #[arg(long, value_parser = seconds)]
+    disconnect_after: Option<Duration>,
+    /// Damages every n-th chunk.
+    #[arg(long)]
+    corrupt_every: Option<u32>,
+}
+
+/// A positive number of seconds, fractions allowed.
+fn seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("{} is not a number", value))?;
+    if seconds <= 0.0 {
+        return Err("must be more than 0".to_owned());
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+fn main() {
+    let args = Args::parse();
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+    let source = match (args.wav, args.silence) {
+        (Some(path), _) => Source::wav(&path).unwrap_or_else(|err| {
+            eprintln!("Cannot load {}: {}", path.display(), err);
+            process::exit(1);
+        }),
+        (None, true) => Source::Silence,
+        (None, false) => Source::default(),
+    };
+    let stall = match (args.stall_every, args.stall_ms) {
+        (Some(every), Some(pause)) => Some(Stall {
+            every,
+            pause: Duration::from_millis(pause),
+        }),
+        _ => None,
+    };
+    let config = SimulatorConfig {
+        source,
+        framing: !args.raw_only,
+        faults: Faults {
+            stall,
+            disconnect_after: args.disconnect_after,
+            corrupt_every: args.corrupt_every,
+        },
+    };
+
+    let simulator = Simulator::bind(args.address.as_str(), config).unwrap_or_else(|err| {
+        eprintln!("Cannot listen on {}: {}", args.address, err);
+        process::exit(1);
+    });
+    match simulator.local_addr() {
+        Ok(address) => println!("Waiting connection on {}", address),
+        Err(err) => eprintln!("Error reading address: {}", err),
+    }
+    if let Err(err) = simulator.run() {
+        eprintln!("Error accepting connection: {}", err);
+        process::exit(1);
+    }
+}
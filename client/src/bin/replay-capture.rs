@@ -0,0 +1,74 @@
+//! Decodes a capture of a session the way the client did, to listen to what
+//! it played.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    process,
+};
+
+use clap::Parser;
+use fast_mic::{
+    capture::ReplayTiming, error::ErrorKind, simulator::SAMPLE_RATE, socket::SocketState,
+    stats::SessionStats, wav::write_wav,
+};
+use ringbuf::RingBuffer;
+
+#[derive(Parser)]
+#[command(about = "Decodes a Fast Mic capture into a WAV file")]
+struct Args {
+    /// Capture written by the client.
+    capture: PathBuf,
+    /// WAV file to write.
+    output: PathBuf,
+    /// Feeds the bytes at the pace they were received instead of all at
+    /// once.
+    #[arg(long)]
+    original_timing: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    let timing = if args.original_timing {
+        ReplayTiming::Original
+    } else {
+        ReplayTiming::Immediate
+    };
+    let (producer, mut consumer) = RingBuffer::<i16>::new(SAMPLE_RATE as usize).split();
+    let stats = SessionStats::new();
+    let mut socket = SocketState::replay(&args.capture, timing, producer, stats.clone())
+        .unwrap_or_else(|err| {
+            eprintln!("Cannot open {}: {}", args.capture.display(), err);
+            process::exit(1);
+        });
+
+    let mut samples = Vec::new();
+    let result = loop {
+        let result = socket.receive();
+        samples.extend(std::iter::from_fn(|| consumer.pop()));
+        if let Err(err) = result {
+            break err;
+        }
+    };
+    // the end of the capture reads like the phone hanging up
+    if result.kind() != ErrorKind::ConnectionLost {
+        eprintln!("Decoding stopped early: {}", result);
+    }
+
+    let written = File::create(&args.output).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_wav(&mut writer, SAMPLE_RATE, &samples)?;
+        writer.flush()
+    });
+    if let Err(err) = written {
+        eprintln!("Cannot write {}: {}", args.output.display(), err);
+        process::exit(1);
+    }
+    let snapshot = stats.snapshot();
+    println!(
+        "Decoded {} samples from {} bytes",
+        samples.len(),
+        snapshot.bytes_received
+    );
+}
@@ -0,0 +1,184 @@
+//! Recording of the bytes received from the phone, to replay sessions that
+//! went wrong.
+//!
+//! A capture starts with `FMCAP`, a version byte and a flags byte (bit 0: the
+//! stream is framed, see [`crate::protocol`]). Then every read from the
+//! socket follows as a record: arrival time (`u64` µs since the capture
+//! started), length (`u32`), the bytes. All integers are little-endian.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const MAGIC: [u8; 5] = *b"FMCAP";
+pub const VERSION: u8 = 1;
+
+const FLAG_FRAMED: u8 = 1;
+const MAX_RECORD: usize = 1 << 20;
+
+/// A new file name in `directory`, based on the current time.
+pub fn capture_path(directory: &Path) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    directory.join(format!("fast-mic-{}.fmcap", millis))
+}
+
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path, framed: bool) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&MAGIC)?;
+        file.write_all(&[VERSION, if framed { FLAG_FRAMED } else { 0 }])?;
+        Ok(CaptureWriter {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    /// Records `data` as arriving now.
+    pub fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        self.record_at(self.started.elapsed(), data)
+    }
+
+    /// Records `data` as arriving `at` after the capture started, e.g. to
+    /// build captures for tests.
+    pub fn record_at(&mut self, at: Duration, data: &[u8]) -> io::Result<()> {
+        self.file
+            .write_all(&(at.as_micros() as u64).to_le_bytes())?;
+        self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.file.write_all(data)?;
+        // what was received before a crash is what matters most
+        self.file.flush()
+    }
+}
+
+/// Passes reads through and records what they return.
+pub struct CapturingReader<R> {
+    inner: R,
+    writer: Option<CaptureWriter>,
+}
+
+impl<R: Read> CapturingReader<R> {
+    pub fn new(inner: R, writer: CaptureWriter) -> Self {
+        CapturingReader {
+            inner,
+            writer: Some(writer),
+        }
+    }
+}
+
+impl<R: Read> Read for CapturingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writer.record(&buf[..read]) {
+                // the session shouldn't end because the disk is full
+                tracing::warn!("Error writing capture, stopping it: {}", err);
+                self.writer = None;
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayTiming {
+    /// Every record becomes available when it arrived originally.
+    Original,
+    /// Everything is available right away.
+    Immediate,
+}
+
+/// Reads a capture back as the byte stream it recorded.
+pub struct ReplayReader {
+    file: BufReader<File>,
+    framed: bool,
+    timing: ReplayTiming,
+    started: Option<Instant>,
+    record: Vec<u8>,
+    offset: usize,
+}
+
+impl ReplayReader {
+    pub fn open(path: &Path, timing: ReplayTiming) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; MAGIC.len() + 2];
+        file.read_exact(&mut header)?;
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(invalid("Not a capture file".to_owned()));
+        }
+        let version = header[MAGIC.len()];
+        if version != VERSION {
+            return Err(invalid(format!("Unknown capture version {}", version)));
+        }
+        Ok(ReplayReader {
+            file,
+            framed: header[MAGIC.len() + 1] & FLAG_FRAMED != 0,
+            timing,
+            started: None,
+            record: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    /// Whether the capture holds frames rather than raw samples.
+    pub fn framed(&self) -> bool {
+        self.framed
+    }
+
+    /// Loads the next record, returns false at the end of the capture.
+    fn next_record(&mut self) -> io::Result<bool> {
+        let mut at = [0u8; 8];
+        match self.file.read_exact(&mut at) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        let mut length = [0u8; 4];
+        self.file.read_exact(&mut length)?;
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_RECORD {
+            return Err(invalid(format!("Invalid record length {}", length)));
+        }
+        self.record.resize(length, 0);
+        self.file.read_exact(&mut self.record)?;
+        self.offset = 0;
+
+        if self.timing == ReplayTiming::Original {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = started + Duration::from_micros(u64::from_le_bytes(at));
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.record.len() {
+            if !self.next_record()? {
+                return Ok(0);
+            }
+        }
+        let read = buf.len().min(self.record.len() - self.offset);
+        buf[..read].copy_from_slice(&self.record[self.offset..self.offset + read]);
+        self.offset += read;
+        Ok(read)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
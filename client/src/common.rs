@@ -1,39 +1,12 @@
-use std::sync::mpsc::{self, Iter, Receiver, Sender};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Result;
+use crossbeam_channel::{Iter, Receiver, RecvTimeoutError, Sender, TryRecvError};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum GuiStatus {
-    Ready,
-    Connecting,
-    Reconnecting,
-    Connected,
-    Failed,
-    Disconnecting,
-}
-
-pub enum LoopStatus {
-    Ready,
-    Connected,
-}
-
-impl GuiStatus {
-    pub fn can_connect(&self) -> bool {
-        match self {
-            GuiStatus::Ready | GuiStatus::Failed => true,
-            GuiStatus::Connecting
-            | GuiStatus::Reconnecting
-            | GuiStatus::Connected
-            | GuiStatus::Disconnecting => false,
-        }
-    }
-}
-
-impl Default for GuiStatus {
-    fn default() -> Self {
-        Self::Ready
-    }
-}
+use crate::{
+    analysis::AnalyzerInput, audio::DeviceSelector, event_loop::SessionConfig, mute::MicState,
+    session::SessionState,
+};
 
 pub struct Communicator<S, T>
 where
@@ -50,7 +23,7 @@ impl<S: Send + Sync + 'static, T: Send + Sync> Communicator<S, T> {
         Ok(())
     }
 
-    pub fn try_receive(&mut self) -> Result<T, mpsc::TryRecvError> {
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
         self.receiver.try_recv()
     }
 
@@ -58,14 +31,27 @@ impl<S: Send + Sync + 'static, T: Send + Sync> Communicator<S, T> {
         self.receiver.recv().map_err(anyhow::Error::msg)
     }
 
-    pub fn iter(&mut self) -> Iter<T> {
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    pub fn iter(&mut self) -> Iter<'_, T> {
         self.receiver.iter()
     }
 
-    pub fn create_pair() -> (Communicator<S, T>, Communicator<T, S>)
-    {
-        let (sender_1, receiver_2) = mpsc::channel::<S>();
-        let (sender_2, receiver_1) = mpsc::channel::<T>();
+    /// The sending end, for others to send on behalf of the owner.
+    pub fn sender(&self) -> &Sender<S> {
+        &self.sender
+    }
+
+    /// The receiving end, for waiting on it together with other channels.
+    pub fn receiver(&self) -> &Receiver<T> {
+        &self.receiver
+    }
+
+    pub fn create_pair() -> (Communicator<S, T>, Communicator<T, S>) {
+        let (sender_1, receiver_2) = crossbeam_channel::unbounded::<S>();
+        let (sender_2, receiver_1) = crossbeam_channel::unbounded::<T>();
         (
             Communicator {
                 sender: sender_1,
@@ -85,17 +71,59 @@ impl<S: Send + Sync + 'static, T: Send + Sync> Communicator<S, T> {
 
 #[derive(Debug, Clone)]
 pub enum LoopMessage {
-    Ready,
-    SocketConnected,
-    SocketCannotConnect,
-    SocketClosed,
-    SocketReconnecting,
-    AudioStreamError(String),
+    /// Sent on every transition of the session.
+    State(SessionState),
+    /// The phone at `address`, as the user gave it, answered. Sent on every
+    /// successful attempt, but not when the phone connected to us.
+    Connected {
+        address: String,
+        peer: Option<SocketAddr>,
+    },
+    /// The output stream died, the connection stays up while it's reopened.
+    AudioDeviceLost(String),
+    AudioDeviceRestored(String),
+    /// The monitor output couldn't start or died, it is off now.
+    MonitorFailed(String),
+    /// Sent whenever muting or push-to-talk changes, whoever changed it.
+    Mic(MicState),
+    /// Passes [`UserAction::SwitchProfile`] on to the GUI, which owns the
+    /// profiles.
+    SwitchProfile(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum UserAction {
     Connect(String),
+    /// Waits for the phone to connect to the given address instead.
+    Listen(String),
     UserDisconnect,
+    /// Takes effect on the next connection.
+    SetLatencyMeasurement(bool),
+    /// Takes effect on the next connection.
+    Configure(SessionConfig),
+    /// Records the received bytes of the next sessions into the directory,
+    /// see [`crate::capture`].
+    SetCapture(Option<PathBuf>),
+    /// Plays a copy of the received audio on the device, or stops it.
+    SetMonitor(Option<DeviceSelector>),
+    /// Of the monitor only, 1.0 leaves the signal unchanged.
+    SetMonitorVolume(f32),
+    SetMonitorMuted(bool),
+    /// Silences the phone, the connection stays up.
+    SetMuted(bool),
+    ToggleMute,
+    /// Keeps the phone silent except while [`UserAction::Talk`] is on.
+    SetPushToTalk(bool),
+    /// The push-to-talk key was pressed or released.
+    Talk(bool),
+    /// How long muting and unmuting take.
+    SetFade(Duration),
+    /// Asks the GUI to switch to the named profile of the settings.
+    SwitchProfile(String),
+    /// Sends every following [`LoopMessage`] to the sender as well, starting
+    /// with the current state, until it disconnects.
+    Subscribe(Sender<LoopMessage>),
+    /// Starts or stops copying the samples to an analyzer.
+    SetAnalysis(Option<AnalyzerInput>),
     Exit,
 }
@@ -0,0 +1,430 @@
+//! Lets other programs drive the client, e.g. Stream Deck macros or scripts.
+//!
+//! A small HTTP server, meant for localhost:
+//! - `POST /actions` takes a [`Command`] as JSON and answers 204
+//! - `GET /stats` answers the current [`StatsSnapshot`]
+//! - `GET /events` streams [`Event`]s as JSON lines until the client hangs
+//!   up, starting with the current state and then stats every second
+//!
+//! Every request needs the header `Authorization: Bearer <token>`.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{select, Sender};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{LoopMessage, UserAction},
+    mute::MicState,
+    session::SessionState,
+    stats::{SessionStats, StatsSnapshot},
+};
+
+pub const DEFAULT_CONTROL_ADDRESS: &str = "127.0.0.1:50552";
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_HEADER_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: usize = 64 * 1024;
+/// How long a connection may keep a read or a write waiting.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Served at once, more are turned away.
+const MAX_CONNECTIONS: usize = 32;
+
+/// What a request to `/actions` can ask for.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Command {
+    Connect { address: String },
+    Listen { address: String },
+    Disconnect,
+    SetMuted { muted: bool },
+    ToggleMute,
+    SetPushToTalk { enabled: bool },
+    Talk { talking: bool },
+    SetMonitorVolume { volume: f32 },
+    SetMonitorMuted { muted: bool },
+    SwitchProfile { name: String },
+}
+
+impl From<Command> for UserAction {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Connect { address } => UserAction::Connect(address),
+            Command::Listen { address } => UserAction::Listen(address),
+            Command::Disconnect => UserAction::UserDisconnect,
+            Command::SetMuted { muted } => UserAction::SetMuted(muted),
+            Command::ToggleMute => UserAction::ToggleMute,
+            Command::SetPushToTalk { enabled } => UserAction::SetPushToTalk(enabled),
+            Command::Talk { talking } => UserAction::Talk(talking),
+            Command::SetMonitorVolume { volume } => UserAction::SetMonitorVolume(volume),
+            Command::SetMonitorMuted { muted } => UserAction::SetMonitorMuted(muted),
+            Command::SwitchProfile { name } => UserAction::SwitchProfile(name),
+        }
+    }
+}
+
+/// A line of `/events`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    State {
+        state: &'static str,
+        /// Why the session failed, in the failed state.
+        error: Option<String>,
+    },
+    Connected {
+        address: String,
+        peer: Option<String>,
+    },
+    AudioDeviceLost {
+        device: String,
+    },
+    AudioDeviceRestored {
+        device: String,
+    },
+    MonitorFailed {
+        error: String,
+    },
+    Mic(MicState),
+    SwitchProfile {
+        name: String,
+    },
+    Stats(StatsSnapshot),
+}
+
+impl From<&LoopMessage> for Event {
+    fn from(message: &LoopMessage) -> Self {
+        match message {
+            LoopMessage::State(state) => Event::State {
+                state: state.name(),
+                error: match state {
+                    SessionState::Failed(err) => Some(err.to_string()),
+                    _ => None,
+                },
+            },
+            LoopMessage::Connected { address, peer } => Event::Connected {
+                address: address.clone(),
+                peer: peer.map(|peer| peer.to_string()),
+            },
+            LoopMessage::AudioDeviceLost(device) => Event::AudioDeviceLost {
+                device: device.clone(),
+            },
+            LoopMessage::AudioDeviceRestored(device) => Event::AudioDeviceRestored {
+                device: device.clone(),
+            },
+            LoopMessage::MonitorFailed(error) => Event::MonitorFailed {
+                error: error.clone(),
+            },
+            LoopMessage::Mic(state) => Event::Mic(*state),
+            LoopMessage::SwitchProfile(name) => Event::SwitchProfile { name: name.clone() },
+        }
+    }
+}
+
+/// A random token for [`ControlServer::start`], 32 hex digits from the
+/// random number generator of the OS.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("The OS has no random number generator");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Serves the control API on its own threads until dropped.
+pub struct ControlServer {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+struct Handler {
+    token: Arc<String>,
+    actions: Sender<UserAction>,
+    stats: SessionStats,
+    stopped: Arc<AtomicBool>,
+    connections: ConnectionLimit,
+}
+
+/// Counts the connections a server is serving, also used by
+/// [`crate::metrics`].
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionLimit {
+    open: Arc<AtomicUsize>,
+}
+
+/// A connection counted by [`ConnectionLimit`] until dropped.
+pub(crate) struct Connection {
+    open: Arc<AtomicUsize>,
+}
+
+/// Also parsed by [`crate::metrics`].
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    /// Only read by the metrics server.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub accept: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl ControlServer {
+    /// Actions go to `actions`, the sender of the loop's communicator.
+    pub fn start(
+        address: impl ToSocketAddrs,
+        token: String,
+        actions: Sender<UserAction>,
+        stats: SessionStats,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handler = Handler {
+            token: Arc::new(token),
+            actions,
+            stats,
+            stopped: stopped.clone(),
+            connections: ConnectionLimit::default(),
+        };
+        thread::spawn(move || handler.accept(listener));
+        Ok(ControlServer { address, stopped })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // wakes the accepting thread so it sees the flag
+        TcpStream::connect(self.address).ok();
+    }
+}
+
+impl Handler {
+    fn accept(self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            if self.stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    let connection = match self.connections.admit(&stream) {
+                        Some(connection) => connection,
+                        None => continue,
+                    };
+                    let handler = self.clone();
+                    thread::spawn(move || {
+                        let _connection = connection;
+                        if let Err(err) = handler.serve(stream) {
+                            tracing::debug!("Control connection closed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => tracing::warn!("Control connection failed: {}", err),
+            }
+        }
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        let request = match read_request(&mut BufReader::new(stream.try_clone()?)) {
+            Ok(request) => request,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                return respond(&mut stream, "400 Bad Request", &err.to_string());
+            }
+            Err(err) => return Err(err),
+        };
+        if !self.authorized(&request) {
+            return respond(&mut stream, "401 Unauthorized", "Missing or wrong token");
+        }
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/actions") => match serde_json::from_slice::<Command>(&request.body) {
+                Ok(command) => {
+                    if self.actions.send(command.into()).is_err() {
+                        return respond(&mut stream, "503 Service Unavailable", "Exiting");
+                    }
+                    respond(&mut stream, "204 No Content", "")
+                }
+                Err(err) => respond(&mut stream, "400 Bad Request", &err.to_string()),
+            },
+            ("GET", "/stats") => {
+                let snapshot = serde_json::to_string(&self.stats.snapshot())?;
+                respond_json(&mut stream, &snapshot)
+            }
+            ("GET", "/events") => self.stream_events(stream),
+            (_, "/actions" | "/stats" | "/events") => {
+                respond(&mut stream, "405 Method Not Allowed", "")
+            }
+            _ => respond(&mut stream, "404 Not Found", ""),
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let given = request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        // compares every byte so the time taken doesn't reveal the token
+        given.len() == self.token.len()
+            && given
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+
+    fn stream_events(&self, mut stream: TcpStream) -> io::Result<()> {
+        let (subscriber, messages) = crossbeam_channel::unbounded();
+        if self
+            .actions
+            .send(UserAction::Subscribe(subscriber))
+            .is_err()
+        {
+            return respond(&mut stream, "503 Service Unavailable", "Exiting");
+        }
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: application/x-ndjson\r\n\
+              Cache-Control: no-cache\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        let ticks = crossbeam_channel::tick(STATS_INTERVAL);
+        while !self.stopped.load(Ordering::Relaxed) {
+            let event = select! {
+                recv(messages) -> message => match message {
+                    Ok(message) => Event::from(&message),
+                    // the loop exited
+                    Err(_) => break,
+                },
+                recv(ticks) -> _ => Event::Stats(self.stats.snapshot()),
+            };
+            let mut line = serde_json::to_vec(&event)?;
+            line.push(b'\n');
+            stream.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => return Err(invalid("Invalid request line")),
+    };
+    let mut authorization = None;
+    let mut accept = None;
+    let mut content_length = 0;
+    for headers in 0.. {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err(invalid("Too many headers"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("Invalid header"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("accept") {
+            accept = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| invalid("Invalid Content-Length"))?;
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(invalid("Body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method,
+        path,
+        authorization,
+        accept,
+        body,
+    })
+}
+
+impl ConnectionLimit {
+    /// Counts `stream` and sets its timeouts, `None` if the server is too
+    /// busy for it and it should be closed.
+    pub(crate) fn admit(&self, stream: &TcpStream) -> Option<Connection> {
+        if let Err(err) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            tracing::warn!("Cannot set connection timeouts: {}", err);
+            return None;
+        }
+        if self.open.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            self.open.fetch_sub(1, Ordering::Relaxed);
+            tracing::debug!("Too many connections, closing one");
+            return None;
+        }
+        Some(Connection {
+            open: self.open.clone(),
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_HEADER_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Incomplete or too long line",
+        ));
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+pub(crate) fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write_response(stream, status, "text/plain; charset=utf-8", body)
+}
+
+fn respond_json(stream: &mut TcpStream, body: &str) -> io::Result<()> {
+    write_response(stream, "200 OK", "application/json", body)
+}
+
+pub(crate) fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,170 @@
+use std::{error::Error, fmt, io, net::SocketAddr};
+
+use crate::socket::AddressProblem;
+
+/// Everything that can end or prevent a streaming session.
+#[derive(Debug)]
+pub enum SessionError {
+    Address(AddressProblem),
+    Resolve {
+        host: String,
+        source: Option<io::Error>,
+    },
+    Refused {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    Timeout {
+        address: SocketAddr,
+    },
+    Unreachable {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    Bind {
+        address: String,
+        source: io::Error,
+    },
+    /// The device sent something that doesn't follow the protocol.
+    Protocol(io::Error),
+    ConnectionLost(io::Error),
+    /// Every reconnection attempt failed, holds the error of the last one.
+    ReconnectFailed {
+        attempts: u32,
+        last: Box<SessionError>,
+    },
+    AudioDeviceNotFound,
+    AudioDevice {
+        context: &'static str,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The output stream died and couldn't be reopened.
+    AudioDeviceLost {
+        device: String,
+        reason: String,
+    },
+    Internal {
+        context: &'static str,
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Address,
+    Dns,
+    Refused,
+    Timeout,
+    Unreachable,
+    Bind,
+    Protocol,
+    ConnectionLost,
+    AudioDeviceNotFound,
+    AudioDevice,
+    AudioDeviceLost,
+    Internal,
+}
+
+impl SessionError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SessionError::Address(_) => ErrorKind::Address,
+            SessionError::Resolve { .. } => ErrorKind::Dns,
+            SessionError::Refused { .. } => ErrorKind::Refused,
+            SessionError::Timeout { .. } => ErrorKind::Timeout,
+            SessionError::Unreachable { .. } => ErrorKind::Unreachable,
+            SessionError::Bind { .. } => ErrorKind::Bind,
+            SessionError::Protocol(_) => ErrorKind::Protocol,
+            SessionError::ConnectionLost(_) => ErrorKind::ConnectionLost,
+            SessionError::ReconnectFailed { last, .. } => last.kind(),
+            SessionError::AudioDeviceNotFound => ErrorKind::AudioDeviceNotFound,
+            SessionError::AudioDevice { .. } => ErrorKind::AudioDevice,
+            SessionError::AudioDeviceLost { .. } => ErrorKind::AudioDeviceLost,
+            SessionError::Internal { .. } => ErrorKind::Internal,
+        }
+    }
+
+    /// Classifies an error from connecting to `address`.
+    pub fn connect(address: SocketAddr, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::ConnectionRefused => SessionError::Refused { address, source },
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                SessionError::Timeout { address }
+            }
+            _ => SessionError::Unreachable { address, source },
+        }
+    }
+
+    /// Classifies an error from reading or writing an open connection.
+    pub fn stream(source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::InvalidData => SessionError::Protocol(source),
+            _ => SessionError::ConnectionLost(source),
+        }
+    }
+
+    pub fn audio(context: &'static str, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        SessionError::AudioDevice {
+            context,
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Address(problem) => write!(f, "Invalid address: {}", problem),
+            SessionError::Resolve { host, .. } => write!(f, "Cannot resolve \"{}\"", host),
+            SessionError::Refused { address, .. } => {
+                write!(f, "Connection to {} refused", address)
+            }
+            SessionError::Timeout { address } => {
+                write!(f, "Connection to {} timed out", address)
+            }
+            SessionError::Unreachable { address, .. } => write!(f, "Cannot reach {}", address),
+            SessionError::Bind { address, .. } => write!(f, "Cannot listen on {}", address),
+            SessionError::Protocol(_) => write!(f, "Device sent invalid data"),
+            SessionError::ConnectionLost(_) => write!(f, "Connection lost"),
+            SessionError::ReconnectFailed { attempts, last } => write!(
+                f,
+                "Lost connection, {} reconnection attempts failed: {}",
+                attempts, last
+            ),
+            SessionError::AudioDeviceNotFound => write!(f, "No audio output device found"),
+            SessionError::AudioDevice { context, .. } => write!(f, "Audio error {}", context),
+            SessionError::AudioDeviceLost { device, reason } => {
+                write!(f, "Lost audio device \"{}\": {}", device, reason)
+            }
+            SessionError::Internal { context, .. } => write!(f, "Internal error {}", context),
+        }
+    }
+}
+
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::Address(problem) => Some(problem),
+            SessionError::Resolve { source, .. } => source
+                .as_ref()
+                .map(|source| source as &(dyn Error + 'static)),
+            SessionError::Refused { source, .. }
+            | SessionError::Unreachable { source, .. }
+            | SessionError::Bind { source, .. }
+            | SessionError::Protocol(source)
+            | SessionError::ConnectionLost(source)
+            | SessionError::Internal { source, .. } => Some(source),
+            SessionError::ReconnectFailed { last, .. } => Some(last.as_ref()),
+            SessionError::AudioDevice { source, .. } => Some(source.as_ref()),
+            SessionError::Timeout { .. }
+            | SessionError::AudioDeviceNotFound
+            | SessionError::AudioDeviceLost { .. } => None,
+        }
+    }
+}
+
+impl From<AddressProblem> for SessionError {
+    fn from(problem: AddressProblem) -> Self {
+        SessionError::Address(problem)
+    }
+}
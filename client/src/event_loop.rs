@@ -1,47 +1,178 @@
 use std::{
-    sync::mpsc::TryRecvError,
-    thread::{self, sleep, JoinHandle},
-    time::Duration,
+    fs,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+use crossbeam_channel::{select, Receiver, Sender};
+use ringbuf::Producer;
+
 use crate::{
-    audio::{start_output_stream, AudioState},
-    common::{UserAction, Communicator, LoopStatus, LoopMessage},
-    socket::{socket_connect, SocketState},
+    analysis::AnalysisTap,
+    audio::{
+        AudioRecoveryPolicy, AudioSink, AudioState, DeviceSelector, MonitorTap, StreamFailure,
+    },
+    capture::capture_path,
+    common::{Communicator, LoopMessage, UserAction},
+    error::SessionError,
+    mute::MicGate,
+    reconnect::ReconnectPolicy,
+    session::{SessionEvent, SessionMachine, SessionState},
+    socket::{socket_connect, socket_listen, ShutdownHandle, SocketListener, SocketState},
+    stats::SessionStats,
 };
 
-use anyhow::{format_err, Result};
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the loop takes from the settings, see [`crate::settings`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    /// The output device of the sessions.
+    pub device: DeviceSelector,
+    /// Of the buffer between the socket and the output, in samples.
+    pub buffer_capacity: usize,
+    /// Without data for this long the connection counts as lost.
+    pub read_timeout: Duration,
+    pub reconnect_policy: ReconnectPolicy,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            device: DeviceSelector::default(),
+            buffer_capacity: 10000,
+            read_timeout: Duration::from_secs(10),
+            reconnect_policy: ReconnectPolicy::default(),
+        }
+    }
+}
 
 pub fn start_event_loop<F>(
     comm: Communicator<LoopMessage, UserAction>,
+    sink: Box<dyn AudioSink>,
+    monitor_sink: Box<dyn AudioSink>,
+    reconnect_policy: ReconnectPolicy,
+    audio_recovery_policy: AudioRecoveryPolicy,
+    stats: SessionStats,
     gui_context: F,
 ) -> JoinHandle<()>
 where
     F: Fn() + Send + Sync + 'static,
 {
     thread::spawn(move || {
+        let (stream_failure_sender, stream_failures) = crossbeam_channel::unbounded();
+        let (network_sender, network_events) = crossbeam_channel::unbounded();
         let mut state = LoopState {
             address: String::new(),
-            audio_state: None,
-            socket_state: None,
+            generation: 0,
+            connection: None,
+            listener: None,
             comm,
-            status: LoopStatus::Ready,
+            subscribers: Vec::new(),
+            sink,
+            audio_state: None,
+            monitor_sink,
+            monitor_tap: MonitorTap::new(),
+            analysis_tap: AnalysisTap::default(),
+            gate: MicGate::new(),
+            monitor: None,
+            audio_recovery: None,
+            config: SessionConfig {
+                reconnect_policy: reconnect_policy.clone(),
+                ..Default::default()
+            },
+            machine: SessionMachine::new(reconnect_policy),
+            audio_recovery_policy,
+            stream_failure_sender,
+            stream_failures,
+            network_sender,
+            network_events,
+            stats,
+            measure_latency: false,
+            capture_directory: None,
             gui_context,
         };
         state.start_loop();
-        println!("Exiting");
+        tracing::info!("Exiting");
     })
 }
 
+/// Reported by the thread that owns the socket of a session.
+enum NetworkEvent {
+    Connected {
+        generation: u64,
+        shutdown: ShutdownHandle,
+    },
+    ConnectFailed {
+        generation: u64,
+        error: SessionError,
+    },
+    Lost {
+        generation: u64,
+        error: SessionError,
+    },
+}
+
+/// The loop's side of a session whose socket lives on its own thread.
+struct Connection {
+    /// Hands a new buffer to the socket thread after the output stream was
+    /// reopened, `None` while it is being reopened.
+    producers: Sender<Option<Producer<i16>>>,
+    /// Set once the socket thread reports the connection as established.
+    shutdown: Option<ShutdownHandle>,
+}
+
+/// The second output, playing what the phone picks up to the user.
+struct Monitor {
+    audio_state: AudioState,
+}
+
+/// An output stream that died and is being reopened.
+struct AudioRecovery {
+    device: String,
+    reason: String,
+    attempt: u32,
+    retry_at: Instant,
+    /// Counted when the device was lost, the buffer nobody plays overruns
+    /// afterwards.
+    overruns: u64,
+}
+
 struct LoopState<F>
 where
     F: Fn() + Send + Sync + 'static,
 {
     address: String,
-    socket_state: Option<SocketState>,
+    /// Increases with every session so events of abandoned ones are ignored.
+    generation: u64,
+    connection: Option<Connection>,
+    listener: Option<SocketListener>,
     comm: Communicator<LoopMessage, UserAction>,
+    /// Get the same messages as `comm`.
+    subscribers: Vec<Sender<LoopMessage>>,
+    sink: Box<dyn AudioSink>,
     audio_state: Option<AudioState>,
-    status: LoopStatus,
+    monitor_sink: Box<dyn AudioSink>,
+    /// Shared with the socket of every session, whether the monitor runs or
+    /// not.
+    monitor_tap: MonitorTap,
+    monitor: Option<Monitor>,
+    /// Shared like `monitor_tap`.
+    analysis_tap: AnalysisTap,
+    gate: MicGate,
+    audio_recovery: Option<AudioRecovery>,
+    config: SessionConfig,
+    machine: SessionMachine,
+    audio_recovery_policy: AudioRecoveryPolicy,
+    stream_failure_sender: Sender<StreamFailure>,
+    stream_failures: Receiver<StreamFailure>,
+    network_sender: Sender<NetworkEvent>,
+    network_events: Receiver<NetworkEvent>,
+    stats: SessionStats,
+    measure_latency: bool,
+    /// Where sessions are captured, if they are.
+    capture_directory: Option<PathBuf>,
     gui_context: F,
 }
 
@@ -50,107 +181,539 @@ where
     F: Fn() + Send + Sync + 'static,
 {
     fn send(&mut self, message: LoopMessage) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(message.clone()).is_ok());
         self.comm.send(message).expect("Cannot send message");
         (self.gui_context)();
     }
 
-    fn seek(&mut self) {
-        if let Err(err) = self.socket_state.as_mut().unwrap().seek() {
-            eprintln!("Cannot seek from socket: {}", err);
-            self.disconnect().expect("Cannot disconnect from socket");
-            self.send(LoopMessage::SocketReconnecting);
-            for _ in 0..5 {
-                match self.connect() {
-                    Ok((socket, audio)) => {
-                        self.socket_state.replace(socket);
-                        self.audio_state.replace(audio);
-                        self.send(LoopMessage::SocketConnected);
-                        self.status = LoopStatus::Connected;
-                        return;
+    fn state(&self) -> &SessionState {
+        self.machine.state()
+    }
+
+    /// Feeds `event` to the session and publishes the new state. Returns
+    /// false if the event wasn't valid in the current state.
+    fn apply(&mut self, event: SessionEvent) -> bool {
+        match self.machine.handle(event, Instant::now()) {
+            Ok(()) => {
+                tracing::debug!(state = self.state().name(), "Session changed");
+                self.send(LoopMessage::State(self.machine.state().clone()));
+                true
+            }
+            Err(err) => {
+                tracing::warn!("{}", err);
+                false
+            }
+        }
+    }
+
+    fn start_audio(
+        &self,
+        selector: &DeviceSelector,
+    ) -> Result<(Producer<i16>, AudioState), SessionError> {
+        let (producer, consumer) =
+            ringbuf::RingBuffer::<i16>::new(self.config.buffer_capacity).split();
+        let audio_state = self.sink.start(
+            consumer,
+            self.stats.clone(),
+            selector,
+            self.stream_failure_sender.clone(),
+        )?;
+        Ok((producer, audio_state))
+    }
+
+    fn stop_audio(&mut self) -> Result<(), SessionError> {
+        self.audio_recovery = None;
+        self.audio_state.take().map_or(Ok(()), |audio| audio.stop())
+    }
+
+    fn start_monitor(&mut self, selector: &DeviceSelector) -> Result<(), SessionError> {
+        let (producer, consumer) =
+            ringbuf::RingBuffer::<i16>::new(self.config.buffer_capacity).split();
+        // the main output's statistics are the ones that matter
+        let stats = SessionStats::new();
+        let audio_state = self.monitor_sink.start(
+            consumer,
+            stats.clone(),
+            selector,
+            self.stream_failure_sender.clone(),
+        )?;
+        self.monitor_tap
+            .attach(producer, stats.snapshot().output_sample_rate);
+        self.monitor = Some(Monitor { audio_state });
+        Ok(())
+    }
+
+    fn stop_monitor(&mut self) -> Result<(), SessionError> {
+        self.monitor_tap.detach();
+        self.monitor
+            .take()
+            .map_or(Ok(()), |monitor| monitor.audio_state.stop())
+    }
+
+    /// Runs `connect` and then reads from the socket on a new thread.
+    fn start_session<C>(&mut self, connect: C)
+    where
+        C: FnOnce() -> Result<SocketState, SessionError> + Send + 'static,
+    {
+        self.generation += 1;
+        let generation = self.generation;
+        let (producers, new_producers) = crossbeam_channel::unbounded();
+        self.connection = Some(Connection {
+            producers,
+            shutdown: None,
+        });
+        let events = self.network_sender.clone();
+        let capture_directory = self.capture_directory.clone();
+        let monitor_tap = self.monitor_tap.clone();
+        let analysis_tap = self.analysis_tap.clone();
+        let gate = self.gate.clone();
+        let read_timeout = self.config.read_timeout;
+        let connect = move || {
+            let mut socket = connect()?;
+            socket.set_read_timeout(read_timeout)?;
+            socket.set_monitor(monitor_tap);
+            socket.set_analysis(analysis_tap);
+            socket.set_gate(gate);
+            if let Some(directory) = capture_directory {
+                start_capture(&mut socket, &directory);
+            }
+            Ok(socket)
+        };
+        thread::spawn(move || run_session(generation, connect, new_producers, events));
+    }
+
+    /// Starts the attempt the session is in the connecting state for.
+    fn connect(&mut self) {
+        let (producer, audio_state) = match self.start_audio(&self.config.device) {
+            Ok(audio) => audio,
+            Err(err) => {
+                self.apply(SessionEvent::ConnectFailed(err));
+                return;
+            }
+        };
+        self.audio_state = Some(audio_state);
+        let address = self.address.clone();
+        let stats = self.stats.clone();
+        let measure_latency = self.measure_latency;
+        self.start_session(move || {
+            socket_connect(address.as_str(), producer, stats, measure_latency)
+        });
+    }
+
+    fn retry(&mut self) {
+        if self.apply(SessionEvent::RetryDue) {
+            if let SessionState::Connecting { attempt } = self.state() {
+                tracing::info!(attempt, "Reconnecting to {}", self.address);
+            }
+            self.connect();
+        }
+    }
+
+    fn listen(&mut self) -> Result<String, SessionError> {
+        let listener = socket_listen(self.address.as_str())?;
+        let address = listener.local_addr()?.to_string();
+        self.listener = Some(listener);
+        Ok(address)
+    }
+
+    fn accept(&mut self) {
+        let (producer, consumer) =
+            ringbuf::RingBuffer::<i16>::new(self.config.buffer_capacity).split();
+        let phone = match self.listener.as_ref().unwrap().accept() {
+            Ok(Some(phone)) => phone,
+            Ok(None) => return,
+            Err(err) => return tracing::warn!("Error accepting connection: {}", err),
+        };
+        let audio_state = match self.sink.start(
+            consumer,
+            self.stats.clone(),
+            &self.config.device,
+            self.stream_failure_sender.clone(),
+        ) {
+            Ok(audio_state) => audio_state,
+            Err(err) => {
+                tracing::warn!("Error accepting connection: {}", err);
+                phone.reject();
+                return;
+            }
+        };
+        self.audio_state = Some(audio_state);
+        let stats = self.stats.clone();
+        let measure_latency = self.measure_latency;
+        // the handshake waits for the phone, never on the loop
+        self.start_session(move || phone.start(producer, stats, measure_latency));
+    }
+
+    /// Closes the connection and the output stream, leaving the state to the
+    /// caller.
+    fn release(&mut self) -> Result<(), SessionError> {
+        self.generation += 1;
+        let socket_result = self
+            .connection
+            .take()
+            .and_then(|connection| connection.shutdown)
+            .map_or(Ok(()), |shutdown| shutdown.shutdown());
+        let audio_result = self.stop_audio();
+        socket_result.and(audio_result)
+    }
+
+    fn disconnect(&mut self) {
+        let result = self.release();
+        self.listener = None;
+        if !self.apply(SessionEvent::Disconnect) {
+            return;
+        }
+        if let Err(err) = result {
+            tracing::warn!("Error disconnecting: {}", err);
+            self.apply(SessionEvent::Failed(err));
+        }
+    }
+
+    /// Returns false when the loop should exit.
+    fn handle_action(&mut self, action: UserAction) -> bool {
+        match action {
+            UserAction::Connect(address) => {
+                if self.state().can_start() {
+                    self.address = address;
+                    self.stats.reset();
+                }
+                if self.apply(SessionEvent::Connect) {
+                    self.connect();
+                }
+            }
+            UserAction::Listen(address) => {
+                if !self.state().can_start() {
+                    self.apply(SessionEvent::Listen { address });
+                    return true;
+                }
+                self.address = address;
+                self.stats.reset();
+                match self.listen() {
+                    Ok(address) => self.apply(SessionEvent::Listen { address }),
+                    Err(err) => self.apply(SessionEvent::Failed(err)),
+                };
+            }
+            UserAction::UserDisconnect => self.disconnect(),
+            UserAction::SetLatencyMeasurement(enabled) => {
+                self.measure_latency = enabled;
+            }
+            UserAction::Configure(config) => {
+                self.machine.set_policy(config.reconnect_policy.clone());
+                self.config = config;
+            }
+            UserAction::SetCapture(directory) => {
+                self.capture_directory = directory;
+            }
+            UserAction::SetMonitor(selector) => {
+                if let Err(err) = self.stop_monitor() {
+                    tracing::warn!("Error stopping monitor: {}", err);
+                }
+                if let Some(selector) = selector {
+                    if let Err(err) = self.start_monitor(&selector) {
+                        tracing::warn!("Error starting monitor: {}", err);
+                        self.send(LoopMessage::MonitorFailed(err.to_string()));
                     }
-                    Err(err) => {
-                        eprintln!("Error reconnecting: {}", err);
+                }
+            }
+            UserAction::SetMonitorVolume(volume) => self.monitor_tap.set_volume(volume),
+            UserAction::SetMonitorMuted(muted) => self.monitor_tap.set_muted(muted),
+            UserAction::SetAnalysis(input) => match input {
+                Some(input) => self.analysis_tap.attach(input),
+                None => self.analysis_tap.detach(),
+            },
+            UserAction::SetMuted(muted) => {
+                self.gate.set_muted(muted);
+                self.send(LoopMessage::Mic(self.gate.state()));
+            }
+            UserAction::ToggleMute => {
+                self.gate.set_muted(!self.gate.state().muted);
+                self.send(LoopMessage::Mic(self.gate.state()));
+            }
+            UserAction::SetPushToTalk(enabled) => {
+                self.gate.set_push_to_talk(enabled);
+                self.send(LoopMessage::Mic(self.gate.state()));
+            }
+            UserAction::Talk(talking) => {
+                self.gate.set_talking(talking);
+                self.send(LoopMessage::Mic(self.gate.state()));
+            }
+            UserAction::SetFade(fade) => self.gate.set_fade(fade),
+            UserAction::SwitchProfile(name) => self.send(LoopMessage::SwitchProfile(name)),
+            UserAction::Subscribe(subscriber) => {
+                let current = [
+                    LoopMessage::State(self.state().clone()),
+                    LoopMessage::Mic(self.gate.state()),
+                ];
+                if current
+                    .into_iter()
+                    .all(|message| subscriber.send(message).is_ok())
+                {
+                    self.subscribers.push(subscriber);
+                }
+            }
+            UserAction::Exit => {
+                self.listener = None;
+                if let Err(err) = self.stop_monitor() {
+                    tracing::warn!("Error stopping monitor: {}", err);
+                }
+                if let Err(err) = self.release() {
+                    tracing::warn!("Error disconnecting: {}", err);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn handle_network_event(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::Connected {
+                generation,
+                shutdown,
+            } => {
+                if generation != self.generation {
+                    // the user gave up on this one while it was connecting
+                    shutdown.shutdown().ok();
+                    return;
+                }
+                if let SessionState::Connecting { attempt } = self.state() {
+                    if *attempt > 0 {
+                        self.stats.record_reconnect();
                     }
                 }
-                sleep(Duration::from_secs(2));
+                let peer = shutdown.peer_addr();
+                if let Some(connection) = self.connection.as_mut() {
+                    connection.shutdown = Some(shutdown);
+                }
+                let connecting = matches!(self.state(), SessionState::Connecting { .. });
+                if self.apply(SessionEvent::Connected) && connecting {
+                    self.send(LoopMessage::Connected {
+                        address: self.address.clone(),
+                        peer,
+                    });
+                }
+            }
+            NetworkEvent::ConnectFailed { generation, error } if generation == self.generation => {
+                if let Err(err) = self.release() {
+                    tracing::warn!("Error stopping audio: {}", err);
+                }
+                self.apply(SessionEvent::ConnectFailed(error));
+            }
+            NetworkEvent::Lost { generation, error } if generation == self.generation => {
+                tracing::warn!("Connection lost: {}", error);
+                if let Err(err) = self.release() {
+                    tracing::warn!("Error disconnecting: {}", err);
+                }
+                self.apply(SessionEvent::ConnectionLost(error));
+            }
+            NetworkEvent::ConnectFailed { .. } | NetworkEvent::Lost { .. } => {}
+        }
+    }
+
+    fn handle_stream_failure(&mut self, failure: StreamFailure) {
+        if self.monitor.as_ref().map(|monitor| monitor.audio_state.id())
+            == Some(failure.stream_id)
+        {
+            // nothing depends on the monitor, the user turns it on again
+            tracing::warn!("Monitor failed: {}", failure.error);
+            self.stop_monitor().ok();
+            self.send(LoopMessage::MonitorFailed(failure.error));
+            return;
+        }
+        // failures of streams that were already replaced don't matter
+        if self.audio_state.as_ref().map(|audio| audio.id()) != Some(failure.stream_id) {
+            return;
+        }
+        let device = self
+            .audio_state
+            .take()
+            .map(|audio_state| audio_state.device_name().to_owned())
+            .unwrap_or_default();
+        tracing::error!("Audio device {} failed: {}", device, failure.error);
+        // nothing plays the old buffer anymore
+        if let Some(connection) = self.connection.as_ref() {
+            connection.producers.send(None).ok();
+        }
+        self.send(LoopMessage::AudioDeviceLost(device.clone()));
+        self.audio_recovery = Some(AudioRecovery {
+            device,
+            reason: failure.error,
+            attempt: 0,
+            retry_at: Instant::now(),
+            overruns: self.stats.snapshot().overruns,
+        });
+        self.recover_audio();
+    }
 
+    fn recover_audio(&mut self) {
+        let mut recovery = match self.audio_recovery.take() {
+            Some(recovery) => recovery,
+            None => return,
+        };
+        let policy = &self.audio_recovery_policy;
+        let selector = if recovery.attempt < policy.attempts {
+            Some(DeviceSelector::Named(recovery.device.clone()))
+        } else if recovery.attempt == policy.attempts && policy.fall_back_to_default {
+            Some(DeviceSelector::Default)
+        } else {
+            None
+        };
+        let selector = match selector {
+            Some(selector) => selector,
+            None => {
+                self.listener = None;
+                if let Err(err) = self.release() {
+                    tracing::warn!("Error disconnecting: {}", err);
+                }
+                self.apply(SessionEvent::Failed(SessionError::AudioDeviceLost {
+                    device: recovery.device,
+                    reason: recovery.reason,
+                }));
+                return;
+            }
+        };
+        match self.start_audio(&selector) {
+            Ok((producer, audio_state)) => {
+                if let Some(connection) = self.connection.as_ref() {
+                    connection.producers.send(Some(producer)).ok();
+                }
+                self.stats.rewind_overruns(recovery.overruns);
+                let restored_on = audio_state.device_name().to_owned();
+                self.audio_state = Some(audio_state);
+                self.send(LoopMessage::AudioDeviceRestored(restored_on));
+            }
+            Err(err) => {
+                tracing::warn!(
+                    attempt = recovery.attempt,
+                    "Error reopening audio device: {}",
+                    err
+                );
+                recovery.attempt += 1;
+                recovery.retry_at = Instant::now() + policy.retry_delay;
+                self.audio_recovery = Some(recovery);
             }
-            self.send(LoopMessage::AudioStreamError("Lost connection".to_owned()));
         }
     }
 
-    fn disconnect(&mut self) -> Result<()> {
-        self.socket_state.take().unwrap().disconnect()?;
-        self.audio_state.take().unwrap().stop()?;
-        self.status = LoopStatus::Ready;
-        Ok(())
+    /// When the loop next has to act without being woken by a message.
+    fn next_deadline(&self) -> Option<Instant> {
+        let state_deadline = match self.state() {
+            SessionState::Reconnecting { retry_at, .. } => Some(*retry_at),
+            // rejecting phones while streaming needs polling too
+            SessionState::Listening { .. }
+            | SessionState::Connected {
+                listen_address: Some(_),
+            } => Some(Instant::now() + ACCEPT_INTERVAL),
+            _ => None,
+        };
+        let audio_deadline = self.audio_recovery.as_ref().map(|recovery| recovery.retry_at);
+        state_deadline.into_iter().chain(audio_deadline).min()
     }
 
-    fn connect(&mut self) -> Result<(SocketState, AudioState)> {
-        let (producer, consumer) = ringbuf::RingBuffer::<i16>::new(10000).split();
-        let audio_state = start_output_stream(consumer)?;
-        let stream = socket_connect(self.address.as_str(), producer).map_err(|err| {
-            eprintln!("Connection error: {:?}", err);
-            match err {
-                crate::socket::SocketError::AddressError => format_err!("Device address invalid"),
-                crate::socket::SocketError::ConnectionError => {
-                    format_err!("Error connecting to device")
+    fn handle_timers(&mut self) {
+        let now = Instant::now();
+        match self.state() {
+            SessionState::Reconnecting { retry_at, .. } if *retry_at <= now => self.retry(),
+            // an accepted phone is still being set up
+            SessionState::Listening { .. } if self.connection.is_none() => self.accept(),
+            SessionState::Listening { .. } | SessionState::Connected { .. } => {
+                if let Some(listener) = self.listener.as_ref() {
+                    listener.reject_pending();
                 }
-                crate::socket::SocketError::SetupError => format_err!("Internal error"),
             }
-        })?;
-        Ok((stream, audio_state))
+            _ => {}
+        }
+        if self
+            .audio_recovery
+            .as_ref()
+            .is_some_and(|recovery| recovery.retry_at <= now)
+        {
+            self.recover_audio();
+        }
     }
 
     fn start_loop(&mut self) {
+        let actions = self.comm.receiver().clone();
+        let network_events = self.network_events.clone();
+        let stream_failures = self.stream_failures.clone();
         loop {
-            match self.status {
-                LoopStatus::Ready => match self.comm.receive().unwrap() {
-                    UserAction::Connect(address) => {
-                        self.address = address;
-                        match self.connect() {
-                            Ok((socket, audio)) => {
-                                self.status = LoopStatus::Connected;
-                                self.audio_state = Some(audio);
-                                self.socket_state = Some(socket);
-                                self.send(LoopMessage::SocketConnected);
-                            }
-                            Err(err) => {
-                                self.status = LoopStatus::Ready;
-                                self.send(LoopMessage::AudioStreamError(err.to_string()));
-                            }
-                        }
-                    }
-                    UserAction::UserDisconnect => eprintln!("Not connected yet"),
-                    UserAction::Exit => break,
-                },
-                LoopStatus::Connected => match self.comm.try_receive() {
-                    Ok(message) => match message {
-                        UserAction::Connect(_) => {
-                            eprintln!("Already connected");
-                        }
-                        UserAction::UserDisconnect => match self.disconnect() {
-                            Err(err) => {
-                                eprintln!("Error disconnecting");
-                                self.send(LoopMessage::AudioStreamError(err.to_string()));
-                            }
-                            Ok(()) => {
-                                self.send(LoopMessage::SocketClosed);
-                            }
-                        },
-                        UserAction::Exit => break,
-                    },
-                    Err(err) => match err {
-                        TryRecvError::Empty => {
-                            self.seek();
-                        }
-                        TryRecvError::Disconnected => {
-                            eprintln!("Communicator disconnected");
+            let timer = self
+                .next_deadline()
+                .map_or_else(crossbeam_channel::never, crossbeam_channel::at);
+            select! {
+                recv(actions) -> action => match action {
+                    Ok(action) => {
+                        if !self.handle_action(action) {
                             break;
                         }
-                    },
+                    }
+                    Err(_) => {
+                        tracing::warn!("Communicator disconnected");
+                        break;
+                    }
                 },
+                // the loop holds a sender of both, so they never disconnect
+                recv(network_events) -> event => self.handle_network_event(event.unwrap()),
+                recv(stream_failures) -> failure => self.handle_stream_failure(failure.unwrap()),
+                recv(timer) -> _ => self.handle_timers(),
             }
         }
     }
 }
+
+fn start_capture(socket: &mut SocketState, directory: &Path) {
+    let path = capture_path(directory);
+    let result = fs::create_dir_all(directory)
+        .map_err(|source| SessionError::Internal {
+            context: "creating capture directory",
+            source,
+        })
+        .and_then(|()| socket.start_capture(&path));
+    match result {
+        Ok(()) => tracing::info!("Capturing session to {}", path.display()),
+        // the session works without it
+        Err(err) => tracing::warn!("Cannot capture session to {}: {}", path.display(), err),
+    }
+}
+
+/// Owns the socket of one session: connects, then reads until the connection
+/// ends or the loop shuts it down.
+fn run_session<C>(
+    generation: u64,
+    connect: C,
+    new_producers: Receiver<Option<Producer<i16>>>,
+    events: Sender<NetworkEvent>,
+) where
+    C: FnOnce() -> Result<SocketState, SessionError>,
+{
+    let _span = tracing::info_span!("session", generation).entered();
+    let connected = connect().and_then(|socket| Ok((socket.shutdown_handle()?, socket)));
+    let mut socket = match connected {
+        Ok((shutdown, socket)) => {
+            events
+                .send(NetworkEvent::Connected {
+                    generation,
+                    shutdown,
+                })
+                .ok();
+            socket
+        }
+        Err(error) => {
+            events
+                .send(NetworkEvent::ConnectFailed { generation, error })
+                .ok();
+            return;
+        }
+    };
+    loop {
+        if let Some(producer) = new_producers.try_iter().last() {
+            socket.set_media_producer(producer);
+        }
+        if let Err(error) = socket.receive() {
+            socket.disconnect().ok();
+            events.send(NetworkEvent::Lost { generation, error }).ok();
+            return;
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Plays into a JACK client port, for studio and broadcast setups.
+
+use std::sync::Mutex;
+
+use crossbeam_channel::Sender;
+use ringbuf::Consumer;
+
+use crate::{
+    audio::{
+        next_stream_id, write_data, AudioSink, AudioState, DeviceSelector, Playback, StreamFailure,
+        INPUT_SAMPLE_RATE,
+    },
+    error::SessionError,
+    resampler::Resampler,
+    stats::SessionStats,
+};
+
+pub const JACK_DEVICE: &str = "JACK";
+
+const CLIENT_NAME: &str = "Fast Mic";
+const PORT_NAME: &str = "out";
+
+#[derive(Debug, Clone, Default)]
+pub struct JackSink {
+    connect_to: Option<String>,
+}
+
+impl JackSink {
+    /// `connect_to` is the full name of a port to connect the output to,
+    /// e.g. `system:playback_1`.
+    pub fn new(connect_to: Option<String>) -> Self {
+        JackSink { connect_to }
+    }
+}
+
+impl AudioSink for JackSink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        if matches!(selector, DeviceSelector::Named(name) if name != JACK_DEVICE) {
+            return Err(SessionError::AudioDeviceNotFound);
+        }
+        let (client, _status) =
+            jack::Client::new(CLIENT_NAME, jack::ClientOptions::NO_START_SERVER)
+                .map_err(|err| SessionError::audio("opening JACK client", err))?;
+        let port = client
+            .register_port(PORT_NAME, jack::AudioOut)
+            .map_err(|err| SessionError::audio("registering JACK port", err))?;
+        let port_name = port
+            .name()
+            .map_err(|err| SessionError::audio("registering JACK port", err))?;
+        let sample_rate = client.sample_rate() as u32;
+        stats.set_output_sample_rate(sample_rate);
+        stats.set_buffer_capacity(consumer.capacity());
+
+        let id = next_stream_id();
+        let client = client
+            .activate_async(
+                Notifications { id, failures },
+                Process {
+                    port,
+                    consumer,
+                    stats,
+                    resampler: Resampler::new(INPUT_SAMPLE_RATE, sample_rate),
+                    ratio: INPUT_SAMPLE_RATE as f64 / sample_rate.max(1) as f64,
+                    input: Vec::new(),
+                    resampled: Vec::new(),
+                },
+            )
+            .map_err(|err| SessionError::audio("starting JACK client", err))?;
+        if let Some(destination) = &self.connect_to {
+            client
+                .as_client()
+                .connect_ports_by_name(&port_name, destination)
+                .map_err(|err| SessionError::audio("connecting JACK port", err))?;
+        }
+        Ok(AudioState::new(
+            id,
+            JACK_DEVICE.to_owned(),
+            JackPlayback {
+                client: Mutex::new(Some(client)),
+            },
+        ))
+    }
+}
+
+struct Notifications {
+    id: u64,
+    failures: Sender<StreamFailure>,
+}
+
+impl jack::NotificationHandler for Notifications {
+    fn shutdown(&mut self, _status: jack::ClientStatus, reason: &str) {
+        self.failures
+            .send(StreamFailure {
+                stream_id: self.id,
+                error: reason.to_owned(),
+            })
+            .ok();
+    }
+}
+
+struct Process {
+    port: jack::Port<jack::AudioOut>,
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    /// From the phone's rate to the server's.
+    resampler: Resampler,
+    /// Input samples per output sample.
+    ratio: f64,
+    input: Vec<i16>,
+    /// Converted samples not played yet.
+    resampled: Vec<i16>,
+}
+
+impl jack::ProcessHandler for Process {
+    fn process(&mut self, _: &jack::Client, scope: &jack::ProcessScope) -> jack::Control {
+        let output = self.port.as_mut_slice(scope);
+        let mut underrun = false;
+        while self.resampled.len() < output.len() {
+            let missing = output.len() - self.resampled.len();
+            let wanted = (missing as f64 * self.ratio).ceil().max(1.0) as usize;
+            let consumer = &mut self.consumer;
+            self.input.clear();
+            self.input.extend((0..wanted).map(|_| {
+                consumer.pop().unwrap_or_else(|| {
+                    underrun = true;
+                    0
+                })
+            }));
+            self.resampler.process(&self.input, &mut self.resampled);
+        }
+        let mut samples = self.resampled.drain(..output.len());
+        write_data(output, 1, &mut || samples.next().unwrap_or(0));
+        if underrun {
+            self.stats.record_underrun();
+        }
+        self.stats.set_buffer_fill(self.consumer.len());
+        jack::Control::Continue
+    }
+}
+
+struct JackPlayback {
+    client: Mutex<Option<jack::AsyncClient<Notifications, Process>>>,
+}
+
+impl Playback for JackPlayback {
+    fn pause(&self) -> Result<(), SessionError> {
+        match self.client.lock().unwrap().take() {
+            Some(client) => client
+                .deactivate()
+                .map(|_| ())
+                .map_err(|err| SessionError::audio("stopping JACK client", err)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+
+const OFFSET_SAMPLES: usize = 8;
+const SMOOTHING: f64 = 1.0 / 8.0;
+
+/// Estimates the phone clock offset from ping/pong round trips and the
+/// delay between capture on the phone and arrival on the client.
+#[derive(Debug, Default)]
+pub struct LatencyMeter {
+    // (round trip, offset) of the last pongs, all in µs
+    round_trips: VecDeque<(i64, i64)>,
+    network_delay: Option<f64>,
+}
+
+impl LatencyMeter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn record_pong(&mut self, sent_at: i64, received_at: i64, now: i64) {
+        let round_trip = (now - sent_at).max(0);
+        let offset = received_at - (sent_at + round_trip / 2);
+        if self.round_trips.len() == OFFSET_SAMPLES {
+            self.round_trips.pop_front();
+        }
+        self.round_trips.push_back((round_trip, offset));
+    }
+
+    pub fn record_audio(&mut self, captured_at: i64, now: i64) {
+        if let Some(offset) = self.clock_offset() {
+            let delay = (now - (captured_at - offset)).max(0) as f64;
+            self.network_delay = Some(match self.network_delay {
+                Some(current) => current + (delay - current) * SMOOTHING,
+                None => delay,
+            });
+        }
+    }
+
+    /// Phone clock minus local clock in µs, taken from the fastest recent
+    /// round trip since it has the least queueing error.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.round_trips
+            .iter()
+            .min_by_key(|(round_trip, _)| *round_trip)
+            .map(|(_, offset)| *offset)
+    }
+
+    pub fn round_trip(&self) -> Option<i64> {
+        self.round_trips.back().map(|(round_trip, _)| *round_trip)
+    }
+
+    /// Smoothed delay between capture and arrival in µs.
+    pub fn network_delay(&self) -> Option<f64> {
+        self.network_delay
+    }
+}
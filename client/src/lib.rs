@@ -1,4 +1,31 @@
+pub mod analysis;
 pub mod audio;
-pub mod socket;
+pub mod capture;
 pub mod common;
-pub mod event_loop;
\ No newline at end of file
+pub mod control;
+pub mod error;
+pub mod event_loop;
+#[cfg(feature = "jack")]
+pub mod jack_sink;
+pub mod latency;
+pub mod logging;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod memory_sink;
+pub mod mute;
+pub mod notifications;
+pub mod output;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub mod pipewire_sink;
+pub mod protocol;
+#[cfg(target_os = "linux")]
+pub mod pulse;
+pub mod reconnect;
+pub mod resampler;
+pub mod session;
+pub mod settings;
+pub mod simulator;
+pub mod socket;
+pub mod stats;
+pub mod tray;
+pub mod wav;
@@ -0,0 +1,305 @@
+//! The diagnostics of the client, as [`tracing`] events. They are printed to
+//! stderr, written to daily log files and kept in memory for the log window
+//! of the GUI, since the Windows build has no console.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+    },
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    reload, Layer, Registry,
+};
+
+use crate::settings::LoggingSettings;
+
+/// Kept for the log window, the oldest are dropped first.
+pub const MAX_LINES: usize = 2000;
+
+/// The least severe events that are logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    /// Every chunk read from the phone.
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+
+    fn of(level: &Level) -> LogLevel {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+/// An event as shown in the log window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub time: String,
+    pub level: LogLevel,
+    /// The module the event comes from.
+    pub target: String,
+    /// The spans the event happened in, outermost first, with their fields.
+    pub spans: String,
+    /// The message followed by the fields of the event.
+    pub message: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>5} {}{}: {}",
+            self.time,
+            self.level.name(),
+            self.spans,
+            self.target,
+            self.message
+        )
+    }
+}
+
+/// The last [`MAX_LINES`] events, shared by the subscriber with the log
+/// window.
+#[derive(Clone, Default)]
+pub struct LogLines {
+    lines: Arc<Mutex<VecDeque<LogLine>>>,
+}
+
+impl LogLines {
+    /// Oldest first.
+    pub fn snapshot(&self) -> Vec<LogLine> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+    }
+
+    fn push(&self, line: LogLine) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// The formatted fields of a span, kept in its extensions.
+struct SpanFields(String);
+
+/// Writes the message, then the other fields as `name=value`.
+#[derive(Default)]
+struct FieldWriter {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldWriter {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &format_args!("{}", value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).ok();
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            write!(self.fields, "{}={:?}", field.name(), value).ok();
+        }
+    }
+}
+
+impl<S> Layer<S> for LogLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut writer = FieldWriter::default();
+        attrs.record(&mut writer);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(writer.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut writer = FieldWriter::default();
+        event.record(&mut writer);
+        let mut message = writer.message;
+        if !writer.fields.is_empty() {
+            message.push(' ');
+            message.push_str(&writer.fields);
+        }
+        let mut spans = String::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            spans.push_str(span.name());
+            if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                if !fields.is_empty() {
+                    write!(spans, "{{{}}}", fields).ok();
+                }
+            }
+            spans.push_str(": ");
+        }
+        let mut time = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut time)).ok();
+        let metadata = event.metadata();
+        self.push(LogLine {
+            time,
+            level: LogLevel::of(metadata.level()),
+            target: metadata.target().to_owned(),
+            spans,
+            message,
+        });
+    }
+}
+
+/// Changes what the installed subscriber logs and reads what it kept.
+#[derive(Clone)]
+pub struct Logging {
+    level: reload::Handle<LevelFilter, Registry>,
+    lines: LogLines,
+    /// Of the log files, `None` if they aren't written.
+    directory: Option<PathBuf>,
+}
+
+impl Logging {
+    /// The subscriber for `settings`, writing its files to `directory`, and
+    /// the handle to it.
+    pub fn new(
+        settings: &LoggingSettings,
+        directory: Option<&Path>,
+    ) -> (Logging, impl Subscriber + Send + Sync + 'static) {
+        let (level, level_handle) = reload::Layer::new(settings.level.filter());
+        let files = directory.filter(|_| settings.files).and_then(|directory| {
+            let appender = Builder::new()
+                .rotation(Rotation::DAILY)
+                .filename_prefix("fast-mic")
+                .filename_suffix("log")
+                .max_log_files(settings.max_files.max(1))
+                .build(directory);
+            match appender {
+                Ok(appender) => Some((appender, directory.to_owned())),
+                Err(err) => {
+                    // there is no subscriber yet to log it
+                    eprintln!("Cannot write logs to {}: {}", directory.display(), err);
+                    None
+                }
+            }
+        });
+        let directory = files.as_ref().map(|(_, directory)| directory.clone());
+        let file_layer = files.map(|(appender, _)| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(appender)
+        });
+        let lines = LogLines::default();
+        let subscriber = Registry::default()
+            .with(level)
+            // stdout may carry the audio
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(io::stderr().is_terminal())
+                    .with_writer(io::stderr),
+            )
+            .with(file_layer)
+            .with(lines.clone());
+        let logging = Logging {
+            level: level_handle,
+            lines,
+            directory,
+        };
+        (logging, subscriber)
+    }
+
+    /// Installs the subscriber for the whole program.
+    pub fn install(settings: &LoggingSettings, directory: Option<&Path>) -> Logging {
+        let (logging, subscriber) = Logging::new(settings, directory);
+        if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+            eprintln!("Cannot install logging: {}", err);
+        }
+        logging
+    }
+
+    /// Where the files go unless the settings say otherwise.
+    pub fn default_directory() -> Option<PathBuf> {
+        directories_next::ProjectDirs::from("", "", "Fast Mic")
+            .map(|dirs| dirs.data_local_dir().join("logs"))
+    }
+
+    pub fn set_level(&self, level: LogLevel) {
+        if let Err(err) = self.level.reload(level.filter()) {
+            eprintln!("Cannot change the log level: {}", err);
+        }
+    }
+
+    pub fn lines(&self) -> &LogLines {
+        &self.lines
+    }
+
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+}
@@ -2,14 +2,41 @@
     all(target_os = "windows", not(feature = "console"),),
     windows_subsystem = "windows"
 )]
-use std::{sync::Arc, time::Duration};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use clap::Parser;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use eframe::IconData;
 use egui::{Button, Color32, FontFamily, FontId, RichText, TextEdit, TextStyle};
 
 use fast_mic::{
-    common::{UserAction, Communicator, GuiStatus, LoopMessage},
+    analysis::{
+        band_edges, Analyzer, ScopeSnapshot, Stage, FLOOR_DB, SPECTRUM_BANDS, WAVEFORM_POINTS,
+    },
+    audio::{
+        output_device_names, AudioRecoveryPolicy, CpalSink, DeviceSelector, INPUT_SAMPLE_RATE,
+    },
+    common::{Communicator, LoopMessage, UserAction},
+    control::{generate_token, ControlServer},
+    error::ErrorKind,
     event_loop::start_event_loop,
+    logging::{LogLevel, Logging},
+    mute::MicState,
+    notifications::{Notification, Notifier},
+    output::OutputKind,
+    reconnect::ReconnectPolicy,
+    session::SessionState,
+    settings::{
+        ControlSettings, Overrides, Settings, SettingsError, StartupSettings, READ_TIMEOUT_MS,
+    },
+    socket::{AddressProblem, DeviceAddress},
+    stats::{SessionStats, StatsSnapshot},
+    tray::{TrayCommand, TrayIcon, TrayStatus},
 };
 
 #[macro_use]
@@ -22,8 +49,45 @@ lazy_static! {
     static ref ICON_BYTES: &'static [u8] = include_bytes!("assets/icon.png");
 }
 
+/// Samples this close to full scale count as clipping.
+const CLIPPING: f32 = 0.99;
+/// How often the loop is checked on while there is no window.
+const TRAY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Parser)]
+#[command(about = "Uses the phone running Fast Mic as a microphone")]
+struct Args {
+    /// Where the audio goes: device, stdout, fifo:<path> or jack[:<port>].
+    /// For this run only, the settings are left alone.
+    #[arg(long)]
+    output: Option<OutputKind>,
+    /// Channels of the raw outputs.
+    #[arg(long)]
+    channels: Option<u16>,
+    /// Serves the control API on the address, e.g. 127.0.0.1:50552.
+    /// For this run only, the settings are left alone.
+    #[arg(long)]
+    control: Option<String>,
+    /// Starts with the named profile of the settings.
+    #[arg(long)]
+    profile: Option<String>,
+    /// Serves the statistics for Prometheus on the address, e.g.
+    /// 127.0.0.1:50553.
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics: Option<String>,
+}
+
 fn main() {
-    let size = Some(egui::vec2(400.0, 250.0));
+    let args = Args::parse();
+    let stored = Settings::path().and_then(|path| Settings::load(&path).ok().flatten());
+    let logging = Logging::install(
+        &stored
+            .as_ref()
+            .map(|settings| settings.logging.clone())
+            .unwrap_or_default(),
+        Logging::default_directory().as_deref(),
+    );
     let img = image::load_from_memory(&ICON_BYTES)
         .expect("Fail loading icon")
         .into_bytes();
@@ -35,27 +99,207 @@ fn main() {
     };
 
     let options = eframe::NativeOptions {
-        min_window_size: size,
-        max_window_size: size,
-        initial_window_size: size,
+        min_window_size: Some(egui::vec2(320.0, 250.0)),
+        initial_window_size: Some(egui::vec2(400.0, 420.0)),
         icon_data: Some(icon_data),
-        // resizable: false,
         ..Default::default()
     };
-    eframe::run_native("Fast Mic", options, Box::new(|cc| Box::new(MyApp::new(cc))));
+    // eframe can't hide its window, so starting minimized starts without one
+    let start_minimized =
+        stored.is_some_and(|settings| settings.startup.tray && settings.startup.minimized);
+    // the running app, or what to create it from
+    let app = if start_minimized {
+        match MyApp::new(None, args, logging.clone()).run_in_tray() {
+            Some(app) => Ok(app),
+            None => return,
+        }
+    } else {
+        Err(args)
+    };
+    eframe::run_native(
+        "Fast Mic",
+        options,
+        Box::new(|cc| {
+            let mut app = app.unwrap_or_else(|args| MyApp::new(cc.storage, args, logging));
+            app.attach_window(cc);
+            Box::new(app)
+        }),
+    );
 }
 
 pub struct MyApp {
     address: String,
-    status: GuiStatus,
+    status: SessionState,
     comm: Communicator<UserAction, LoopMessage>,
     error_message: Option<String>,
+    error_hint: Option<&'static str>,
+    audio_notice: Option<String>,
+    stats: SessionStats,
+    measure_latency: bool,
+    capture: bool,
+    listen_mode: bool,
+    listen_address: String,
+    event_loop: Option<JoinHandle<()>>,
+    output: OutputKind,
+    channels: u16,
+    monitor: MonitorControls,
+    mic: MicControls,
+    control: ControlPanel,
+    settings: Settings,
+    /// From the command line, applied on top of `settings`.
+    overrides: Overrides,
+    /// `None` if saving is impossible or would lose what's stored.
+    settings_path: Option<PathBuf>,
+    profiles: ProfilePanel,
+    settings_window: SettingsWindow,
+    scope: ScopeView,
+    repaint: Repaint,
+    tray: TrayControls,
+    notifier: Notifier,
+    logging: Logging,
+    log_view: LogView,
+    #[cfg(feature = "metrics")]
+    metrics: Option<fast_mic::metrics::MetricsServer>,
+}
+
+/// The context of the window, once there is one, for the threads that wake
+/// it up.
+#[derive(Clone, Default)]
+struct Repaint(Arc<Mutex<Option<egui::Context>>>);
+
+impl Repaint {
+    fn attach(&self, ctx: &egui::Context) {
+        *self.0.lock().unwrap() = Some(ctx.clone());
+    }
+
+    fn ctx(&self) -> Option<egui::Context> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn request(&self) {
+        if let Some(ctx) = self.0.lock().unwrap().as_ref() {
+            ctx.request_repaint();
+        }
+    }
+}
+
+/// The icon in the system tray, see [`fast_mic::tray`].
+struct TrayControls {
+    icon: Option<TrayIcon>,
+    sender: Sender<TrayCommand>,
+    commands: Receiver<TrayCommand>,
+}
+
+/// Everything that isn't needed to connect, in a window of its own.
+struct SettingsWindow {
+    open: bool,
+    tab: SettingsTab,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SettingsTab {
+    Connection,
+    AudioDevice,
+    Buffering,
+    Processing,
+    About,
+}
+
+impl SettingsTab {
+    const ALL: [SettingsTab; 5] = [
+        SettingsTab::Connection,
+        SettingsTab::AudioDevice,
+        SettingsTab::Buffering,
+        SettingsTab::Processing,
+        SettingsTab::About,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            SettingsTab::Connection => "Connection",
+            SettingsTab::AudioDevice => "Audio device",
+            SettingsTab::Buffering => "Buffering",
+            SettingsTab::Processing => "Processing",
+            SettingsTab::About => "About",
+        }
+    }
+}
+
+/// Picks, adds and removes the profiles of the settings.
+struct ProfilePanel {
+    new_name: String,
+}
+
+enum PhoneAction {
+    Connect(String),
+    Pin(String),
+    Unpin(String),
+}
+
+enum ProfileChange {
+    Select(String),
+    /// A copy of the active profile.
+    Add(String),
+    Remove,
+}
+
+/// Mute and push-to-talk, as last reported by the loop.
+struct MicControls {
+    state: MicState,
+}
+
+/// The local server other programs drive the client with, see
+/// [`fast_mic::control`]. Its settings are shared by every profile.
+struct ControlPanel {
+    server: Option<ControlServer>,
+    error: Option<String>,
+}
+
+/// The second output, playing what the phone picks up to the user.
+struct MonitorControls {
+    enabled: bool,
+    /// Empty for the default device.
+    device: String,
+    volume: f32,
+    muted: bool,
+    output_devices: Vec<String>,
+}
+
+/// The levels, waveform and spectrum of what the phone sends, before and
+/// after the averaging filter and muting. The analyzer only gets samples while it is shown.
+struct ScopeView {
+    open: bool,
+    analyzer: Analyzer,
+}
+
+/// The last events of [`fast_mic::logging`], to copy into bug reports.
+struct LogView {
+    open: bool,
+}
+
+fn capture_directory() -> PathBuf {
+    std::env::temp_dir().join("fast-mic")
+}
+
+fn capture_action(enabled: bool) -> UserAction {
+    UserAction::SetCapture(enabled.then(capture_directory))
+}
+
+fn reconnect_countdown(attempt: u32, max_attempts: Option<u32>, retry_at: Instant) -> String {
+    let remaining = retry_at
+        .saturating_duration_since(Instant::now())
+        .as_secs_f32()
+        .ceil();
+    match max_attempts {
+        Some(max_attempts) => format!("Attempt {} of {} in {}s", attempt, max_attempts, remaining),
+        None => format!("Attempt {} in {}s", attempt, remaining),
+    }
 }
 
 impl eframe::App for MyApp {
-    fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        storage.set_string("address", self.address.to_owned());
-        storage.flush();
+    fn save(&mut self, _storage: &mut dyn eframe::Storage) {
+        self.store_profile();
+        self.save_settings();
     }
 
     fn auto_save_interval(&self) -> std::time::Duration {
@@ -63,126 +307,1386 @@ impl eframe::App for MyApp {
     }
 
     fn on_exit(&mut self, _gl: &eframe::glow::Context) {
-        if let Err(err) = self.comm.send(UserAction::Exit) {
-            println!("Error sending message: {}", err);
-        }
+        self.stop_event_loop();
     }
 
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if !self.status.can_connect() {
-            if let Ok(message) = self.comm.try_receive() {
-                match message {
-                    LoopMessage::Ready => self.status = GuiStatus::Ready,
-                    LoopMessage::SocketConnected => self.status = GuiStatus::Connected,
-                    LoopMessage::SocketCannotConnect => {
-                        self.status = GuiStatus::Ready;
-                        self.error_message = Some("Error connecting".to_string());
-                    }
-                    LoopMessage::SocketClosed => {
-                        self.status = GuiStatus::Ready;
-                    }
-                    LoopMessage::SocketReconnecting => {
-                        self.status = GuiStatus::Reconnecting;
-                    }
-                    LoopMessage::AudioStreamError(error) => {
-                        self.status = GuiStatus::Failed;
-                        self.error_message = Some(error)
-                    }
-                }
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.handle_messages();
+        while let Ok(command) = self.tray.commands.try_recv() {
+            if command == TrayCommand::Quit {
+                frame.quit();
             }
+            self.handle_tray_command(command);
         }
+        self.update_tray();
+        self.mic.handle_keys(ctx, &self.comm);
 
-        let address = self.address.to_owned();
-        let text_edit = TextEdit::singleline(&mut self.address)
+        let address_field = if self.listen_mode {
+            &mut self.listen_address
+        } else {
+            &mut self.address
+        };
+        let address_problem = if self.status.can_start() {
+            address_field.parse::<DeviceAddress>().err()
+        } else {
+            None
+        };
+        let text_edit = TextEdit::singleline(address_field)
             .desired_width(160.0)
-            .interactive(self.status.can_connect());
-        let button = Button::new(get_button_text(&self.status)).sense(
-            if self.status.can_connect() || self.status == GuiStatus::Connected {
+            .interactive(self.status.can_start());
+        let button = Button::new(get_button_text(&self.status, self.listen_mode)).sense(
+            if !self.status.can_start() || address_problem.is_none() {
                 egui::Sense::click()
             } else {
                 egui::Sense::focusable_noninteractive()
             },
         );
 
+        let mut start = false;
+        let mut phone_action = None;
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.vertical_centered(|ui| {
-                ui.add_space(30.0);
-                ui.heading(
-                    RichText::new(get_status_text(&self.status))
-                        .color(get_text_color(&self.status)),
-                );
-                ui.add_space(10.0);
-                ui.add(text_edit);
-                ui.add_space(10.0);
-                if ui.add(button).clicked() {
-                    self.error_message = None;
-                    if self.status == GuiStatus::Connected {
-                        match self.comm.send(UserAction::UserDisconnect) {
-                            Ok(_) => {
-                                self.status = GuiStatus::Disconnecting;
-                            }
-                            Err(err) => {
-                                eprintln!("Communicator error: {}", err);
-                                self.status = GuiStatus::Failed;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(30.0);
+                    ui.heading(
+                        RichText::new(get_status_text(&self.status))
+                            .color(get_text_color(&self.status)),
+                    );
+                    if let SessionState::Reconnecting {
+                        attempt,
+                        max_attempts,
+                        retry_at,
+                    } = self.status
+                    {
+                        ui.label(reconnect_countdown(attempt, max_attempts, retry_at));
+                        ctx.request_repaint();
+                    }
+                    if let Some(audio_notice) = self.audio_notice.as_ref() {
+                        ui.label(RichText::new(audio_notice).small().color(*YELLOW));
+                    }
+                    if let SessionState::Listening { address } = &self.status {
+                        ui.label(format!("Listening on {}", address));
+                    }
+                    ui.add_space(10.0);
+                    ui.add_enabled_ui(self.status.can_start(), |ui| {
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.listen_mode, false, "Connect to phone");
+                            ui.radio_value(&mut self.listen_mode, true, "Wait for phone");
+                        });
+                    });
+                    ui.add(text_edit);
+                    if let Some(problem) = address_problem.as_ref() {
+                        let color = match problem {
+                            AddressProblem::Empty => ui.visuals().weak_text_color(),
+                            _ => *RED,
+                        };
+                        ui.label(RichText::new(problem.to_string()).small().color(color));
+                    }
+                    ui.add_space(10.0);
+                    if ui.add(button).clicked() {
+                        if !self.status.can_start() {
+                            self.error_message = None;
+                            self.error_hint = None;
+                            self.audio_notice = None;
+                            if let Err(err) = self.comm.send(UserAction::UserDisconnect) {
+                                tracing::error!("Communicator error: {}", err);
                                 self.error_message = Some("Communicator error".to_string());
                             }
+                        } else {
+                            start = true;
                         }
-                    } else {
-                        match self.comm.send(UserAction::Connect(address)) {
-                            Ok(_) => {
-                                self.status = GuiStatus::Connecting;
-                            }
-                            Err(err) => {
-                                eprintln!("Communicator error: {}", err);
-                                self.status = GuiStatus::Failed;
-                                self.error_message = Some("Communicator error".to_string());
-                            }
+                    };
+                    self.mic.show(ui, &self.comm);
+                    phone_action = show_phones(ui, &mut self.settings, self.status.can_start());
+                    if let Some(error_message) = self.error_message.as_ref() {
+                        ui.add_space(20.0);
+                        ui.label(error_message);
+                        if let Some(error_hint) = self.error_hint {
+                            ui.label(RichText::new(error_hint).small());
                         }
                     }
-                };
-                if let Some(error_message) = self.error_message.as_ref() {
                     ui.add_space(20.0);
-                    ui.label(error_message);
+                    ui.horizontal(|ui| {
+                        if ui.button("Settings").clicked() {
+                            self.settings_window.open = !self.settings_window.open;
+                        }
+                        if ui.button("Scope").clicked() {
+                            self.scope.set_open(!self.scope.open, &self.comm);
+                        }
+                        if ui.button("Log").clicked() {
+                            self.log_view.open = !self.log_view.open;
+                        }
+                    });
+                });
+            });
+        });
+        if start {
+            self.start_session();
+        }
+        if let Some(action) = phone_action {
+            self.handle_phone_action(action);
+        }
+        self.show_settings(ctx);
+        self.scope.show(ctx, &self.comm);
+        self.log_view.show(ctx, &self.logging);
+    }
+}
+
+impl MicControls {
+    fn show(&mut self, ui: &mut egui::Ui, comm: &Communicator<UserAction, LoopMessage>) {
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            let text = if self.state.muted { "Unmute" } else { "Mute" };
+            if ui.button(text).clicked() {
+                send(comm, UserAction::SetMuted(!self.state.muted));
+            }
+            let mut push_to_talk = self.state.push_to_talk;
+            if ui.checkbox(&mut push_to_talk, "Push to talk").changed() {
+                send(comm, UserAction::SetPushToTalk(push_to_talk));
+            }
+        });
+        let hint = if self.state.push_to_talk {
+            "Ctrl+M to mute, hold Space to talk"
+        } else {
+            "Ctrl+M to mute"
+        };
+        ui.label(RichText::new(hint).small().weak());
+    }
+
+    fn handle_keys(&mut self, ctx: &egui::Context, comm: &Communicator<UserAction, LoopMessage>) {
+        let (toggle_mute, space_down) = {
+            let input = ctx.input();
+            (
+                input.modifiers.command && input.key_pressed(egui::Key::M),
+                input.key_down(egui::Key::Space),
+            )
+        };
+        if toggle_mute {
+            send(comm, UserAction::ToggleMute);
+        }
+        // Space belongs to the address field while typing
+        let talking = self.state.push_to_talk && space_down && ctx.memory().focus().is_none();
+        if talking != self.state.talking {
+            // not waiting for the loop, so a held key doesn't send it again
+            self.state.talking = talking;
+            send(comm, UserAction::Talk(talking));
+        }
+    }
+}
+
+fn send(comm: &Communicator<UserAction, LoopMessage>, action: UserAction) {
+    if let Err(err) = comm.send(action) {
+        tracing::error!("Communicator error: {}", err);
+    }
+}
+
+impl ProfilePanel {
+    fn show(&mut self, ui: &mut egui::Ui, settings: &Settings) -> Option<ProfileChange> {
+        let mut change = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("profile")
+                .selected_text(settings.profile.clone())
+                .show_ui(ui, |ui| {
+                    for name in settings.profiles.keys() {
+                        if ui
+                            .selectable_label(*name == settings.profile, name)
+                            .clicked()
+                        {
+                            change = Some(ProfileChange::Select(name.clone()));
+                        }
+                    }
+                });
+            ui.add(
+                TextEdit::singleline(&mut self.new_name)
+                    .hint_text("New profile")
+                    .desired_width(100.0),
+            );
+            let name = self.new_name.trim();
+            let can_add = !name.is_empty() && !settings.profiles.contains_key(name);
+            if ui.add_enabled(can_add, Button::new("Add")).clicked() {
+                change = Some(ProfileChange::Add(name.to_owned()));
+                self.new_name.clear();
+            }
+            let can_remove = settings.profiles.len() > 1;
+            if ui.add_enabled(can_remove, Button::new("Delete")).clicked() {
+                change = Some(ProfileChange::Remove);
+            }
+        });
+        change
+    }
+}
+
+impl TrayControls {
+    /// Shows or hides the icon to match the settings. Without a tray asks for
+    /// the window, so the user is never left with neither.
+    fn apply(&mut self, settings: &StartupSettings, status: TrayStatus, repaint: &Repaint) {
+        if !settings.tray {
+            self.icon = None;
+            return;
+        }
+        if self.icon.is_some() {
+            return;
+        }
+        let repaint = repaint.clone();
+        match TrayIcon::start(status, self.sender.clone(), move || repaint.request()) {
+            Ok(icon) => self.icon = Some(icon),
+            Err(err) => {
+                tracing::warn!("Cannot start tray icon: {}", err);
+                self.sender.send(TrayCommand::ShowWindow).ok();
+            }
+        }
+    }
+}
+
+impl ControlPanel {
+    /// Starts or stops the server to match the settings.
+    fn apply(
+        &mut self,
+        settings: &ControlSettings,
+        comm: &Communicator<UserAction, LoopMessage>,
+        stats: &SessionStats,
+    ) {
+        self.server = None;
+        self.error = None;
+        if !settings.enabled {
+            return;
+        }
+        match ControlServer::start(
+            settings.address.as_str(),
+            settings.token.clone(),
+            comm.sender().clone(),
+            stats.clone(),
+        ) {
+            Ok(server) => self.server = Some(server),
+            Err(err) => {
+                tracing::warn!("Cannot start control API: {}", err);
+                self.error = Some(format!("Cannot listen on {}: {}", settings.address, err));
+            }
+        }
+    }
+
+    fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        settings: &mut ControlSettings,
+        comm: &Communicator<UserAction, LoopMessage>,
+        stats: &SessionStats,
+    ) {
+        if ui
+            .checkbox(
+                &mut settings.enabled,
+                "Allow other programs to control Fast Mic",
+            )
+            .changed()
+        {
+            self.apply(settings, comm, stats);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            // the server is restarted by turning it off and on
+            ui.add_enabled(
+                !settings.enabled,
+                TextEdit::singleline(&mut settings.address).desired_width(160.0),
+            );
+        });
+        if let Some(server) = self.server.as_ref() {
+            ui.label(format!("Listening on http://{}", server.local_addr()));
+            ui.horizontal(|ui| {
+                ui.label("Token");
+                ui.add(TextEdit::singleline(&mut settings.token.as_str()));
+            });
+        }
+        if let Some(error) = self.error.as_ref() {
+            ui.colored_label(*RED, error);
+        }
+    }
+}
+
+impl MonitorControls {
+    fn action(&self) -> UserAction {
+        let selector = match self.device.as_str() {
+            "" => DeviceSelector::Default,
+            name => DeviceSelector::Named(name.to_owned()),
+        };
+        UserAction::SetMonitor(self.enabled.then_some(selector))
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, comm: &Communicator<UserAction, LoopMessage>) {
+        let send = |action| send(comm, action);
+        let mut changed = ui.checkbox(&mut self.enabled, "Hear the phone").changed();
+        let selected = match self.device.as_str() {
+            "" => "Default",
+            name => name,
+        };
+        egui::ComboBox::from_label("Monitor device")
+            .selected_text(selected.to_owned())
+            .show_ui(ui, |ui| {
+                changed |= ui
+                    .selectable_value(&mut self.device, String::new(), "Default")
+                    .changed();
+                for name in self.output_devices.iter() {
+                    changed |= ui
+                        .selectable_value(&mut self.device, name.clone(), name)
+                        .changed();
+                }
+            });
+        if changed {
+            send(self.action());
+        }
+        ui.horizontal(|ui| {
+            let volume = ui.add(
+                egui::Slider::new(&mut self.volume, 0.0..=2.0)
+                    .text("Volume")
+                    .show_value(false),
+            );
+            if volume.changed() {
+                send(UserAction::SetMonitorVolume(self.volume));
+            }
+            if ui.checkbox(&mut self.muted, "Mute").changed() {
+                send(UserAction::SetMonitorMuted(self.muted));
+            }
+        });
+    }
+}
+
+impl ScopeView {
+    fn set_open(&mut self, open: bool, comm: &Communicator<UserAction, LoopMessage>) {
+        self.open = open;
+        send(
+            comm,
+            UserAction::SetAnalysis(open.then(|| self.analyzer.input())),
+        );
+    }
+
+    fn show(&mut self, ctx: &egui::Context, comm: &Communicator<UserAction, LoopMessage>) {
+        let mut open = self.open;
+        egui::Window::new("Scope")
+            .open(&mut open)
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                let input = self.analyzer.snapshot(Stage::Input);
+                let processed = self.analyzer.snapshot(Stage::Processed);
+                egui::Grid::new("levels").num_columns(2).show(ui, |ui| {
+                    for (name, snapshot) in [("Input", &input), ("Processed", &processed)] {
+                        ui.label(name);
+                        show_level(ui, snapshot);
+                        ui.end_row();
+                    }
+                });
+                ui.label(RichText::new("Waveform").strong());
+                draw_waveform(ui, &input, &processed);
+                ui.label(RichText::new("Spectrum").strong());
+                draw_spectrum(ui, &input, &processed);
+                ui.label(
+                    RichText::new("Grey as received, green as played")
+                        .small()
+                        .weak(),
+                );
+            });
+        if open != self.open {
+            self.set_open(open, comm);
+        }
+    }
+}
+
+impl LogView {
+    fn show(&mut self, ctx: &egui::Context, logging: &Logging) {
+        let mut open = self.open;
+        egui::Window::new("Log")
+            .open(&mut open)
+            .resizable(true)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                let lines = logging.lines().snapshot();
+                ui.horizontal(|ui| {
+                    if ui.button("Copy").clicked() {
+                        ui.output().copied_text =
+                            lines.iter().map(|line| format!("{}\n", line)).collect();
+                    }
+                    if ui.button("Clear").clicked() {
+                        logging.lines().clear();
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::both().stick_to_bottom().show(ui, |ui| {
+                    for line in lines.iter() {
+                        let text = RichText::new(line.to_string()).monospace().small();
+                        ui.label(match line.level {
+                            LogLevel::Error => text.color(*RED),
+                            LogLevel::Warn => text.color(*YELLOW),
+                            _ => text,
+                        });
+                    }
+                });
+            });
+        self.open = open;
+    }
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(FLOOR_DB)
+}
+
+fn show_level(ui: &mut egui::Ui, snapshot: &ScopeSnapshot) {
+    let peak = to_db(snapshot.peak);
+    let text = format!("peak {:.0} dB, RMS {:.0} dB", peak, to_db(snapshot.rms));
+    // the meter shows the top 60dB
+    let bar = egui::ProgressBar::new(((peak + 60.0) / 60.0).clamp(0.0, 1.0));
+    if snapshot.peak >= CLIPPING {
+        ui.add(bar.text(RichText::new(format!("{}, clipping", text)).color(*RED)));
+    } else {
+        ui.add(bar.text(text));
+    }
+}
+
+fn scope_painter(ui: &mut egui::Ui, height: f32) -> (egui::Rect, egui::Painter) {
+    let size = egui::vec2(ui.available_width(), height);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    painter.rect_filled(response.rect, 2.0, ui.visuals().extreme_bg_color);
+    (response.rect, painter)
+}
+
+fn draw_waveform(ui: &mut egui::Ui, input: &ScopeSnapshot, processed: &ScopeSnapshot) {
+    let (rect, painter) = scope_painter(ui, 80.0);
+    let weak = ui.visuals().weak_text_color();
+    let step = rect.width() / WAVEFORM_POINTS as f32;
+    for (snapshot, color) in [(input, weak), (processed, *GREEN)] {
+        // the newest points on the right
+        let start = rect.right() - snapshot.waveform.len() as f32 * step;
+        for (index, &(low, high)) in snapshot.waveform.iter().enumerate() {
+            let x = start + index as f32 * step;
+            let y = |sample: f32| rect.center().y - sample * rect.height() / 2.0;
+            let color = if low <= -CLIPPING || high >= CLIPPING {
+                *RED
+            } else {
+                color
+            };
+            painter.line_segment(
+                [egui::pos2(x, y(high)), egui::pos2(x, y(low) + 1.0)],
+                (step.max(1.0), color),
+            );
+        }
+    }
+}
+
+fn draw_spectrum(ui: &mut egui::Ui, input: &ScopeSnapshot, processed: &ScopeSnapshot) {
+    let (rect, painter) = scope_painter(ui, 120.0);
+    let weak = ui.visuals().weak_text_color();
+    let edges = band_edges();
+    let (lowest, highest) = (edges[0].ln(), edges[SPECTRUM_BANDS].ln());
+    let x = |frequency: f32| {
+        rect.left() + (frequency.ln() - lowest) / (highest - lowest) * rect.width()
+    };
+    let font = FontId::proportional(10.0);
+    for (frequency, label) in [
+        (50.0, "50"),
+        (100.0, "100"),
+        (1000.0, "1k"),
+        (10000.0, "10k"),
+    ] {
+        let x = x(frequency);
+        painter.line_segment(
+            [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+            (1.0, ui.visuals().faint_bg_color),
+        );
+        painter.text(
+            egui::pos2(x + 2.0, rect.bottom()),
+            egui::Align2::LEFT_BOTTOM,
+            label,
+            font.clone(),
+            weak,
+        );
+    }
+    // from -100dB at the bottom to 0dB at the top
+    let y = |level: f32| rect.top() - level.max(-100.0) / 100.0 * rect.height();
+    for (snapshot, color) in [(input, weak), (processed, *GREEN)] {
+        let points = snapshot
+            .spectrum
+            .iter()
+            .zip(edges.windows(2))
+            .map(|(&level, edges)| egui::pos2(x((edges[0] * edges[1]).sqrt()), y(level)))
+            .collect();
+        painter.add(egui::Shape::line(points, (1.5, color)));
+    }
+}
+
+/// The favorites, renamed in place, and the other recent connections.
+fn show_phones(
+    ui: &mut egui::Ui,
+    settings: &mut Settings,
+    can_connect: bool,
+) -> Option<PhoneAction> {
+    if settings.favorites.is_empty() && settings.recents.is_empty() {
+        return None;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let mut action = None;
+    egui::CollapsingHeader::new("Phones")
+        .default_open(true)
+        .show(ui, |ui| {
+            egui::Grid::new("phones").num_columns(3).show(ui, |ui| {
+                let recents = &settings.recents;
+                let last_seen = |address: &str| {
+                    recents
+                        .iter()
+                        .find(|recent| recent.address == address)
+                        .map(|recent| format_last_seen(now.saturating_sub(recent.last_seen)))
+                        .unwrap_or_default()
+                };
+                for favorite in settings.favorites.iter_mut() {
+                    ui.add(TextEdit::singleline(&mut favorite.name).desired_width(100.0));
+                    ui.label(
+                        RichText::new(format!(
+                            "{} {}",
+                            favorite.address,
+                            last_seen(&favorite.address)
+                        ))
+                        .small(),
+                    );
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(can_connect, Button::new("Connect"))
+                            .clicked()
+                        {
+                            action = Some(PhoneAction::Connect(favorite.address.clone()));
+                        }
+                        if ui.button("Unpin").clicked() {
+                            action = Some(PhoneAction::Unpin(favorite.address.clone()));
+                        }
+                    });
+                    ui.end_row();
+                }
+                for recent in settings.recents.iter() {
+                    if settings
+                        .favorites
+                        .iter()
+                        .any(|favorite| favorite.address == recent.address)
+                    {
+                        continue;
+                    }
+                    ui.label(&recent.device);
+                    ui.label(
+                        RichText::new(format!("{} {}", recent.address, last_seen(&recent.address)))
+                            .small(),
+                    );
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(can_connect, Button::new("Connect"))
+                            .clicked()
+                        {
+                            action = Some(PhoneAction::Connect(recent.address.clone()));
+                        }
+                        if ui.button("Pin").clicked() {
+                            action = Some(PhoneAction::Pin(recent.address.clone()));
+                        }
+                    });
+                    ui.end_row();
                 }
             });
         });
+    action
+}
+
+fn format_last_seen(seconds_ago: u64) -> String {
+    match seconds_ago {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{} min ago", seconds_ago / 60),
+        3600..=86399 => format!("{} h ago", seconds_ago / 3600),
+        _ => format!("{} days ago", seconds_ago / 86400),
+    }
+}
+
+fn show_stats(ui: &mut egui::Ui, stats: &SessionStats, status: &SessionState) {
+    let snapshot = stats.snapshot();
+    egui::Grid::new("stats").striped(true).show(ui, |ui| {
+        for (name, value) in stats_rows(&snapshot) {
+            ui.label(name);
+            ui.label(value);
+            ui.end_row();
+        }
+    });
+    if ui.button("Copy as JSON").clicked() {
+        match snapshot.to_json() {
+            Ok(json) => ui.output().copied_text = json,
+            Err(err) => tracing::warn!("Error exporting statistics: {}", err),
+        }
+    }
+    if matches!(status, SessionState::Connected { .. }) {
+        ui.ctx().request_repaint();
+    }
+}
+
+fn stats_rows(snapshot: &StatsSnapshot) -> Vec<(&'static str, String)> {
+    vec![
+        ("Session", format!("{:.0}s", snapshot.session_seconds)),
+        (
+            "Received",
+            format!("{:.1} KiB", snapshot.bytes_received as f64 / 1024.0),
+        ),
+        (
+            "Sample rate",
+            format!(
+                "{:.0} Hz (output {} Hz)",
+                snapshot.measured_sample_rate, snapshot.output_sample_rate
+            ),
+        ),
+        (
+            "Buffer",
+            format!("{} / {}", snapshot.buffer_fill, snapshot.buffer_capacity),
+        ),
+        ("Underruns", snapshot.underruns.to_string()),
+        ("Overruns", snapshot.overruns.to_string()),
+        ("Reconnects", snapshot.reconnects.to_string()),
+        (
+            "Latency",
+            format!("{:.0} ms", snapshot.estimated_latency_ms),
+        ),
+        ("Jitter", format!("{:.1} ms", snapshot.jitter_ms)),
+        (
+            "Buffer delay",
+            format!("{:.0} ms", snapshot.buffer_delay_ms),
+        ),
+        ("Device latency", format_ms(snapshot.device_latency_ms)),
+        ("Network delay", format_ms(snapshot.network_delay_ms)),
+        ("Round trip", format_ms(snapshot.round_trip_ms)),
+        ("End to end", format_ms(snapshot.end_to_end_latency_ms)),
+    ]
+}
+
+fn format_ms(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{:.0} ms", value),
+        None => "-".to_owned(),
     }
 }
 
-fn get_text_color(status: &GuiStatus) -> Color32 {
+fn get_text_color(status: &SessionState) -> Color32 {
     match status {
-        GuiStatus::Disconnecting
-        | GuiStatus::Connecting
-        | GuiStatus::Reconnecting => *YELLOW,
-        GuiStatus::Ready | GuiStatus::Connected => *GREEN,
-        GuiStatus::Failed => *RED,
+        SessionState::Connecting { .. }
+        | SessionState::Reconnecting { .. }
+        | SessionState::Listening { .. } => *YELLOW,
+        SessionState::Idle | SessionState::Connected { .. } => *GREEN,
+        SessionState::Failed(_) => *RED,
     }
 }
 
 impl MyApp {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        setup_custom_fonts(&cc.egui_ctx);
+    /// Runs without a window until [`MyApp::attach_window`].
+    fn new(storage: Option<&dyn eframe::Storage>, args: Args, logging: Logging) -> Self {
         let (gui_comm, event_loop_comm) = Communicator::<UserAction, LoopMessage>::create_pair();
-        let cloned_ctx = cc.egui_ctx.clone();
-        let mut address = String::new();
-        if let Some(storage) = cc.storage {
-            if let Some(stored_address) = storage.get_string("address") {
-                address = stored_address;
+        let repaint = Repaint::default();
+        let loop_repaint = repaint.clone();
+        let scope_repaint = repaint.clone();
+        let mut error_message = None;
+        let mut settings_path = Settings::path();
+        let loaded = match settings_path.as_deref().map(Settings::load) {
+            Some(Ok(Some(settings))) => Ok(settings),
+            Some(Err(err)) => {
+                // keeps the file for the user to fix
+                settings_path = None;
+                Err(err)
+            }
+            _ => storage.map_or_else(
+                || Ok(Settings::default()),
+                |storage| Settings::from_storage(|key| storage.get_string(key)),
+            ),
+        };
+        let mut settings = loaded.unwrap_or_else(|err| {
+            tracing::error!("Error loading settings: {}", err);
+            error_message = Some(format!("{}, changes won't be saved", err));
+            Settings::default()
+        });
+        if let Some(profile) = args.profile.as_deref() {
+            if let Err(err) = settings.select(profile) {
+                error_message = Some(err.to_string());
             }
         }
-        start_event_loop(event_loop_comm, move || {
-            cloned_ctx.request_repaint();
+        let overrides = Overrides {
+            output: args.output.as_ref().map(OutputKind::to_string),
+            channels: args.channels,
+            control_address: args.control,
+        };
+        if settings.control.token.is_empty() {
+            settings.control.token = generate_token();
+        }
+        logging.set_level(settings.logging.level);
+
+        let device = overrides.device(&settings.profile().device);
+        let output = device.output.parse::<OutputKind>().unwrap_or_else(|err| {
+            error_message = Some(err.to_string());
+            OutputKind::Device
         });
+        let channels = device.channels;
+        let sink = output.sink(channels).unwrap_or_else(|err| {
+            error_message = Some(format!("Cannot use output {}: {}", output, err));
+            OutputKind::Device
+                .sink(channels)
+                .expect("The device output always exists")
+        });
+        let stats = SessionStats::new();
+        #[cfg(feature = "metrics")]
+        let metrics =
+            args.metrics.as_deref().and_then(
+                |address| match fast_mic::metrics::MetricsServer::start(address, stats.clone()) {
+                    Ok(server) => Some(server),
+                    Err(err) => {
+                        tracing::error!("Cannot serve metrics on {}: {}", address, err);
+                        error_message =
+                            Some(format!("Cannot serve metrics on {}: {}", address, err));
+                        None
+                    }
+                },
+            );
+        let event_loop = start_event_loop(
+            event_loop_comm,
+            sink,
+            Box::new(CpalSink),
+            ReconnectPolicy::default(),
+            AudioRecoveryPolicy::default(),
+            stats.clone(),
+            move || loop_repaint.request(),
+        );
+        let mut control = ControlPanel {
+            server: None,
+            error: None,
+        };
+        control.apply(&overrides.control(&settings.control), &gui_comm, &stats);
+        let (tray_sender, tray_commands) = crossbeam_channel::unbounded();
 
-        Self {
-            address,
+        let mut app = Self {
+            address: String::new(),
             comm: gui_comm,
             status: Default::default(),
-            error_message: None,
+            error_message,
+            error_hint: None,
+            audio_notice: None,
+            stats,
+            measure_latency: false,
+            capture: false,
+            listen_mode: false,
+            listen_address: String::new(),
+            event_loop: Some(event_loop),
+            output,
+            channels,
+            monitor: MonitorControls {
+                enabled: false,
+                device: String::new(),
+                volume: 1.0,
+                muted: false,
+                output_devices: output_device_names(),
+            },
+            mic: MicControls {
+                state: MicState::default(),
+            },
+            control,
+            settings,
+            overrides,
+            settings_path,
+            profiles: ProfilePanel {
+                new_name: String::new(),
+            },
+            settings_window: SettingsWindow {
+                open: false,
+                tab: SettingsTab::Connection,
+            },
+            scope: ScopeView {
+                open: false,
+                analyzer: Analyzer::start(move || scope_repaint.request()),
+            },
+            repaint,
+            tray: TrayControls {
+                icon: None,
+                sender: tray_sender,
+                commands: tray_commands,
+            },
+            notifier: Notifier::desktop(),
+            logging,
+            log_view: LogView { open: false },
+            #[cfg(feature = "metrics")]
+            metrics,
+        };
+        app.load_profile();
+        let status = app.tray_status();
+        app.tray.apply(&app.settings.startup, status, &app.repaint);
+        if app.settings.startup.auto_connect {
+            app.auto_connect();
+        }
+        app
+    }
+
+    fn attach_window(&mut self, cc: &eframe::CreationContext<'_>) {
+        setup_custom_fonts(&cc.egui_ctx);
+        set_visuals(&cc.egui_ctx, self.settings.profile().ui.dark_mode);
+        self.repaint.attach(&cc.egui_ctx);
+    }
+
+    /// Keeps the session going from the tray alone, until the user asks for
+    /// the window. Returns `None` if they quit instead.
+    fn run_in_tray(mut self) -> Option<Self> {
+        loop {
+            self.handle_messages();
+            self.update_tray();
+            match self.tray.commands.recv_timeout(TRAY_POLL_INTERVAL) {
+                Ok(TrayCommand::ShowWindow) => return Some(self),
+                Ok(TrayCommand::Quit) => {
+                    self.store_profile();
+                    self.save_settings();
+                    self.stop_event_loop();
+                    return None;
+                }
+                Ok(command) => self.handle_tray_command(command),
+                Err(RecvTimeoutError::Timeout) => {}
+                // the app holds a sender
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+    }
+
+    fn stop_event_loop(&mut self) {
+        if let Err(err) = self.comm.send(UserAction::Exit) {
+            tracing::error!("Error sending message: {}", err);
+        }
+        // lets the sink remove the virtual microphone
+        if let Some(event_loop) = self.event_loop.take() {
+            event_loop.join().ok();
+        }
+    }
+
+    fn handle_messages(&mut self) {
+        while let Ok(message) = self.comm.try_receive() {
+            if let Some(notification) = Notification::for_message(&self.status, &message) {
+                self.notifier
+                    .notify(&notification, &self.settings.notifications, Instant::now());
+            }
+            match message {
+                LoopMessage::State(state) => {
+                    match &state {
+                        SessionState::Idle => self.audio_notice = None,
+                        SessionState::Failed(error) => {
+                            tracing::warn!("Session failed: {:?}", error);
+                            self.audio_notice = None;
+                            self.error_message = Some(error.to_string());
+                            self.error_hint = Some(get_error_hint(error.kind()));
+                        }
+                        _ => {}
+                    }
+                    self.status = state;
+                }
+                LoopMessage::Connected { address, peer } => {
+                    let device = peer.map_or_else(String::new, |peer| peer.ip().to_string());
+                    self.settings
+                        .record_connection(&address, device, SystemTime::now());
+                    self.save_settings();
+                }
+                LoopMessage::AudioDeviceLost(device) => {
+                    self.audio_notice = Some(format!("Audio device {} lost, reopening...", device));
+                }
+                LoopMessage::AudioDeviceRestored(device) => {
+                    self.audio_notice = Some(format!("Audio restored on {}", device));
+                }
+                LoopMessage::MonitorFailed(error) => {
+                    self.monitor.enabled = false;
+                    self.audio_notice = Some(format!("Monitor stopped: {}", error));
+                }
+                LoopMessage::Mic(state) => self.mic.state = state,
+                LoopMessage::SwitchProfile(name) => {
+                    if let Err(err) = self.switch_profile(&name) {
+                        self.error_message = Some(err.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Connects to the phone, or waits for it in listen mode.
+    fn start_session(&mut self) {
+        self.error_message = None;
+        self.error_hint = None;
+        self.audio_notice = None;
+        let action = if self.listen_mode {
+            UserAction::Listen(self.listen_address.clone())
+        } else {
+            UserAction::Connect(self.address.clone())
+        };
+        if let Err(err) = self.comm.send(action) {
+            tracing::error!("Communicator error: {}", err);
+            self.error_message = Some("Communicator error".to_string());
+        }
+    }
+
+    /// Connects to the last phone, or waits for one in listen mode.
+    fn auto_connect(&mut self) {
+        if !self.listen_mode {
+            match self.settings.last_address() {
+                Some(address) => self.address = address.to_owned(),
+                None => return,
+            }
+        }
+        self.start_session();
+    }
+
+    /// Showing the window and quitting are up to the caller.
+    fn handle_tray_command(&mut self, command: TrayCommand) {
+        match command {
+            TrayCommand::Connect if self.status.can_start() => self.start_session(),
+            TrayCommand::Disconnect => send(&self.comm, UserAction::UserDisconnect),
+            TrayCommand::ToggleMute => send(&self.comm, UserAction::ToggleMute),
+            _ => {}
+        }
+    }
+
+    fn tray_status(&self) -> TrayStatus {
+        TrayStatus::new(&self.status, &self.mic.state, self.repaint.ctx().is_some())
+    }
+
+    fn update_tray(&mut self) {
+        let status = self.tray_status();
+        if let Some(icon) = self.tray.icon.as_mut() {
+            icon.set_status(status);
+        }
+    }
+
+    /// Writes what the user changed into the active profile.
+    fn store_profile(&mut self) {
+        let profile = self.settings.profile_mut();
+        let connection = &mut profile.connection;
+        connection.address = self.address.clone();
+        connection.listen_mode = self.listen_mode;
+        connection.listen_address = self.listen_address.clone();
+        connection.capture = self.capture;
+        profile.latency.measure_latency = self.measure_latency;
+        profile.device.monitor = self.monitor.enabled;
+        profile.device.monitor_device = self.monitor.device.clone();
+        profile.dsp.monitor_volume = self.monitor.volume;
+        profile.dsp.monitor_muted = self.monitor.muted;
+        profile.dsp.push_to_talk = self.mic.state.push_to_talk;
+    }
+
+    /// Shows the active profile and hands it to the loop.
+    fn load_profile(&mut self) {
+        let profile = self.settings.profile().clone();
+        self.address = profile.connection.address.clone();
+        self.listen_mode = profile.connection.listen_mode;
+        self.listen_address = profile.connection.listen_address.clone();
+        self.capture = profile.connection.capture;
+        self.measure_latency = profile.latency.measure_latency;
+        self.monitor.enabled = profile.device.monitor;
+        self.monitor.device = profile.device.monitor_device.clone();
+        self.monitor.volume = profile.dsp.monitor_volume;
+        self.monitor.muted = profile.dsp.monitor_muted;
+        self.mic.state.push_to_talk = profile.dsp.push_to_talk;
+        if let Some(ctx) = self.repaint.ctx() {
+            set_visuals(&ctx, profile.ui.dark_mode);
+        }
+        for action in [
+            UserAction::Configure(profile.session_config()),
+            UserAction::SetLatencyMeasurement(self.measure_latency),
+            capture_action(self.capture),
+            UserAction::SetMonitorVolume(self.monitor.volume),
+            UserAction::SetMonitorMuted(self.monitor.muted),
+            self.monitor.action(),
+            UserAction::SetPushToTalk(self.mic.state.push_to_talk),
+            UserAction::SetFade(Duration::from_millis(profile.dsp.fade_ms)),
+        ] {
+            send(&self.comm, action);
+        }
+        // the sink is only created at startup
+        let device = self.overrides.device(&profile.device);
+        if device.output != self.output.to_string() || device.channels != self.channels {
+            self.audio_notice = Some(format!("Restart to use output {}", device.output));
+        }
+    }
+
+    fn switch_profile(&mut self, name: &str) -> Result<(), SettingsError> {
+        self.store_profile();
+        self.settings.select(name)?;
+        self.load_profile();
+        self.save_settings();
+        Ok(())
+    }
+
+    fn change_profile(&mut self, change: ProfileChange) {
+        let result = match change {
+            ProfileChange::Select(name) => self.switch_profile(&name),
+            ProfileChange::Add(name) => {
+                self.store_profile();
+                self.settings.duplicate(&name);
+                self.save_settings();
+                Ok(())
+            }
+            ProfileChange::Remove => {
+                self.settings.remove();
+                self.load_profile();
+                self.save_settings();
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            self.error_message = Some(err.to_string());
+        }
+    }
+
+    fn handle_phone_action(&mut self, action: PhoneAction) {
+        match action {
+            PhoneAction::Connect(address) => {
+                self.listen_mode = false;
+                self.address = address;
+                self.start_session();
+            }
+            PhoneAction::Pin(address) => self.settings.pin(&address),
+            PhoneAction::Unpin(address) => self.settings.unpin(&address),
+        }
+        self.save_settings();
+    }
+
+    /// The changes apply right away where the loop allows it, and on the
+    /// next connection otherwise.
+    fn show_settings(&mut self, ctx: &egui::Context) {
+        let mut open = self.settings_window.open;
+        let mut profile_change = None;
+        let mut configure = false;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Profile");
+                    profile_change = self.profiles.show(ui, &self.settings);
+                });
+                ui.separator();
+                ui.horizontal_wrapped(|ui| {
+                    for tab in SettingsTab::ALL {
+                        ui.selectable_value(&mut self.settings_window.tab, tab, tab.title());
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| match self.settings_window.tab {
+                    SettingsTab::Connection => configure = self.show_connection_settings(ui),
+                    SettingsTab::AudioDevice => configure = self.show_device_settings(ui),
+                    SettingsTab::Buffering => configure = self.show_buffering_settings(ui),
+                    SettingsTab::Processing => self.show_processing_settings(ui),
+                    SettingsTab::About => self.show_about(ui),
+                });
+            });
+        if let Some(change) = profile_change {
+            self.change_profile(change);
+        }
+        if configure {
+            let config = self.settings.profile().session_config();
+            send(&self.comm, UserAction::Configure(config));
+        }
+        if self.settings_window.open && !open {
+            self.store_profile();
+            self.save_settings();
+        }
+        self.settings_window.open = open;
+    }
+
+    /// Returns whether the session settings changed.
+    fn show_connection_settings(&mut self, ui: &mut egui::Ui) -> bool {
+        let can_start = self.status.can_start();
+        let connection = &mut self.settings.profile_mut().connection;
+        let mut changed = false;
+        egui::Grid::new("connection").num_columns(2).show(ui, |ui| {
+            ui.label("Listen address");
+            ui.add_enabled(
+                can_start,
+                TextEdit::singleline(&mut self.listen_address).desired_width(160.0),
+            );
+            ui.end_row();
+            ui.label("Read timeout");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut connection.read_timeout_ms)
+                        .clamp_range(READ_TIMEOUT_MS)
+                        .speed(100)
+                        .suffix(" ms"),
+                )
+                .changed();
+            ui.end_row();
+            ui.label("Reconnect");
+            ui.horizontal(|ui| {
+                changed |= ui
+                    .checkbox(&mut connection.reconnect_forever, "Forever")
+                    .changed();
+                changed |= ui
+                    .add_enabled(
+                        !connection.reconnect_forever,
+                        egui::DragValue::new(&mut connection.reconnect_attempts)
+                            .clamp_range(0..=100)
+                            .suffix(" attempts"),
+                    )
+                    .changed();
+            });
+            ui.end_row();
+            ui.label("First retry after");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut connection.reconnect_delay_ms)
+                        .clamp_range(100..=60_000)
+                        .speed(50)
+                        .suffix(" ms"),
+                )
+                .changed();
+            ui.end_row();
+            ui.label("Longest wait");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut connection.reconnect_max_delay_ms)
+                        .clamp_range(100..=300_000)
+                        .speed(100)
+                        .suffix(" ms"),
+                )
+                .changed();
+            ui.end_row();
+        });
+        let capture = ui.add_enabled(
+            can_start,
+            egui::Checkbox::new(&mut self.capture, "Capture received stream"),
+        );
+        if capture.changed() {
+            send(&self.comm, capture_action(self.capture));
+        }
+        if self.capture {
+            ui.label(RichText::new(format!("Saved to {}", capture_directory().display())).small());
+        }
+        ui.separator();
+        ui.label(RichText::new("Startup").strong());
+        let startup = &mut self.settings.startup;
+        let tray = ui.checkbox(&mut startup.tray, "Show tray icon");
+        ui.add_enabled(
+            startup.tray,
+            egui::Checkbox::new(&mut startup.minimized, "Start with only the tray icon"),
+        );
+        ui.checkbox(&mut startup.auto_connect, "Connect to the last phone");
+        if startup.tray {
+            // eframe can neither hide its window nor open a new one
+            ui.label(
+                RichText::new("Closing the window quits Fast Mic, tray icon included")
+                    .small()
+                    .weak(),
+            );
+        }
+        if tray.changed() {
+            let status = self.tray_status();
+            self.tray
+                .apply(&self.settings.startup, status, &self.repaint);
+        }
+        ui.separator();
+        ui.label(RichText::new("Notify when").strong());
+        let notifications = &mut self.settings.notifications;
+        ui.checkbox(&mut notifications.reconnecting, "The phone drops out");
+        ui.checkbox(&mut notifications.reconnected, "It reconnects");
+        ui.checkbox(&mut notifications.failed, "Reconnecting gives up");
+        ui.checkbox(
+            &mut notifications.audio_device_lost,
+            "The audio device is lost",
+        );
+        ui.horizontal(|ui| {
+            ui.label("At most one of each every");
+            ui.add(
+                egui::DragValue::new(&mut notifications.min_interval_secs)
+                    .clamp_range(0..=3600)
+                    .suffix(" s"),
+            );
+        });
+        ui.separator();
+        ui.label(RichText::new("Control API").strong());
+        if let Some(address) = self.overrides.control_address.as_ref() {
+            ui.label(
+                RichText::new(format!("Started on {} by --control for this run", address))
+                    .small()
+                    .weak(),
+            );
+        }
+        self.control
+            .show(ui, &mut self.settings.control, &self.comm, &self.stats);
+        changed
+    }
+
+    /// Returns whether the session settings changed.
+    fn show_device_settings(&mut self, ui: &mut egui::Ui) -> bool {
+        let device = &mut self.settings.profile_mut().device;
+        let mut changed = false;
+        egui::Grid::new("device").num_columns(2).show(ui, |ui| {
+            ui.label("Output");
+            ui.add(
+                TextEdit::singleline(&mut device.output)
+                    .hint_text("device, stdout, fifo:<path> or jack")
+                    .desired_width(160.0),
+            );
+            ui.end_row();
+            ui.label("Channels");
+            ui.add(egui::DragValue::new(&mut device.channels).clamp_range(1..=8));
+            ui.end_row();
+            ui.label("Device name");
+            changed |= ui
+                .add(
+                    TextEdit::singleline(&mut device.device_prefix)
+                        .hint_text("Default device")
+                        .desired_width(160.0),
+                )
+                .changed();
+            ui.end_row();
+        });
+        let device = self.overrides.device(device);
+        match device.output.parse::<OutputKind>() {
+            Ok(output) if output == self.output && device.channels == self.channels => {}
+            // the sink is only created at startup
+            Ok(_) => {
+                ui.label(RichText::new("Restart Fast Mic to use the new output").small());
+            }
+            Err(err) => {
+                ui.colored_label(*RED, err.to_string());
+            }
+        }
+        ui.label(
+            RichText::new("The device is picked by the start of its name on each connection")
+                .small()
+                .weak(),
+        );
+        ui.separator();
+        ui.label(RichText::new("Monitor").strong());
+        self.monitor.show(ui, &self.comm);
+        changed
+    }
+
+    /// Returns whether the session settings changed.
+    fn show_buffering_settings(&mut self, ui: &mut egui::Ui) -> bool {
+        let latency = &mut self.settings.profile_mut().latency;
+        let buffer = ui
+            .horizontal(|ui| {
+                ui.label("Buffer");
+                ui.add(
+                    egui::DragValue::new(&mut latency.buffer_capacity)
+                        .clamp_range(480..=96_000)
+                        .speed(10)
+                        .suffix(" samples"),
+                )
+            })
+            .inner;
+        let buffer_ms = latency.buffer_capacity as f64 * 1000.0 / INPUT_SAMPLE_RATE as f64;
+        ui.label(
+            RichText::new(format!(
+                "Holds up to {:.0} ms, used from the next connection",
+                buffer_ms
+            ))
+            .small()
+            .weak(),
+        );
+        let measure_latency = ui.add_enabled(
+            self.status.can_start(),
+            egui::Checkbox::new(&mut self.measure_latency, "Measure latency"),
+        );
+        if measure_latency.changed() {
+            send(
+                &self.comm,
+                UserAction::SetLatencyMeasurement(self.measure_latency),
+            );
+        }
+        if matches!(self.status, SessionState::Connected { .. }) {
+            let snapshot = self.stats.snapshot();
+            ui.label(format!(
+                "Now {:.0} ms buffered, {} underruns, {} overruns",
+                snapshot.buffer_delay_ms, snapshot.underruns, snapshot.overruns
+            ));
+            ui.ctx().request_repaint();
+        }
+        buffer.changed()
+    }
+
+    fn show_processing_settings(&mut self, ui: &mut egui::Ui) {
+        let mut push_to_talk = self.mic.state.push_to_talk;
+        if ui.checkbox(&mut push_to_talk, "Push to talk").changed() {
+            send(&self.comm, UserAction::SetPushToTalk(push_to_talk));
+        }
+        ui.label(
+            RichText::new("Hold Space in the main window to talk")
+                .small()
+                .weak(),
+        );
+        let dsp = &mut self.settings.profile_mut().dsp;
+        let fade = ui.add(
+            egui::Slider::new(&mut dsp.fade_ms, 0..=200)
+                .text("Mute fade")
+                .suffix(" ms"),
+        );
+        if fade.changed() {
+            send(
+                &self.comm,
+                UserAction::SetFade(Duration::from_millis(dsp.fade_ms)),
+            );
+        }
+    }
+
+    fn show_about(&mut self, ui: &mut egui::Ui) {
+        ui.label(format!("Fast Mic {}", env!("CARGO_PKG_VERSION")));
+        ui.label(format!(
+            "Output {}, {} channels",
+            self.output, self.channels
+        ));
+        match self.settings_path.as_ref() {
+            Some(path) => ui.label(format!("Settings in {}", path.display())),
+            None => ui.colored_label(*YELLOW, "Settings aren't saved"),
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            ui.label(format!(
+                "Metrics on http://{}/metrics",
+                metrics.local_addr()
+            ));
+        }
+        let dark_mode = &mut self.settings.profile_mut().ui.dark_mode;
+        if ui.checkbox(dark_mode, "Dark mode").changed() {
+            set_visuals(ui.ctx(), *dark_mode);
+        }
+        ui.separator();
+        ui.label(RichText::new("Logging").strong());
+        let logging = &mut self.settings.logging;
+        egui::ComboBox::from_label("Level")
+            .selected_text(logging.level.name())
+            .show_ui(ui, |ui| {
+                for level in LogLevel::ALL {
+                    if ui
+                        .selectable_value(&mut logging.level, level, level.name())
+                        .changed()
+                    {
+                        self.logging.set_level(level);
+                    }
+                }
+            });
+        ui.checkbox(&mut logging.files, "Write log files (after a restart)");
+        match self.logging.directory() {
+            Some(directory) => ui.label(format!("Logs in {}", directory.display())),
+            None => ui.label("Logs aren't written to files"),
+        };
+        ui.separator();
+        ui.label(RichText::new("Statistics").strong());
+        show_stats(ui, &self.stats, &self.status);
+    }
+
+    fn save_settings(&self) {
+        if let Some(path) = self.settings_path.as_ref() {
+            if let Err(err) = self.settings.save(path) {
+                tracing::error!("Error saving settings: {}", err);
+            }
         }
     }
 }
 
+fn set_visuals(ctx: &egui::Context, dark_mode: bool) {
+    ctx.set_visuals(if dark_mode {
+        egui::Visuals::dark()
+    } else {
+        egui::Visuals::light()
+    });
+}
+
 fn setup_custom_fonts(ctx: &egui::Context) {
     let mut fonts = egui::FontDefinitions::default();
 
@@ -227,23 +1731,49 @@ fn setup_custom_fonts(ctx: &egui::Context) {
     .into();
     ctx.set_style(style);
 }
-fn get_button_text(status: &GuiStatus) -> &str {
-    if let GuiStatus::Connecting = status {
-        return "Connect";
+fn get_button_text(status: &SessionState, listen_mode: bool) -> &str {
+    match status {
+        SessionState::Idle | SessionState::Failed(_) => {
+            if listen_mode {
+                "Listen"
+            } else {
+                "Connect"
+            }
+        }
+        SessionState::Connecting { .. }
+        | SessionState::Reconnecting { .. }
+        | SessionState::Listening { .. } => "Cancel",
+        SessionState::Connected { .. } => "Disconnect",
     }
-    if status.can_connect() {
-        return "Connect";
+}
+
+fn get_error_hint(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Address => "Type the address shown in the mobile app",
+        ErrorKind::Dns => "Check the device name, or use the IP address shown in the mobile app",
+        ErrorKind::Refused => "Start the server in the mobile app and check the port",
+        ErrorKind::Timeout | ErrorKind::Unreachable => {
+            "Make sure the phone and the PC are connected to the same network"
+        }
+        ErrorKind::Bind => "Another program may be using this port, try a different one",
+        ErrorKind::Protocol => "Update the mobile app and the client to the same version",
+        ErrorKind::ConnectionLost => {
+            "Check that the mobile app is still running and the Wi-Fi signal is stable"
+        }
+        ErrorKind::AudioDeviceNotFound => "Install VB-CABLE or connect an output device",
+        ErrorKind::AudioDevice => "Check the output device in the system sound settings",
+        ErrorKind::AudioDeviceLost => "Reconnect the output device, then connect again",
+        ErrorKind::Internal => "Restart the client and report the problem if it persists",
     }
-    "Disconnect"
 }
 
-fn get_status_text(status: &GuiStatus) -> &str {
+fn get_status_text(status: &SessionState) -> &str {
     match status {
-        GuiStatus::Ready => "Waiting for connection",
-        GuiStatus::Connecting => "Connecting...",
-        GuiStatus::Connected => "Connected",
-        GuiStatus::Failed => "Connection failed",
-        GuiStatus::Disconnecting => "Disconnecting...",
-        GuiStatus::Reconnecting => "Reconnecting...",
+        SessionState::Idle => "Waiting for connection",
+        SessionState::Connecting { .. } => "Connecting...",
+        SessionState::Connected { .. } => "Connected",
+        SessionState::Failed(_) => "Connection failed",
+        SessionState::Reconnecting { .. } => "Reconnecting...",
+        SessionState::Listening { .. } => "Waiting for phone",
     }
 }
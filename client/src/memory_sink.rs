@@ -0,0 +1,203 @@
+//! An [`AudioSink`] without a sound card, for tests and headless runs.
+//!
+//! Nothing plays on its own: every [`MemorySink::advance`] moves a simulated
+//! clock forward and each running output pulls the samples a real device
+//! would have pulled in that time into a recording.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+use ringbuf::Consumer;
+
+use crate::{
+    audio::{next_stream_id, AudioSink, AudioState, DeviceSelector, Playback, StreamFailure},
+    error::SessionError,
+    stats::SessionStats,
+    wav,
+};
+
+pub const MEMORY_DEVICE: &str = "Memory";
+
+#[derive(Clone)]
+pub struct MemorySink {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    sample_rate: u32,
+    available: bool,
+    elapsed: Duration,
+    played: u64,
+    outputs: Vec<Output>,
+    recording: Vec<i16>,
+}
+
+struct Output {
+    id: u64,
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    failures: Sender<StreamFailure>,
+    stopped: Arc<AtomicBool>,
+}
+
+struct MemoryPlayback {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Playback for MemoryPlayback {
+    fn pause(&self) -> Result<(), SessionError> {
+        self.stopped.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for MemoryPlayback {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl MemorySink {
+    pub fn new(sample_rate: u32) -> Self {
+        MemorySink {
+            inner: Arc::new(Mutex::new(Inner {
+                sample_rate,
+                available: true,
+                elapsed: Duration::ZERO,
+                played: 0,
+                outputs: Vec::new(),
+                recording: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the clock forward, letting the running output play for
+    /// `duration`. An output that runs out of samples plays silence, like a
+    /// device would.
+    ///
+    /// There is a single recording, so at most one output may be running.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.elapsed += duration;
+        let due = (inner.elapsed.as_secs_f64() * inner.sample_rate as f64) as u64;
+        let count = due.saturating_sub(inner.played) as usize;
+        inner.played = due;
+
+        let Inner {
+            outputs, recording, ..
+        } = &mut *inner;
+        outputs.retain(|output| !output.stopped.load(Ordering::Relaxed));
+        assert!(
+            outputs.len() <= 1,
+            "{} outputs are running on the same memory sink",
+            outputs.len()
+        );
+        for output in outputs.iter_mut() {
+            let mut underrun = false;
+            for _ in 0..count {
+                recording.push(output.consumer.pop().unwrap_or_else(|| {
+                    underrun = true;
+                    0
+                }));
+            }
+            if underrun {
+                output.stats.record_underrun();
+            }
+            output.stats.set_buffer_fill(output.consumer.len());
+        }
+    }
+
+    /// Time played so far on the simulated clock.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().unwrap().elapsed
+    }
+
+    /// Everything played so far, silence included.
+    pub fn recording(&self) -> Vec<i16> {
+        self.inner.lock().unwrap().recording.clone()
+    }
+
+    /// Outputs that were started and not stopped yet.
+    pub fn running(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .outputs
+            .iter()
+            .filter(|output| !output.stopped.load(Ordering::Relaxed))
+            .count()
+    }
+
+    /// Whether new outputs can be started, to simulate a missing device.
+    pub fn set_available(&self, available: bool) {
+        self.inner.lock().unwrap().available = available;
+    }
+
+    /// Makes every running output report an error, as if the device was
+    /// unplugged.
+    pub fn fail(&self, error: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        for output in inner.outputs.drain(..) {
+            output.stopped.store(true, Ordering::Relaxed);
+            output
+                .failures
+                .send(StreamFailure {
+                    stream_id: output.id,
+                    error: error.to_owned(),
+                })
+                .ok();
+        }
+    }
+
+    /// Writes the recording as a mono 16-bit WAV file.
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let inner = self.inner.lock().unwrap();
+        let mut writer = BufWriter::new(File::create(path)?);
+        wav::write_wav(&mut writer, inner.sample_rate, &inner.recording)?;
+        writer.flush()
+    }
+}
+
+impl AudioSink for MemorySink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        let mut inner = self.inner.lock().unwrap();
+        let found = match selector {
+            DeviceSelector::Named(name) => name == MEMORY_DEVICE,
+            DeviceSelector::Prefix(_) | DeviceSelector::Default => true,
+        };
+        if !inner.available || !found {
+            return Err(SessionError::AudioDeviceNotFound);
+        }
+        stats.set_output_sample_rate(inner.sample_rate);
+        stats.set_buffer_capacity(consumer.capacity());
+
+        let id = next_stream_id();
+        let stopped = Arc::new(AtomicBool::new(false));
+        inner.outputs.push(Output {
+            id,
+            consumer,
+            stats,
+            failures,
+            stopped: stopped.clone(),
+        });
+        Ok(AudioState::new(
+            id,
+            MEMORY_DEVICE.to_owned(),
+            MemoryPlayback { stopped },
+        ))
+    }
+}
@@ -0,0 +1,238 @@
+//! The session statistics for Prometheus and other OpenMetrics scrapers,
+//! with the `metrics` feature.
+//!
+//! A small HTTP server answering `GET /metrics` without a token, so it is
+//! meant for localhost or a trusted network. Counters restart with every
+//! session the user starts, which scrapers treat as a reset.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    control::{read_request, respond, write_response, ConnectionLimit},
+    stats::{SessionStats, StatsSnapshot},
+};
+
+pub const DEFAULT_METRICS_ADDRESS: &str = "127.0.0.1:50553";
+
+const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPEN_METRICS_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// The text format of Prometheus 0.0.4.
+    Prometheus,
+    OpenMetrics,
+}
+
+/// Serves `/metrics` on its own threads until dropped.
+pub struct MetricsServer {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MetricsServer {
+    pub fn start(address: impl ToSocketAddrs, stats: SessionStats) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let accepting = stopped.clone();
+        thread::spawn(move || accept(listener, stats, accepting));
+        Ok(MetricsServer { address, stopped })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // wakes the accepting thread so it sees the flag
+        TcpStream::connect(self.address).ok();
+    }
+}
+
+fn accept(listener: TcpListener, stats: SessionStats, stopped: Arc<AtomicBool>) {
+    let connections = ConnectionLimit::default();
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                let connection = match connections.admit(&stream) {
+                    Some(connection) => connection,
+                    None => continue,
+                };
+                let stats = stats.clone();
+                thread::spawn(move || {
+                    let _connection = connection;
+                    if let Err(err) = serve(stream, &stats) {
+                        tracing::debug!("Metrics connection closed: {}", err);
+                    }
+                });
+            }
+            Err(err) => tracing::warn!("Metrics connection failed: {}", err),
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, stats: &SessionStats) -> io::Result<()> {
+    let request = match read_request(&mut BufReader::new(stream.try_clone()?)) {
+        Ok(request) => request,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            return respond(&mut stream, "400 Bad Request", &err.to_string());
+        }
+        Err(err) => return Err(err),
+    };
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => {
+            let format = match request.accept {
+                Some(accept) if accept.contains("application/openmetrics-text") => {
+                    MetricsFormat::OpenMetrics
+                }
+                _ => MetricsFormat::Prometheus,
+            };
+            let content_type = match format {
+                MetricsFormat::Prometheus => PROMETHEUS_TYPE,
+                MetricsFormat::OpenMetrics => OPEN_METRICS_TYPE,
+            };
+            let body = render(&stats.snapshot(), format);
+            write_response(&mut stream, "200 OK", content_type, &body)
+        }
+        (_, "/metrics") => respond(&mut stream, "405 Method Not Allowed", ""),
+        _ => respond(&mut stream, "404 Not Found", ""),
+    }
+}
+
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// The metrics of `snapshot` in `format`. Latencies only measured with the
+/// handshake are left out without it.
+pub fn render(snapshot: &StatsSnapshot, format: MetricsFormat) -> String {
+    let bitrate = if snapshot.session_seconds > 0.0 {
+        snapshot.bytes_received as f64 * 8.0 / snapshot.session_seconds
+    } else {
+        0.0
+    };
+    let seconds = |ms: f64| ms / 1000.0;
+    let metrics = [
+        (
+            "fastmic_session_seconds",
+            Kind::Gauge,
+            "Time since the session started.",
+            Some(snapshot.session_seconds),
+        ),
+        (
+            "fastmic_received_bytes",
+            Kind::Counter,
+            "Bytes received from the phone.",
+            Some(snapshot.bytes_received as f64),
+        ),
+        (
+            "fastmic_bitrate_bits_per_second",
+            Kind::Gauge,
+            "Average bitrate of the session.",
+            Some(bitrate),
+        ),
+        (
+            "fastmic_measured_sample_rate_hertz",
+            Kind::Gauge,
+            "Sample rate the phone actually sends at.",
+            Some(snapshot.measured_sample_rate),
+        ),
+        (
+            "fastmic_buffer_fill_samples",
+            Kind::Gauge,
+            "Samples waiting for the output device.",
+            Some(snapshot.buffer_fill as f64),
+        ),
+        (
+            "fastmic_buffer_capacity_samples",
+            Kind::Gauge,
+            "Size of the output buffer.",
+            Some(snapshot.buffer_capacity as f64),
+        ),
+        (
+            "fastmic_underruns",
+            Kind::Counter,
+            "Times the output device found the buffer empty.",
+            Some(snapshot.underruns as f64),
+        ),
+        (
+            "fastmic_overruns",
+            Kind::Counter,
+            "Chunks dropped because the buffer was full.",
+            Some(snapshot.overruns as f64),
+        ),
+        (
+            "fastmic_reconnects",
+            Kind::Counter,
+            "Reconnections after the phone dropped out.",
+            Some(snapshot.reconnects as f64),
+        ),
+        (
+            "fastmic_estimated_latency_seconds",
+            Kind::Gauge,
+            "Buffered audio plus the chunk being read.",
+            Some(seconds(snapshot.estimated_latency_ms)),
+        ),
+        (
+            "fastmic_jitter_seconds",
+            Kind::Gauge,
+            "Variation of the time between chunks.",
+            Some(seconds(snapshot.jitter_ms)),
+        ),
+        (
+            "fastmic_round_trip_seconds",
+            Kind::Gauge,
+            "Round trip of the latency handshake.",
+            snapshot.round_trip_ms.map(seconds),
+        ),
+        (
+            "fastmic_end_to_end_latency_seconds",
+            Kind::Gauge,
+            "From the phone's microphone to the output device.",
+            snapshot.end_to_end_latency_ms.map(seconds),
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        // OpenMetrics names the family without the suffix of its sample
+        let (family, sample, kind) = match (kind, format) {
+            (Kind::Counter, MetricsFormat::OpenMetrics) => {
+                (name.to_owned(), format!("{}_total", name), "counter")
+            }
+            (Kind::Counter, MetricsFormat::Prometheus) => {
+                let sample = format!("{}_total", name);
+                (sample.clone(), sample, "counter")
+            }
+            (Kind::Gauge, _) => (name.to_owned(), name.to_owned(), "gauge"),
+        };
+        writeln!(text, "# HELP {} {}", family, help).ok();
+        writeln!(text, "# TYPE {} {}", family, kind).ok();
+        writeln!(text, "{} {}", sample, value).ok();
+    }
+    if format == MetricsFormat::OpenMetrics {
+        text.push_str("# EOF\n");
+    }
+    text
+}
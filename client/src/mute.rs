@@ -0,0 +1,107 @@
+//! Muting the phone without disconnecting it.
+//!
+//! The socket keeps receiving while muted, only the decoded samples are
+//! faded out, so unmuting is instant and doesn't click.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::audio::INPUT_SAMPLE_RATE;
+
+pub const DEFAULT_FADE: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MicState {
+    pub muted: bool,
+    /// Only open while [`MicState::talking`].
+    pub push_to_talk: bool,
+    /// The push-to-talk key is held.
+    pub talking: bool,
+}
+
+impl MicState {
+    pub fn is_open(&self) -> bool {
+        !self.muted && (!self.push_to_talk || self.talking)
+    }
+}
+
+/// Shared by the loop, which sets the state, and the sessions, which apply
+/// it to what they decode.
+#[derive(Clone, Default)]
+pub struct MicGate {
+    muted: Arc<AtomicBool>,
+    push_to_talk: Arc<AtomicBool>,
+    talking: Arc<AtomicBool>,
+    /// Samples a fade in or out takes.
+    fade_samples: Arc<AtomicU32>,
+    /// Of the last sample, between 0 and 1.
+    gain: Arc<Mutex<f32>>,
+}
+
+impl MicGate {
+    pub fn new() -> Self {
+        let gate = MicGate::default();
+        *gate.gain.lock().unwrap() = 1.0;
+        gate.set_fade(DEFAULT_FADE);
+        gate
+    }
+
+    pub fn state(&self) -> MicState {
+        MicState {
+            muted: self.muted.load(Ordering::Relaxed),
+            push_to_talk: self.push_to_talk.load(Ordering::Relaxed),
+            talking: self.talking.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn set_push_to_talk(&self, enabled: bool) {
+        self.push_to_talk.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_talking(&self, talking: bool) {
+        self.talking.store(talking, Ordering::Relaxed);
+    }
+
+    /// Zero cuts instantly.
+    pub fn set_fade(&self, fade: Duration) {
+        let samples = fade.as_secs_f64() * INPUT_SAMPLE_RATE as f64;
+        self.fade_samples.store(samples as u32, Ordering::Relaxed);
+    }
+
+    /// Jumps to the level the state asks for, as a new stream has nothing to
+    /// fade from.
+    pub fn reset(&self) {
+        let open = self.state().is_open();
+        *self.gain.lock().unwrap() = if open { 1.0 } else { 0.0 };
+    }
+
+    /// Fades `samples` towards silence or full level, whichever the state
+    /// asks for.
+    pub fn apply(&self, samples: &mut [i16]) {
+        let target = if self.state().is_open() { 1.0 } else { 0.0 };
+        let mut gain = self.gain.lock().unwrap();
+        if *gain == target && target == 1.0 {
+            return;
+        }
+        let step = 1.0 / self.fade_samples.load(Ordering::Relaxed).max(1) as f32;
+        for sample in samples.iter_mut() {
+            if *gain < target {
+                *gain = (*gain + step).min(target);
+            } else if *gain > target {
+                *gain = (*gain - step).max(target);
+            }
+            *sample = (*sample as f32 * *gain).round() as i16;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Desktop notifications about the connection, for when the window is
+//! hidden or behind the call.
+//!
+//! On Linux they go to the freedesktop notification server over D-Bus.
+
+use std::{collections::HashMap, thread, time::Instant};
+
+use crate::{common::LoopMessage, session::SessionState, settings::NotificationSettings};
+
+/// What a notification is about, each can be turned off on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationKind {
+    /// The phone dropped out and the loop tries to get it back.
+    Reconnecting,
+    Reconnected,
+    /// The loop gave up on a phone that was connected.
+    Failed,
+    AudioDeviceLost,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub summary: String,
+    pub body: String,
+}
+
+impl Notification {
+    /// The notification about `message`, given the state before it.
+    pub fn for_message(previous: &SessionState, message: &LoopMessage) -> Option<Notification> {
+        let (kind, summary, body) = match (previous, message) {
+            (previous, LoopMessage::State(SessionState::Reconnecting { .. }))
+                if in_drop_out(previous) =>
+            {
+                return None
+            }
+            (_, LoopMessage::State(SessionState::Reconnecting { max_attempts, .. })) => (
+                NotificationKind::Reconnecting,
+                "Phone disconnected",
+                match max_attempts {
+                    Some(max_attempts) => format!("Reconnecting, up to {} attempts", max_attempts),
+                    None => "Reconnecting until it is back".to_owned(),
+                },
+            ),
+            (previous, LoopMessage::State(SessionState::Connected { .. }))
+                if in_drop_out(previous) =>
+            {
+                (
+                    NotificationKind::Reconnected,
+                    "Phone reconnected",
+                    "The microphone is back".to_owned(),
+                )
+            }
+            (previous, LoopMessage::State(SessionState::Failed(err)))
+                if in_drop_out(previous) || matches!(previous, SessionState::Connected { .. }) =>
+            {
+                (NotificationKind::Failed, "Phone lost", err.to_string())
+            }
+            (_, LoopMessage::AudioDeviceLost(device)) => (
+                NotificationKind::AudioDeviceLost,
+                "Audio device lost",
+                format!("Reopening {}", device),
+            ),
+            _ => return None,
+        };
+        Some(Notification {
+            kind,
+            summary: summary.to_owned(),
+            body,
+        })
+    }
+}
+
+/// Whether the loop is trying to get a lost phone back, i.e. waiting for the
+/// next attempt or running it.
+fn in_drop_out(state: &SessionState) -> bool {
+    match state {
+        SessionState::Reconnecting { .. } => true,
+        SessionState::Connecting { attempt } => *attempt > 0,
+        _ => false,
+    }
+}
+
+/// Shows notifications the settings allow, at most one of each kind per
+/// [`NotificationSettings::min_interval_secs`].
+pub struct Notifier {
+    show: Box<dyn Fn(&Notification) + Send>,
+    last_shown: HashMap<NotificationKind, Instant>,
+}
+
+impl Notifier {
+    pub fn new(show: impl Fn(&Notification) + Send + 'static) -> Self {
+        Notifier {
+            show: Box::new(show),
+            last_shown: HashMap::new(),
+        }
+    }
+
+    /// Shows them on the desktop.
+    pub fn desktop() -> Self {
+        Self::new(show_on_desktop)
+    }
+
+    /// Returns whether `notification` was shown.
+    pub fn notify(
+        &mut self,
+        notification: &Notification,
+        settings: &NotificationSettings,
+        now: Instant,
+    ) -> bool {
+        let enabled = match notification.kind {
+            NotificationKind::Reconnecting => settings.reconnecting,
+            NotificationKind::Reconnected => settings.reconnected,
+            NotificationKind::Failed => settings.failed,
+            NotificationKind::AudioDeviceLost => settings.audio_device_lost,
+        };
+        let recent = self
+            .last_shown
+            .get(&notification.kind)
+            .is_some_and(|shown| {
+                now.saturating_duration_since(*shown).as_secs() < settings.min_interval_secs
+            });
+        if !enabled || recent {
+            return false;
+        }
+        self.last_shown.insert(notification.kind, now);
+        (self.show)(notification);
+        true
+    }
+}
+
+fn show_on_desktop(notification: &Notification) {
+    let notification = notification.clone();
+    // the notification server can take its time to answer
+    thread::spawn(move || {
+        let shown = notify_rust::Notification::new()
+            .appname("Fast Mic")
+            .summary(&notification.summary)
+            .body(&notification.body)
+            .show();
+        if let Err(err) = shown {
+            tracing::warn!("Cannot show notification: {}", err);
+        }
+    });
+}
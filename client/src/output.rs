@@ -0,0 +1,269 @@
+//! Where the received audio goes: a sound device, or raw PCM for other
+//! programs.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::Sender;
+use ringbuf::Consumer;
+
+use crate::{
+    audio::{
+        default_sink, next_stream_id, write_pcm, AudioSink, AudioState, DeviceSelector, Playback,
+        StreamFailure, INPUT_SAMPLE_RATE,
+    },
+    error::SessionError,
+    stats::SessionStats,
+};
+
+const STDOUT_DEVICE: &str = "stdout";
+const TICK: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputKind {
+    /// The virtual microphone or an output device, see [`default_sink`].
+    #[default]
+    Device,
+    /// Raw PCM on the standard output.
+    Stdout,
+    /// Raw PCM into a named pipe, created if missing.
+    Fifo(PathBuf),
+    /// A JACK client port, connected to the given port if any.
+    Jack { connect_to: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidOutput(String);
+
+impl fmt::Display for InvalidOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown output \"{}\", use device, stdout, fifo:<path> or jack[:<port>]",
+            self.0
+        )
+    }
+}
+
+impl Error for InvalidOutput {}
+
+impl FromStr for OutputKind {
+    type Err = InvalidOutput;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = match input.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (input, None),
+        };
+        match (kind, argument) {
+            ("device", None) => Ok(OutputKind::Device),
+            ("stdout", None) => Ok(OutputKind::Stdout),
+            ("fifo", Some(path)) if !path.is_empty() => Ok(OutputKind::Fifo(path.into())),
+            ("jack", None) => Ok(OutputKind::Jack { connect_to: None }),
+            // JACK port names contain colons themselves
+            ("jack", Some(port)) if !port.is_empty() => Ok(OutputKind::Jack {
+                connect_to: Some(port.to_owned()),
+            }),
+            _ => Err(InvalidOutput(input.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for OutputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputKind::Device => write!(f, "device"),
+            OutputKind::Stdout => write!(f, "stdout"),
+            OutputKind::Fifo(path) => write!(f, "fifo:{}", path.display()),
+            OutputKind::Jack { connect_to: None } => write!(f, "jack"),
+            OutputKind::Jack {
+                connect_to: Some(port),
+            } => write!(f, "jack:{}", port),
+        }
+    }
+}
+
+impl OutputKind {
+    /// Creates the sink. `channels` applies to the raw outputs, which
+    /// repeat the mono signal on each.
+    pub fn sink(&self, channels: u16) -> Result<Box<dyn AudioSink>, SessionError> {
+        match self {
+            OutputKind::Device => Ok(default_sink()),
+            OutputKind::Stdout => Ok(Box::new(PcmSink::new(PcmTarget::Stdout, channels))),
+            OutputKind::Fifo(path) => Ok(Box::new(PcmSink::new(
+                PcmTarget::Fifo(path.clone()),
+                channels,
+            ))),
+            #[cfg(feature = "jack")]
+            OutputKind::Jack { connect_to } => Ok(Box::new(crate::jack_sink::JackSink::new(
+                connect_to.clone(),
+            ))),
+            #[cfg(not(feature = "jack"))]
+            OutputKind::Jack { .. } => Err(SessionError::audio(
+                "opening JACK client",
+                "This build has no JACK support, rebuild with --features jack",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PcmTarget {
+    Stdout,
+    Fifo(PathBuf),
+}
+
+/// Writes 16-bit little-endian PCM at the pace of a sound card, with silence
+/// when the phone falls behind, so readers can rely on the timing.
+#[derive(Debug, Clone)]
+pub struct PcmSink {
+    target: PcmTarget,
+    channels: usize,
+}
+
+impl PcmSink {
+    pub fn new(target: PcmTarget, channels: u16) -> Self {
+        PcmSink {
+            target,
+            channels: channels.max(1) as usize,
+        }
+    }
+
+    fn device_name(&self) -> String {
+        match &self.target {
+            PcmTarget::Stdout => STDOUT_DEVICE.to_owned(),
+            PcmTarget::Fifo(path) => path.display().to_string(),
+        }
+    }
+}
+
+impl AudioSink for PcmSink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        let device_name = self.device_name();
+        if matches!(selector, DeviceSelector::Named(name) if *name != device_name) {
+            return Err(SessionError::AudioDeviceNotFound);
+        }
+        if let PcmTarget::Fifo(path) = &self.target {
+            create_fifo(path)?;
+        }
+        stats.set_output_sample_rate(INPUT_SAMPLE_RATE);
+        stats.set_buffer_capacity(consumer.capacity());
+
+        let id = next_stream_id();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let output = PcmOutput {
+            id,
+            target: self.target.clone(),
+            channels: self.channels,
+            consumer,
+            stats,
+            failures,
+            stopped: stopped.clone(),
+        };
+        thread::spawn(move || output.run());
+        Ok(AudioState::new(id, device_name, PcmPlayback { stopped }))
+    }
+}
+
+struct PcmPlayback {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Playback for PcmPlayback {
+    fn pause(&self) -> Result<(), SessionError> {
+        self.stopped.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for PcmPlayback {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+struct PcmOutput {
+    id: u64,
+    target: PcmTarget,
+    channels: usize,
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    failures: Sender<StreamFailure>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl PcmOutput {
+    fn run(mut self) {
+        if let Err(err) = self.write() {
+            if !self.stopped.load(Ordering::Relaxed) {
+                tracing::error!("Output stream failed: {}", err);
+                self.failures
+                    .send(StreamFailure {
+                        stream_id: self.id,
+                        error: err.to_string(),
+                    })
+                    .ok();
+            }
+        }
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        let mut writer: Box<dyn Write> = match &self.target {
+            PcmTarget::Stdout => Box::new(io::stdout()),
+            // blocks until a reader opens the pipe
+            PcmTarget::Fifo(path) => Box::new(OpenOptions::new().write(true).open(path)?),
+        };
+        let started = Instant::now();
+        let mut frames_written = 0u64;
+        let mut bytes = Vec::new();
+        while !self.stopped.load(Ordering::Relaxed) {
+            thread::sleep(TICK);
+            let due = (started.elapsed().as_secs_f64() * INPUT_SAMPLE_RATE as f64) as u64;
+            let frames = (due - frames_written) as usize;
+            frames_written = due;
+
+            bytes.clear();
+            bytes.resize(frames * self.channels * 2, 0);
+            write_pcm(&mut bytes, self.channels, &mut self.consumer, &self.stats);
+            writer.write_all(&bytes)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn create_fifo(path: &Path) -> Result<(), SessionError> {
+    if path.exists() {
+        return Ok(());
+    }
+    let status = Command::new("mkfifo")
+        .arg(path)
+        .status()
+        .map_err(|err| SessionError::audio("creating named pipe", err))?;
+    if !status.success() {
+        return Err(SessionError::audio(
+            "creating named pipe",
+            format!("mkfifo exited with {}", status),
+        ));
+    }
+    Ok(())
+}
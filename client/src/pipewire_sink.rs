@@ -0,0 +1,224 @@
+//! A virtual microphone on PipeWire, which PulseAudio applications see too
+//! through `pipewire-pulse`.
+//!
+//! The output stream is the microphone itself: its node is announced as an
+//! `Audio/Source`, so other applications record the samples straight from
+//! the buffers it fills, with no sink or module in between. The node belongs
+//! to the client's connection to the server, which removes it whenever the
+//! client goes away, crashes included, and never touches the microphone of
+//! another running client.
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use crossbeam_channel::Sender;
+use pipewire as pw;
+use pw::{properties::properties, spa};
+use ringbuf::Consumer;
+
+use crate::{
+    audio::{
+        next_stream_id, write_pcm, AudioSink, AudioState, DeviceSelector, Playback, StreamFailure,
+        INPUT_SAMPLE_RATE,
+    },
+    error::SessionError,
+    pulse::VIRTUAL_DEVICE,
+    stats::SessionStats,
+};
+
+const NODE_NAME: &str = "fast_mic";
+/// Frames asked for at once, 10ms.
+const QUANTUM: u32 = INPUT_SAMPLE_RATE / 100;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PipeWireSink;
+
+impl PipeWireSink {
+    /// Fails if there is no PipeWire server to connect to.
+    pub fn connect() -> Result<Self, SessionError> {
+        let error = |err| SessionError::audio("connecting to PipeWire", err);
+        let main_loop = pw::main_loop::MainLoopRc::new(None).map_err(error)?;
+        let context = pw::context::ContextRc::new(&main_loop, None).map_err(error)?;
+        context.connect_rc(None).map_err(error)?;
+        Ok(PipeWireSink)
+    }
+}
+
+impl AudioSink for PipeWireSink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        if matches!(selector, DeviceSelector::Named(name) if name != VIRTUAL_DEVICE) {
+            return Err(SessionError::AudioDeviceNotFound);
+        }
+        stats.set_output_sample_rate(INPUT_SAMPLE_RATE);
+        stats.set_buffer_capacity(consumer.capacity());
+        stats.set_device_latency(Duration::from_secs_f64(
+            QUANTUM as f64 / INPUT_SAMPLE_RATE as f64,
+        ));
+
+        let id = next_stream_id();
+        let (stop, stopped) = pw::channel::channel();
+        let (started_sender, started) = mpsc::channel();
+        // the loop and the stream can't leave the thread that made them
+        thread::spawn(move || {
+            let output = Output {
+                id,
+                consumer,
+                stats,
+                failures,
+            };
+            if let Err(err) = output.run(stopped, &started_sender) {
+                started_sender
+                    .send(Err(SessionError::audio("opening PipeWire stream", err)))
+                    .ok();
+            }
+        });
+        match started.recv() {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(SessionError::audio(
+                    "opening PipeWire stream",
+                    "The stream thread exited",
+                ))
+            }
+        }
+        Ok(AudioState::new(
+            id,
+            VIRTUAL_DEVICE.to_owned(),
+            PipeWirePlayback { stop },
+        ))
+    }
+}
+
+struct PipeWirePlayback {
+    stop: pw::channel::Sender<()>,
+}
+
+impl Playback for PipeWirePlayback {
+    fn pause(&self) -> Result<(), SessionError> {
+        self.stop.send(()).ok();
+        Ok(())
+    }
+}
+
+impl Drop for PipeWirePlayback {
+    fn drop(&mut self) {
+        self.stop.send(()).ok();
+    }
+}
+
+struct Output {
+    id: u64,
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    failures: Sender<StreamFailure>,
+}
+
+impl Output {
+    /// Runs the loop of the stream until `stopped` receives, after telling
+    /// `started` the stream is up.
+    fn run(
+        self,
+        stopped: pw::channel::Receiver<()>,
+        started: &mpsc::Sender<Result<(), SessionError>>,
+    ) -> Result<(), pw::Error> {
+        let Output {
+            id,
+            consumer,
+            stats,
+            failures,
+        } = self;
+        let main_loop = pw::main_loop::MainLoopRc::new(None)?;
+        let context = pw::context::ContextRc::new(&main_loop, None)?;
+        let core = context.connect_rc(None)?;
+        let stream = pw::stream::StreamBox::new(
+            &core,
+            VIRTUAL_DEVICE,
+            properties! {
+                *pw::keys::MEDIA_TYPE => "Audio",
+                *pw::keys::MEDIA_CLASS => "Audio/Source",
+                *pw::keys::MEDIA_ROLE => "Communication",
+                *pw::keys::NODE_NAME => NODE_NAME,
+                *pw::keys::NODE_DESCRIPTION => VIRTUAL_DEVICE,
+                *pw::keys::NODE_LATENCY => format!("{}/{}", QUANTUM, INPUT_SAMPLE_RATE),
+            },
+        )?;
+
+        let _listener = stream
+            .add_local_listener_with_user_data(consumer)
+            .state_changed(move |_, _, _, state| {
+                if let pw::stream::StreamState::Error(error) = state {
+                    tracing::error!("Virtual microphone stream failed: {}", error);
+                    failures
+                        .send(StreamFailure {
+                            stream_id: id,
+                            error,
+                        })
+                        .ok();
+                }
+            })
+            .process(move |stream, consumer| {
+                let mut buffer = match stream.dequeue_buffer() {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
+                let frames = match buffer.requested() {
+                    0 => QUANTUM as usize,
+                    requested => requested as usize,
+                };
+                let data = &mut buffer.datas_mut()[0];
+                let written = data.data().map_or(0, |bytes| {
+                    let length = (frames * 2).min(bytes.len());
+                    write_pcm(&mut bytes[..length], 1, consumer, &stats)
+                });
+                let chunk = data.chunk_mut();
+                *chunk.offset_mut() = 0;
+                *chunk.stride_mut() = 2;
+                *chunk.size_mut() = (written * 2) as u32;
+            })
+            .register()?;
+
+        let format = format();
+        let mut params = [spa::pod::Pod::from_bytes(&format).expect("The format is a pod")];
+        // not linked to the speakers, applications link to it when they record
+        stream.connect(
+            spa::utils::Direction::Output,
+            None,
+            pw::stream::StreamFlags::MAP_BUFFERS | pw::stream::StreamFlags::RT_PROCESS,
+            &mut params,
+        )?;
+
+        let quit = main_loop.clone();
+        let _stopped = stopped.attach(main_loop.loop_(), move |()| quit.quit());
+        started.send(Ok(())).ok();
+        main_loop.run();
+        Ok(())
+    }
+}
+
+/// Mono 16-bit samples at the phone's rate, PipeWire converts them for the
+/// applications that record.
+fn format() -> Vec<u8> {
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::S16LE);
+    audio_info.set_rate(INPUT_SAMPLE_RATE);
+    audio_info.set_channels(1);
+    let mut position = [0; spa::param::audio::MAX_CHANNELS];
+    position[0] = spa::sys::SPA_AUDIO_CHANNEL_MONO;
+    audio_info.set_position(position);
+    spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(spa::pod::Object {
+            type_: spa::sys::SPA_TYPE_OBJECT_Format,
+            id: spa::sys::SPA_PARAM_EnumFormat,
+            properties: audio_info.into(),
+        }),
+    )
+    .expect("Cannot serialize the audio format")
+    .0
+    .into_inner()
+}
@@ -0,0 +1,122 @@
+//! Framing used by the latency measurement mode.
+//!
+//! By default the phone streams raw little-endian `i16` samples. When the
+//! client opens the connection with [`HELLO`], a phone that understands it
+//! answers with [`MAGIC`] and from then on wraps everything in frames:
+//!
+//! - audio: `FRAME_AUDIO`, capture time of the first sample (`i64` µs since
+//!   the epoch, phone clock), payload length (`u32`), payload
+//! - pong: `FRAME_PONG`, the echoed ping time (`i64`), the phone time when the
+//!   ping was received (`i64`)
+//!
+//! The client sends pings as `FRAME_PING` followed by its own time (`i64`).
+//! All integers are little-endian.
+
+use std::{
+    io::{self, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const MAGIC: [u8; 4] = *b"FMLT";
+pub const VERSION: u8 = 1;
+pub const HELLO: [u8; 5] = [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION];
+
+pub const FRAME_AUDIO: u8 = 1;
+pub const FRAME_PONG: u8 = 2;
+pub const FRAME_PING: u8 = 1;
+
+pub const MAX_PAYLOAD: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Audio { captured_at: i64, payload: Vec<u8> },
+    Pong { sent_at: i64, received_at: i64 },
+}
+
+/// Microseconds since the Unix epoch on the local clock.
+pub fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as i64)
+        .unwrap_or_default()
+}
+
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Frame> {
+    match read_u8(reader)? {
+        FRAME_AUDIO => {
+            let captured_at = read_i64(reader)?;
+            let mut length = [0u8; 4];
+            reader.read_exact(&mut length)?;
+            let length = u32::from_le_bytes(length) as usize;
+            if length > MAX_PAYLOAD || !length.is_multiple_of(2) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid audio frame length {}", length),
+                ));
+            }
+            let mut payload = vec![0u8; length];
+            reader.read_exact(&mut payload)?;
+            Ok(Frame::Audio {
+                captured_at,
+                payload,
+            })
+        }
+        FRAME_PONG => Ok(Frame::Pong {
+            sent_at: read_i64(reader)?,
+            received_at: read_i64(reader)?,
+        }),
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown frame type {}", kind),
+        )),
+    }
+}
+
+pub fn write_frame(writer: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    match frame {
+        Frame::Audio {
+            captured_at,
+            payload,
+        } => {
+            writer.write_all(&[FRAME_AUDIO])?;
+            writer.write_all(&captured_at.to_le_bytes())?;
+            writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+            writer.write_all(payload)
+        }
+        Frame::Pong {
+            sent_at,
+            received_at,
+        } => {
+            writer.write_all(&[FRAME_PONG])?;
+            writer.write_all(&sent_at.to_le_bytes())?;
+            writer.write_all(&received_at.to_le_bytes())
+        }
+    }
+}
+
+pub fn write_ping(writer: &mut impl Write, sent_at: i64) -> io::Result<()> {
+    writer.write_all(&[FRAME_PING])?;
+    writer.write_all(&sent_at.to_le_bytes())
+}
+
+pub fn read_ping(reader: &mut impl Read) -> io::Result<i64> {
+    match read_u8(reader)? {
+        FRAME_PING => read_i64(reader),
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown client message {}", kind),
+        )),
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_i64(reader: &mut impl Read) -> io::Result<i64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
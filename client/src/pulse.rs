@@ -0,0 +1,227 @@
+//! A virtual microphone on PulseAudio, or PipeWire through `pipewire-pulse`,
+//! for when the client is built without the `pipewire` feature or the server
+//! isn't PipeWire.
+//!
+//! Talks to the sound server with `pactl` and `pacat`, which every desktop
+//! running either ships with, instead of linking the client libraries: a null
+//! sink receives the samples and a source remapped from its monitor shows up
+//! in other applications as the "Fast Mic" microphone. Both are removed when
+//! the [`PulseSink`] is dropped. Only the modules loaded by this process are
+//! ever unloaded, so a second client keeps its microphone; the server keeps
+//! them if the client crashes, until it restarts.
+
+use std::{
+    io::{self, Write},
+    process::{self, Child, ChildStdin, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+use ringbuf::Consumer;
+
+use crate::{
+    audio::{
+        next_stream_id, write_pcm, AudioSink, AudioState, DeviceSelector, Playback, StreamFailure,
+        INPUT_SAMPLE_RATE,
+    },
+    error::SessionError,
+    stats::SessionStats,
+};
+
+/// Name of the microphone as shown to other applications.
+pub const VIRTUAL_DEVICE: &str = "Fast Mic";
+
+/// Followed by the process id, so that every client has its own.
+const SINK_PREFIX: &str = "fast_mic_sink";
+const SOURCE_PREFIX: &str = "fast_mic";
+const LATENCY: Duration = Duration::from_millis(40);
+// 10ms
+const CHUNK_SAMPLES: usize = INPUT_SAMPLE_RATE as usize / 100;
+
+pub struct PulseSink {
+    sink_name: String,
+    /// Loaded by this process, in order.
+    modules: Vec<u32>,
+}
+
+impl PulseSink {
+    /// Creates the virtual microphone. Fails if the tools or the sound
+    /// server aren't there.
+    pub fn create() -> Result<Self, SessionError> {
+        pactl(&["--version"])?;
+        run(Command::new("pacat").arg("--version"))?;
+
+        let id = process::id();
+        let mut sink = PulseSink {
+            sink_name: format!("{}_{}", SINK_PREFIX, id),
+            modules: Vec::new(),
+        };
+        let sink_name = sink.sink_name.clone();
+        sink.load_module(&[
+            "module-null-sink",
+            &format!("sink_name={}", sink_name),
+            "sink_properties=\"device.description='Fast Mic Output'\"",
+        ])?;
+        sink.load_module(&[
+            "module-remap-source",
+            &format!("master={}.monitor", sink_name),
+            &format!("source_name={}_{}", SOURCE_PREFIX, id),
+            &format!(
+                "source_properties=\"device.description='{}'\"",
+                VIRTUAL_DEVICE
+            ),
+        ])?;
+        Ok(sink)
+    }
+
+    fn load_module(&mut self, arguments: &[&str]) -> Result<(), SessionError> {
+        let output = pactl(&[&["load-module"], arguments].concat())?;
+        let module = output.trim().parse().map_err(|_| {
+            SessionError::audio(
+                "creating virtual microphone",
+                format!("Unexpected module index \"{}\"", output.trim()),
+            )
+        })?;
+        self.modules.push(module);
+        Ok(())
+    }
+}
+
+impl Drop for PulseSink {
+    fn drop(&mut self) {
+        // the source depends on the sink, remove it first
+        for module in self.modules.iter().rev() {
+            if let Err(err) = pactl(&["unload-module", &module.to_string()]) {
+                tracing::warn!("Cannot remove virtual microphone: {}", err);
+            }
+        }
+    }
+}
+
+impl AudioSink for PulseSink {
+    fn start(
+        &self,
+        consumer: Consumer<i16>,
+        stats: SessionStats,
+        selector: &DeviceSelector,
+        failures: Sender<StreamFailure>,
+    ) -> Result<AudioState, SessionError> {
+        if matches!(selector, DeviceSelector::Named(name) if name != VIRTUAL_DEVICE) {
+            return Err(SessionError::AudioDeviceNotFound);
+        }
+        let mut child = Command::new("pacat")
+            .args([
+                "--playback",
+                "--raw",
+                &format!("--device={}", self.sink_name),
+                "--format=s16le",
+                &format!("--rate={}", INPUT_SAMPLE_RATE),
+                "--channels=1",
+                &format!("--latency-msec={}", LATENCY.as_millis()),
+                "--client-name=Fast Mic",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| SessionError::audio("opening output stream", err))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        stats.set_output_sample_rate(INPUT_SAMPLE_RATE);
+        stats.set_buffer_capacity(consumer.capacity());
+        stats.set_device_latency(LATENCY);
+
+        let id = next_stream_id();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let output = Output {
+            id,
+            child,
+            stdin,
+            consumer,
+            stats,
+            failures,
+            stopped: stopped.clone(),
+        };
+        thread::spawn(move || output.run());
+        Ok(AudioState::new(
+            id,
+            VIRTUAL_DEVICE.to_owned(),
+            PulsePlayback { stopped },
+        ))
+    }
+}
+
+struct PulsePlayback {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Playback for PulsePlayback {
+    fn pause(&self) -> Result<(), SessionError> {
+        self.stopped.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for PulsePlayback {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Feeds one `pacat`, which blocks the writes once its buffer is full and so
+/// sets the pace like a device callback would.
+struct Output {
+    id: u64,
+    child: Child,
+    stdin: ChildStdin,
+    consumer: Consumer<i16>,
+    stats: SessionStats,
+    failures: Sender<StreamFailure>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Output {
+    fn run(mut self) {
+        let mut chunk = vec![0; CHUNK_SAMPLES * 2];
+        while !self.stopped.load(Ordering::Relaxed) {
+            write_pcm(&mut chunk, 1, &mut self.consumer, &self.stats);
+            if let Err(err) = self.stdin.write_all(&chunk) {
+                if !self.stopped.load(Ordering::Relaxed) {
+                    tracing::error!("Virtual microphone stream failed: {}", err);
+                    self.failures
+                        .send(StreamFailure {
+                            stream_id: self.id,
+                            error: err.to_string(),
+                        })
+                        .ok();
+                }
+                break;
+            }
+        }
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+fn pactl(arguments: &[&str]) -> Result<String, SessionError> {
+    run(Command::new("pactl").args(arguments))
+}
+
+/// Runs a tool to completion and returns its output.
+fn run(command: &mut Command) -> Result<String, SessionError> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| SessionError::audio("creating virtual microphone", err))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(SessionError::audio(
+            "creating virtual microphone",
+            io::Error::other(message),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
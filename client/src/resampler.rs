@@ -0,0 +1,46 @@
+//! Sample rate conversion for outputs that can't play the phone's rate.
+
+/// Converts a stream of mono samples by linear interpolation, one chunk at a
+/// time without gaps between chunks.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    /// Input samples per output sample.
+    step: f64,
+    /// Of the next output sample, counted from `last`.
+    position: f64,
+    /// The last input sample of the previous chunk.
+    last: i16,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler {
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            position: 0.0,
+            last: 0,
+        }
+    }
+
+    /// Appends the converted `input` to `output`.
+    pub fn process(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        if self.step == 1.0 {
+            output.extend_from_slice(input);
+            return;
+        }
+        let sample = |index: usize| match index {
+            0 => self.last,
+            _ => input[index - 1],
+        };
+        while self.position < input.len() as f64 {
+            let index = self.position as usize;
+            let fraction = self.position - index as f64;
+            let (from, to) = (sample(index) as f64, sample(index + 1) as f64);
+            output.push((from + (to - from) * fraction).round() as i16);
+            self.position += self.step;
+        }
+        self.position -= input.len() as f64;
+        if let Some(&last) = input.last() {
+            self.last = last;
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! The lifecycle of a streaming session.
+//!
+//! The event loop feeds every user action and everything it observes on the
+//! network into a [`SessionMachine`] and publishes the resulting
+//! [`SessionState`], so the GUI shows exactly the state the loop acts on.
+
+use std::{fmt, sync::Arc, time::Instant};
+
+use crate::{error::SessionError, reconnect::ReconnectPolicy};
+
+#[derive(Debug, Clone, Default)]
+pub enum SessionState {
+    #[default]
+    Idle,
+    /// A connection attempt is running, `attempt` is 0 for the one the user
+    /// asked for.
+    Connecting { attempt: u32 },
+    /// Waiting for the phone to connect to `address`.
+    Listening { address: String },
+    /// Streaming. `listen_address` is set when the phone connected to us, in
+    /// which case losing it means waiting for it again.
+    Connected { listen_address: Option<String> },
+    Reconnecting {
+        attempt: u32,
+        max_attempts: Option<u32>,
+        retry_at: Instant,
+    },
+    Failed(Arc<SessionError>),
+}
+
+#[derive(Debug)]
+pub enum SessionEvent {
+    /// The user asked to connect to the phone.
+    Connect,
+    /// The user asked to wait for the phone, which is now possible on
+    /// `address`.
+    Listen { address: String },
+    /// The user asked to stop.
+    Disconnect,
+    /// A connection attempt succeeded or a phone connected to us.
+    Connected,
+    ConnectFailed(SessionError),
+    ConnectionLost(SessionError),
+    /// The delay before the next reconnection attempt has passed.
+    RetryDue,
+    /// Something the session can't recover from, e.g. the output device went
+    /// away for good or listening wasn't possible.
+    Failed(SessionError),
+}
+
+/// An event that has no meaning in the current state, e.g. a second connect.
+#[derive(Debug)]
+pub struct InvalidTransition {
+    pub state: &'static str,
+    pub event: SessionEvent,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot handle {:?} while {}", self.event, self.state)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+impl SessionState {
+    /// Whether a new session can be started, i.e. nothing is running.
+    pub fn can_start(&self) -> bool {
+        match self {
+            SessionState::Idle | SessionState::Failed(_) => true,
+            SessionState::Connecting { .. }
+            | SessionState::Listening { .. }
+            | SessionState::Connected { .. }
+            | SessionState::Reconnecting { .. } => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionState::Idle => "idle",
+            SessionState::Connecting { .. } => "connecting",
+            SessionState::Listening { .. } => "listening",
+            SessionState::Connected { .. } => "connected",
+            SessionState::Reconnecting { .. } => "reconnecting",
+            SessionState::Failed(_) => "failed",
+        }
+    }
+
+    /// The state `event` leads to, or the event back if it's not valid here.
+    fn next(
+        &self,
+        event: SessionEvent,
+        policy: &ReconnectPolicy,
+        now: Instant,
+    ) -> Result<SessionState, SessionEvent> {
+        let next = match (self, event) {
+            (state, SessionEvent::Connect) if state.can_start() => {
+                SessionState::Connecting { attempt: 0 }
+            }
+            (state, SessionEvent::Listen { address }) if state.can_start() => {
+                SessionState::Listening { address }
+            }
+            (state, SessionEvent::Disconnect) if !state.can_start() => SessionState::Idle,
+            (SessionState::Connecting { .. }, SessionEvent::Connected) => {
+                SessionState::Connected {
+                    listen_address: None,
+                }
+            }
+            (SessionState::Listening { address }, SessionEvent::Connected) => {
+                SessionState::Connected {
+                    listen_address: Some(address.clone()),
+                }
+            }
+            (SessionState::Connecting { attempt: 0 }, SessionEvent::ConnectFailed(err)) => {
+                SessionState::Failed(Arc::new(err))
+            }
+            (SessionState::Connecting { attempt }, SessionEvent::ConnectFailed(err)) => {
+                reconnect(attempt + 1, err, policy, now)
+            }
+            // a phone that connected and vanished right away
+            (SessionState::Listening { address }, SessionEvent::ConnectFailed(_)) => {
+                SessionState::Listening {
+                    address: address.clone(),
+                }
+            }
+            (
+                SessionState::Connected {
+                    listen_address: None,
+                },
+                SessionEvent::ConnectionLost(err),
+            ) => reconnect(1, err, policy, now),
+            (
+                SessionState::Connected {
+                    listen_address: Some(address),
+                },
+                SessionEvent::ConnectionLost(_),
+            ) => SessionState::Listening {
+                address: address.clone(),
+            },
+            (SessionState::Reconnecting { attempt, .. }, SessionEvent::RetryDue) => {
+                SessionState::Connecting { attempt: *attempt }
+            }
+            (_, SessionEvent::Failed(err)) => SessionState::Failed(Arc::new(err)),
+            (_, event) => return Err(event),
+        };
+        Ok(next)
+    }
+}
+
+/// Waits for the next attempt, or gives up if the policy doesn't allow it.
+fn reconnect(
+    attempt: u32,
+    last_error: SessionError,
+    policy: &ReconnectPolicy,
+    now: Instant,
+) -> SessionState {
+    if !policy.allows(attempt) {
+        return SessionState::Failed(Arc::new(SessionError::ReconnectFailed {
+            attempts: attempt - 1,
+            last: Box::new(last_error),
+        }));
+    }
+    SessionState::Reconnecting {
+        attempt,
+        max_attempts: policy.max_attempts,
+        retry_at: now + policy.delay(attempt),
+    }
+}
+
+pub struct SessionMachine {
+    state: SessionState,
+    policy: ReconnectPolicy,
+}
+
+impl SessionMachine {
+    pub fn new(policy: ReconnectPolicy) -> Self {
+        SessionMachine {
+            state: SessionState::Idle,
+            policy,
+        }
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    pub fn policy(&self) -> &ReconnectPolicy {
+        &self.policy
+    }
+
+    /// Applies from the next attempt on.
+    pub fn set_policy(&mut self, policy: ReconnectPolicy) {
+        self.policy = policy;
+    }
+
+    /// Moves to the state `event` leads to. Invalid events leave the state
+    /// untouched.
+    pub fn handle(&mut self, event: SessionEvent, now: Instant) -> Result<(), InvalidTransition> {
+        match self.state.next(event, &self.policy, now) {
+            Ok(state) => {
+                self.state = state;
+                Ok(())
+            }
+            Err(event) => Err(InvalidTransition {
+                state: self.state.name(),
+                event,
+            }),
+        }
+    }
+}
@@ -0,0 +1,596 @@
+//! The settings file, with named profiles like "Desk" or "Podcast".
+//!
+//! Stored as TOML in the configuration directory of the user. Whenever the
+//! layout changes, [`VERSION`] goes up and a step is added to
+//! [`MIGRATIONS`], so files written by older versions keep loading. Version
+//! 0 is the address eframe stored before the file existed.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs,
+    io::{self, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use toml::{value::Table, Value};
+
+use crate::{
+    audio::{DeviceSelector, CABLE_PREFIX},
+    control::DEFAULT_CONTROL_ADDRESS,
+    event_loop::SessionConfig,
+    logging::LogLevel,
+    mute::DEFAULT_FADE,
+    reconnect::ReconnectPolicy,
+};
+
+pub const VERSION: u32 = 1;
+pub const DEFAULT_PROFILE: &str = "Default";
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:50551";
+/// Older connections are forgotten.
+pub const MAX_RECENTS: usize = 10;
+/// What the read timeout can be set to, a hand edited file included.
+pub const READ_TIMEOUT_MS: RangeInclusive<u64> = 500..=60_000;
+
+type Migration = fn(Table) -> Table;
+
+/// `MIGRATIONS[n]` turns version n into version n + 1.
+const MIGRATIONS: [Migration; VERSION as usize] = [from_storage];
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// Written by a newer version of the client, which may have changed the
+    /// meaning of the fields.
+    NewerVersion(u32),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(err) => write!(f, "Cannot access the settings: {}", err),
+            SettingsError::Parse(err) => write!(f, "Invalid settings: {}", err),
+            SettingsError::NewerVersion(version) => write!(
+                f,
+                "The settings are from a newer version ({}) of Fast Mic",
+                version
+            ),
+            SettingsError::UnknownProfile(name) => write!(f, "No profile \"{}\"", name),
+        }
+    }
+}
+
+impl Error for SettingsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SettingsError::Io(err) => Some(err),
+            SettingsError::Parse(err) => Some(err),
+            SettingsError::NewerVersion(_) | SettingsError::UnknownProfile(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for SettingsError {
+    fn from(err: io::Error) -> Self {
+        SettingsError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(err: toml::de::Error) -> Self {
+        SettingsError::Parse(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    /// The name of the active profile.
+    pub profile: String,
+    /// Phones connected to, the latest first.
+    pub recents: Vec<RecentConnection>,
+    pub favorites: Vec<Favorite>,
+    pub startup: StartupSettings,
+    pub notifications: NotificationSettings,
+    pub logging: LoggingSettings,
+    /// Shared by every profile, so scripts keep working after switching.
+    pub control: ControlSettings,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentConnection {
+    /// As the user gave it, to connect again.
+    pub address: String,
+    /// The address of the phone it led to. The phone doesn't announce a
+    /// name, this is the closest there is.
+    pub device: String,
+    /// Seconds since the Unix epoch.
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Favorite {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupSettings {
+    /// Shows an icon in the system tray, see [`crate::tray`].
+    pub tray: bool,
+    /// Only shows the tray icon until it is asked for the window.
+    pub minimized: bool,
+    /// To the last phone, or waits for it in listen mode.
+    pub auto_connect: bool,
+}
+
+/// Which desktop notifications to show, see [`crate::notifications`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub reconnecting: bool,
+    pub reconnected: bool,
+    pub failed: bool,
+    pub audio_device_lost: bool,
+    /// Between two notifications of the same kind, so a flaky network
+    /// doesn't flood the desktop.
+    pub min_interval_secs: u64,
+}
+
+/// See [`crate::logging`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingSettings {
+    pub level: LogLevel,
+    /// Writes a log file a day, read at startup.
+    pub files: bool,
+    /// Days of log files kept.
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    pub enabled: bool,
+    pub address: String,
+    /// Generated on the first start.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub connection: ConnectionSettings,
+    pub device: DeviceSettings,
+    pub latency: LatencySettings,
+    pub dsp: DspSettings,
+    pub ui: UiSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionSettings {
+    pub address: String,
+    /// Waits for the phone on `listen_address` instead of connecting to it.
+    pub listen_mode: bool,
+    pub listen_address: String,
+    pub read_timeout_ms: u64,
+    pub reconnect_attempts: u32,
+    /// Ignores `reconnect_attempts`.
+    pub reconnect_forever: bool,
+    pub reconnect_delay_ms: u64,
+    pub reconnect_max_delay_ms: u64,
+    pub capture: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    /// See [`crate::output::OutputKind`], applies after a restart.
+    pub output: String,
+    pub channels: u16,
+    /// The first output device starting with it is used, the default device
+    /// when empty.
+    pub device_prefix: String,
+    pub monitor: bool,
+    /// Empty for the default device.
+    pub monitor_device: String,
+}
+
+/// Settings given on the command line for this run only. They are applied on
+/// top of the loaded settings where the outputs and servers are started, and
+/// never saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overrides {
+    pub output: Option<String>,
+    pub channels: Option<u16>,
+    /// Serves the control API on this address.
+    pub control_address: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencySettings {
+    /// Of the buffer between the network and the output, in samples.
+    pub buffer_capacity: usize,
+    pub measure_latency: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspSettings {
+    pub push_to_talk: bool,
+    /// Of muting and unmuting.
+    pub fade_ms: u64,
+    /// Of the monitor only, 1.0 leaves the signal unchanged.
+    pub monitor_volume: f32,
+    pub monitor_muted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiSettings {
+    pub dark_mode: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: VERSION,
+            profile: DEFAULT_PROFILE.to_owned(),
+            recents: Vec::new(),
+            favorites: Vec::new(),
+            startup: StartupSettings::default(),
+            notifications: NotificationSettings::default(),
+            logging: LoggingSettings::default(),
+            control: ControlSettings::default(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_owned(), Profile::default())]),
+        }
+    }
+}
+
+impl Default for StartupSettings {
+    fn default() -> Self {
+        StartupSettings {
+            tray: true,
+            minimized: false,
+            auto_connect: false,
+        }
+    }
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            reconnecting: true,
+            reconnected: true,
+            failed: true,
+            audio_device_lost: true,
+            min_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        LoggingSettings {
+            level: LogLevel::default(),
+            files: true,
+            max_files: 7,
+        }
+    }
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        ControlSettings {
+            enabled: false,
+            address: DEFAULT_CONTROL_ADDRESS.to_owned(),
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        let session = SessionConfig::default();
+        let reconnect = session.reconnect_policy;
+        ConnectionSettings {
+            address: String::new(),
+            listen_mode: false,
+            listen_address: DEFAULT_LISTEN_ADDRESS.to_owned(),
+            read_timeout_ms: session.read_timeout.as_millis() as u64,
+            reconnect_attempts: reconnect.max_attempts.unwrap_or_default(),
+            reconnect_forever: reconnect.max_attempts.is_none(),
+            reconnect_delay_ms: reconnect.initial_delay.as_millis() as u64,
+            reconnect_max_delay_ms: reconnect.max_delay.as_millis() as u64,
+            capture: false,
+        }
+    }
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        DeviceSettings {
+            output: "device".to_owned(),
+            channels: 1,
+            device_prefix: CABLE_PREFIX.to_owned(),
+            monitor: false,
+            monitor_device: String::new(),
+        }
+    }
+}
+
+impl Default for LatencySettings {
+    fn default() -> Self {
+        LatencySettings {
+            buffer_capacity: SessionConfig::default().buffer_capacity,
+            measure_latency: false,
+        }
+    }
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        DspSettings {
+            push_to_talk: false,
+            fade_ms: DEFAULT_FADE.as_millis() as u64,
+            monitor_volume: 1.0,
+            monitor_muted: false,
+        }
+    }
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        UiSettings { dark_mode: true }
+    }
+}
+
+impl Overrides {
+    pub fn device(&self, device: &DeviceSettings) -> DeviceSettings {
+        DeviceSettings {
+            output: self.output.clone().unwrap_or_else(|| device.output.clone()),
+            channels: self.channels.unwrap_or(device.channels),
+            ..device.clone()
+        }
+    }
+
+    pub fn control(&self, control: &ControlSettings) -> ControlSettings {
+        match self.control_address.as_ref() {
+            Some(address) => ControlSettings {
+                enabled: true,
+                address: address.clone(),
+                ..control.clone()
+            },
+            None => control.clone(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn session_config(&self) -> SessionConfig {
+        let connection = &self.connection;
+        let device = match self.device.device_prefix.as_str() {
+            "" => DeviceSelector::Default,
+            prefix => DeviceSelector::Prefix(prefix.to_owned()),
+        };
+        SessionConfig {
+            device,
+            buffer_capacity: self.latency.buffer_capacity.max(1),
+            read_timeout: Duration::from_millis(
+                connection
+                    .read_timeout_ms
+                    .clamp(*READ_TIMEOUT_MS.start(), *READ_TIMEOUT_MS.end()),
+            ),
+            reconnect_policy: ReconnectPolicy {
+                max_attempts: (!connection.reconnect_forever)
+                    .then_some(connection.reconnect_attempts),
+                initial_delay: Duration::from_millis(connection.reconnect_delay_ms),
+                max_delay: Duration::from_millis(connection.reconnect_max_delay_ms),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Settings {
+    /// Where the settings are kept, if the system has a configuration
+    /// directory.
+    pub fn path() -> Option<PathBuf> {
+        directories_next::ProjectDirs::from("", "", "Fast Mic")
+            .map(|dirs| dirs.config_dir().join("settings.toml"))
+    }
+
+    /// Reads the file at `path`, `None` if there is none yet.
+    pub fn load(path: &Path) -> Result<Option<Settings>, SettingsError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Settings::parse(&text).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // never leaves a half written file behind
+        let temporary = path.with_extension("toml.tmp");
+        // a left over one would keep its permissions
+        fs::remove_file(&temporary).ok();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // only the user may read the control token
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&temporary)?
+            .write_all(self.to_toml().as_bytes())?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Reads settings of any version, a missing version counts as the
+    /// current one.
+    pub fn parse(text: &str) -> Result<Settings, SettingsError> {
+        let mut table: Table = toml::from_str(text)?;
+        table
+            .entry("version")
+            .or_insert_with(|| Value::Integer(VERSION as i64));
+        Settings::migrate(table)
+    }
+
+    /// Takes over the address eframe stored, `get` looks up a key of its
+    /// storage.
+    pub fn from_storage(get: impl Fn(&str) -> Option<String>) -> Result<Settings, SettingsError> {
+        let mut table = Table::new();
+        if let Some(address) = get("address") {
+            table.insert("address".to_owned(), Value::String(address));
+        }
+        table.insert("version".to_owned(), Value::Integer(0));
+        Settings::migrate(table)
+    }
+
+    fn migrate(mut table: Table) -> Result<Settings, SettingsError> {
+        let version = match table.get("version") {
+            Some(Value::Integer(version)) if *version >= 0 => *version as u32,
+            _ => {
+                return Err(SettingsError::Parse(serde::de::Error::custom(
+                    "version must be a positive integer",
+                )))
+            }
+        };
+        if version > VERSION {
+            return Err(SettingsError::NewerVersion(version));
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            table = migration(table);
+        }
+        table.insert("version".to_owned(), Value::Integer(VERSION as i64));
+        let mut settings: Settings = Value::Table(table).try_into()?;
+        if settings.profiles.is_empty() {
+            settings
+                .profiles
+                .insert(DEFAULT_PROFILE.to_owned(), Profile::default());
+        }
+        if !settings.profiles.contains_key(&settings.profile) {
+            settings.profile = settings.profiles.keys().next().unwrap().clone();
+        }
+        Ok(settings)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("The settings are valid TOML")
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[&self.profile]
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        self.profiles.get_mut(&self.profile).unwrap()
+    }
+
+    pub fn select(&mut self, name: &str) -> Result<(), SettingsError> {
+        if !self.profiles.contains_key(name) {
+            return Err(SettingsError::UnknownProfile(name.to_owned()));
+        }
+        self.profile = name.to_owned();
+        Ok(())
+    }
+
+    /// Adds a copy of the active profile and selects it.
+    pub fn duplicate(&mut self, name: &str) {
+        let profile = self.profile().clone();
+        self.profiles.insert(name.to_owned(), profile);
+        self.profile = name.to_owned();
+    }
+
+    /// The phone connected to last, or else the address of the profile.
+    pub fn last_address(&self) -> Option<&str> {
+        let address = match self.recents.first() {
+            Some(recent) => &recent.address,
+            None => &self.profile().connection.address,
+        };
+        (!address.is_empty()).then_some(address.as_str())
+    }
+
+    /// Moves `address` to the top of the recent connections.
+    pub fn record_connection(&mut self, address: &str, device: String, at: SystemTime) {
+        self.recents.retain(|recent| recent.address != address);
+        self.recents.insert(
+            0,
+            RecentConnection {
+                address: address.to_owned(),
+                device,
+                last_seen: at
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_secs())
+                    .unwrap_or_default(),
+            },
+        );
+        self.recents.truncate(MAX_RECENTS);
+    }
+
+    pub fn favorite(&self, address: &str) -> Option<&Favorite> {
+        self.favorites
+            .iter()
+            .find(|favorite| favorite.address == address)
+    }
+
+    /// Adds `address` to the favorites, named after itself until the user
+    /// renames it.
+    pub fn pin(&mut self, address: &str) {
+        if self.favorite(address).is_none() {
+            self.favorites.push(Favorite {
+                name: address.to_owned(),
+                address: address.to_owned(),
+            });
+        }
+    }
+
+    pub fn unpin(&mut self, address: &str) {
+        self.favorites
+            .retain(|favorite| favorite.address != address);
+    }
+
+    /// Removes the active profile unless it's the last one.
+    pub fn remove(&mut self) {
+        if self.profiles.len() > 1 {
+            self.profiles.remove(&self.profile);
+            self.profile = self.profiles.keys().next().unwrap().clone();
+        }
+    }
+}
+
+/// Moves the address eframe stored into a profile named "Default".
+fn from_storage(old: Table) -> Table {
+    let connection = Table::from_iter(
+        old.get("address")
+            .cloned()
+            .map(|address| ("address".to_owned(), address)),
+    );
+    let profile = Table::from_iter([("connection".to_owned(), Value::Table(connection))]);
+    Table::from_iter([
+        (
+            "profile".to_owned(),
+            Value::String(DEFAULT_PROFILE.to_owned()),
+        ),
+        (
+            "profiles".to_owned(),
+            Value::Table(Table::from_iter([(
+                DEFAULT_PROFILE.to_owned(),
+                Value::Table(profile),
+            )])),
+        ),
+    ])
+}
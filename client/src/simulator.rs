@@ -0,0 +1,308 @@
+//! Stands in for the phone app's `Sender`, for development and tests.
+//!
+//! Serves one client at a time and streams a signal in real time, in the raw
+//! format or, when the client asks for it, in frames (see [`crate::protocol`]).
+//! [`Faults`] make it misbehave the way a phone on bad Wi-Fi does.
+
+use std::{
+    f64::consts::TAU,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    protocol::{self, Frame},
+    wav,
+};
+
+pub const SAMPLE_RATE: u32 = 48000;
+
+const CHUNK: Duration = Duration::from_millis(20);
+const CHUNK_SAMPLES: usize = SAMPLE_RATE as usize / 50;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(200);
+const AMPLITUDE: f64 = i16::MAX as f64 / 2.0;
+
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Played in a loop.
+    Samples(Arc<Vec<i16>>),
+    /// A sine going from `from` to `to` Hz exponentially, over and over.
+    Sweep {
+        from: f64,
+        to: f64,
+        period: Duration,
+    },
+    Silence,
+}
+
+impl Source {
+    /// Loads a 16-bit PCM file recorded at 48kHz, which is what the phone
+    /// captures.
+    pub fn wav(path: impl AsRef<Path>) -> io::Result<Source> {
+        let wav = wav::read_wav(&mut BufReader::new(File::open(path)?))?;
+        if wav.sample_rate != SAMPLE_RATE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample rate must be {} Hz, got {} Hz",
+                    SAMPLE_RATE, wav.sample_rate
+                ),
+            ));
+        }
+        if wav.samples.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "No samples"));
+        }
+        Ok(Source::Samples(Arc::new(wav.samples)))
+    }
+
+    /// Fails for sources that can't be played: no samples, or a sweep
+    /// through frequencies that aren't positive.
+    pub fn validate(&self) -> io::Result<()> {
+        let problem = match self {
+            Source::Samples(samples) if samples.is_empty() => "No samples",
+            Source::Sweep { from, to, .. }
+                if !(from.is_finite() && to.is_finite() && *from > 0.0 && *to > 0.0) =>
+            {
+                "Sweep frequencies must be positive"
+            }
+            _ => return Ok(()),
+        };
+        Err(io::Error::new(io::ErrorKind::InvalidInput, problem))
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Sweep {
+            from: 100.0,
+            to: 8000.0,
+            period: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Pauses in sending, like a congested network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stall {
+    pub every: Duration,
+    pub pause: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Faults {
+    pub stall: Option<Stall>,
+    /// Closes the connection after streaming this long.
+    pub disconnect_after: Option<Duration>,
+    /// Damages every n-th chunk: a stray byte that shifts the samples in the
+    /// raw format, an unknown frame type when framed.
+    pub corrupt_every: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulatorConfig {
+    pub source: Source,
+    /// Accepts the client's hello and switches to frames like the current
+    /// app. Without it the simulator behaves like an app that only knows the
+    /// raw format.
+    pub framing: bool,
+    pub faults: Faults,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            source: Source::default(),
+            framing: true,
+            faults: Faults::default(),
+        }
+    }
+}
+
+pub struct Simulator {
+    listener: TcpListener,
+    config: SimulatorConfig,
+}
+
+impl Simulator {
+    pub fn bind(address: impl ToSocketAddrs, config: SimulatorConfig) -> io::Result<Self> {
+        config.source.validate()?;
+        Ok(Simulator {
+            listener: TcpListener::bind(address)?,
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients one after the other, until accepting fails.
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            let (stream, address) = self.listener.accept()?;
+            if let Err(err) = self.serve_client(stream, address) {
+                tracing::warn!("Connection closed: {}", err);
+            }
+        }
+    }
+
+    /// Waits for a client and streams to it until it goes away or a fault
+    /// ends the connection.
+    pub fn serve_next(&self) -> io::Result<()> {
+        let (stream, address) = self.listener.accept()?;
+        self.serve_client(stream, address)
+    }
+
+    fn serve_client(&self, stream: TcpStream, address: SocketAddr) -> io::Result<()> {
+        tracing::info!(%address, "Client connected");
+        let result = self.serve(stream);
+        tracing::info!(%address, "Client disconnected");
+        result
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        let framed = self.config.framing && handshake(&mut stream)?;
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        if framed {
+            let reader = stream.try_clone()?;
+            let writer = writer.clone();
+            thread::spawn(move || answer_pings(reader, writer));
+        }
+
+        let faults = &self.config.faults;
+        let mut generator = Generator::new(self.config.source.clone());
+        let mut next_chunk = Instant::now();
+        let mut streamed = Duration::ZERO;
+        let mut next_stall = faults.stall.as_ref().map(|stall| stall.every);
+        let mut chunks = 0u32;
+        let result = loop {
+            if faults
+                .disconnect_after
+                .is_some_and(|disconnect_after| streamed >= disconnect_after)
+            {
+                tracing::info!("Disconnecting as configured");
+                break Ok(());
+            }
+            if let (Some(stall), Some(at)) = (faults.stall.as_ref(), next_stall) {
+                if streamed >= at {
+                    thread::sleep(stall.pause);
+                    next_chunk = Instant::now();
+                    next_stall = Some(at + stall.every);
+                }
+            }
+
+            chunks += 1;
+            let corrupt = faults
+                .corrupt_every
+                .is_some_and(|every| every > 0 && chunks.is_multiple_of(every));
+            let samples = generator.next_chunk(CHUNK_SAMPLES);
+            let sent = send_chunk(&mut writer.lock().unwrap(), &samples, framed, corrupt);
+            if let Err(err) = sent {
+                break Err(err);
+            }
+            streamed += CHUNK;
+
+            next_chunk += CHUNK;
+            if let Some(wait) = next_chunk.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        };
+        stream.shutdown(Shutdown::Both).ok();
+        result
+    }
+}
+
+/// Waits briefly for the client's hello and answers it. Returns whether the
+/// connection is framed from now on.
+fn handshake(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut hello = [0u8; protocol::HELLO.len()];
+    let framed = match stream.read_exact(&mut hello) {
+        Ok(()) => hello == protocol::HELLO,
+        Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            false
+        }
+        Err(err) => return Err(err),
+    };
+    stream.set_read_timeout(None)?;
+    if framed {
+        stream.write_all(&protocol::MAGIC)?;
+    }
+    Ok(framed)
+}
+
+fn answer_pings(mut reader: TcpStream, writer: Arc<Mutex<TcpStream>>) {
+    while let Ok(sent_at) = protocol::read_ping(&mut reader) {
+        let pong = Frame::Pong {
+            sent_at,
+            received_at: protocol::now_micros(),
+        };
+        if protocol::write_frame(&mut *writer.lock().unwrap(), &pong).is_err() {
+            break;
+        }
+    }
+}
+
+fn send_chunk(
+    writer: &mut TcpStream,
+    samples: &[i16],
+    framed: bool,
+    corrupt: bool,
+) -> io::Result<()> {
+    let payload: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    if corrupt {
+        writer.write_all(&[0xFF])?;
+    }
+    if !framed {
+        return writer.write_all(&payload);
+    }
+    let duration = samples.len() as i64 * 1_000_000 / SAMPLE_RATE as i64;
+    protocol::write_frame(
+        writer,
+        &Frame::Audio {
+            captured_at: protocol::now_micros() - duration,
+            payload,
+        },
+    )
+}
+
+struct Generator {
+    source: Source,
+    position: usize,
+    phase: f64,
+}
+
+impl Generator {
+    fn new(source: Source) -> Self {
+        Generator {
+            source,
+            position: 0,
+            phase: 0.0,
+        }
+    }
+
+    fn next_chunk(&mut self, length: usize) -> Vec<i16> {
+        (0..length).map(|_| self.next_sample()).collect()
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let position = self.position;
+        self.position += 1;
+        match &self.source {
+            Source::Samples(samples) => samples[position % samples.len()],
+            Source::Sweep { from, to, period } => {
+                let period = (period.as_secs_f64() * SAMPLE_RATE as f64).max(1.0) as usize;
+                let progress = (position % period) as f64 / period as f64;
+                let frequency = from * (to / from).powf(progress);
+                self.phase = (self.phase + TAU * frequency / SAMPLE_RATE as f64) % TAU;
+                (self.phase.sin() * AMPLITUDE) as i16
+            }
+            Source::Silence => 0,
+        }
+    }
+}
@@ -1,87 +1,655 @@
 use ringbuf::Producer;
 
 use std::{
-    io::Read,
-    net::{Shutdown, SocketAddr, TcpStream},
-    time::Duration,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
+pub const DEFAULT_PORT: u16 = 50551;
+
 const BUFFER_SIZE: usize = 3840;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const PING_INTERVAL: Duration = Duration::from_secs(1);
 
-use anyhow::{format_err, Result};
+use crate::{
+    analysis::{AnalysisTap, Stage},
+    audio::MonitorTap,
+    capture::{CaptureWriter, CapturingReader, ReplayReader, ReplayTiming},
+    error::SessionError,
+    latency::LatencyMeter,
+    mute::MicGate,
+    protocol::{self, Frame},
+    stats::SessionStats,
+};
 
 pub fn socket_connect(
     address: &str,
     media_producer: Producer<i16>,
-) -> Result<SocketState, SocketError> {
-    let address_parsed = (address)
-        .parse::<SocketAddr>()
-        .map_err(|_| SocketError::AddressError)?;
-
-    let stream = TcpStream::connect(address_parsed).map_err(|err| {
-        eprintln!("Error connecting: {}", err);
-        SocketError::ConnectionError
+    stats: SessionStats,
+    measure_latency: bool,
+) -> Result<SocketState, SessionError> {
+    let _span = tracing::info_span!("connect", address).entered();
+    let mut last_error = None;
+    for address_resolved in resolve_address(address)? {
+        match TcpStream::connect_timeout(&address_resolved, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                tracing::info!("Connected to {}", address_resolved);
+                return start_session(address, stream, media_producer, stats, measure_latency)
+            }
+            Err(err) => {
+                tracing::warn!("Error connecting to {}: {}", address_resolved, err);
+                last_error = Some(SessionError::connect(address_resolved, err));
+            }
+        }
+    }
+    // resolve_address never returns an empty list
+    Err(last_error.unwrap())
+}
+
+/// Binds `address` and waits for phones to connect to it, for networks
+/// where the client can't reach the phone.
+pub fn socket_listen(address: &str) -> Result<SocketListener, SessionError> {
+    // a plain socket address may use port 0 to let the system pick one
+    let addresses = match address.parse::<SocketAddr>() {
+        Ok(address) => vec![address],
+        Err(_) => resolve_address(address)?,
+    };
+    let listener = TcpListener::bind(&addresses[..]).map_err(|source| SessionError::Bind {
+        address: address.to_owned(),
+        source,
     })?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|source| SessionError::Internal {
+            context: "setting up listener",
+            source,
+        })?;
+    Ok(SocketListener { listener })
+}
+
+pub struct SocketListener {
+    listener: TcpListener,
+}
+
+impl SocketListener {
+    pub fn local_addr(&self) -> Result<SocketAddr, SessionError> {
+        self.listener
+            .local_addr()
+            .map_err(|source| SessionError::Internal {
+                context: "reading listener address",
+                source,
+            })
+    }
+
+    /// Takes the next phone waiting to connect, without blocking. Nothing
+    /// is read from it until [`IncomingPhone::start`].
+    pub fn accept(&self) -> Result<Option<IncomingPhone>, SessionError> {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                stream
+                    .set_nonblocking(false)
+                    .map_err(|source| SessionError::Internal {
+                        context: "setting up connection",
+                        source,
+                    })?;
+                Ok(Some(IncomingPhone { stream, address }))
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(SessionError::ConnectionLost(err)),
+        }
+    }
+
+    /// Closes connections from other phones while one is streaming, returns
+    /// how many were refused.
+    pub fn reject_pending(&self) -> usize {
+        let mut rejected = 0;
+        while let Ok((stream, address)) = self.listener.accept() {
+            tracing::warn!(
+                "Client {} tried to connect, but one connection was already established",
+                address
+            );
+            stream.shutdown(Shutdown::Both).ok();
+            rejected += 1;
+        }
+        rejected
+    }
+}
+
+/// A phone that connected to a [`SocketListener`].
+pub struct IncomingPhone {
+    stream: TcpStream,
+    address: SocketAddr,
+}
+
+impl IncomingPhone {
+    /// Sets up the session like [`socket_connect`], which waits for the
+    /// phone's answer when measuring latency.
+    pub fn start(
+        self,
+        media_producer: Producer<i16>,
+        stats: SessionStats,
+        measure_latency: bool,
+    ) -> Result<SocketState, SessionError> {
+        start_session(
+            &self.address.to_string(),
+            self.stream,
+            media_producer,
+            stats,
+            measure_latency,
+        )
+    }
+
+    /// Closes the connection without reading from it.
+    pub fn reject(self) {
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+}
+
+/// A device address as typed by the user: an IPv4 address, a bracketed IPv6
+/// address or a hostname, with an optional port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressProblem {
+    Empty,
+    MissingHost,
+    UnclosedBracket,
+    InvalidIpv6(String),
+    UnbracketedIpv6,
+    InvalidIpv4(String),
+    InvalidHostname(String),
+    InvalidPort(String),
+    TrailingCharacters(String),
+}
+
+impl fmt::Display for AddressProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressProblem::Empty => write!(f, "Type the address shown on the phone"),
+            AddressProblem::MissingHost => write!(f, "The address is missing before the port"),
+            AddressProblem::UnclosedBracket => write!(f, "IPv6 address is missing a closing \"]\""),
+            AddressProblem::InvalidIpv6(host) => {
+                write!(f, "\"{}\" is not a valid IPv6 address", host)
+            }
+            AddressProblem::UnbracketedIpv6 => write!(
+                f,
+                "Put IPv6 addresses in brackets when adding a port, e.g. [fe80::1]:{}",
+                DEFAULT_PORT
+            ),
+            AddressProblem::InvalidIpv4(host) => write!(
+                f,
+                "\"{}\" is not a valid IPv4 address, each part must be between 0 and 255",
+                host
+            ),
+            AddressProblem::InvalidHostname(host) => write!(
+                f,
+                "\"{}\" is not a valid hostname, use letters, digits, \"-\" and \".\"",
+                host
+            ),
+            AddressProblem::InvalidPort(port) => {
+                write!(f, "Port \"{}\" must be a number between 1 and 65535", port)
+            }
+            AddressProblem::TrailingCharacters(rest) => {
+                write!(f, "Unexpected \"{}\" after the IPv6 address", rest)
+            }
+        }
+    }
+}
+
+impl Error for AddressProblem {}
+
+impl fmt::Display for DeviceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl FromStr for DeviceAddress {
+    type Err = AddressProblem;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(AddressProblem::Empty);
+        }
+
+        if let Some(rest) = input.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or(AddressProblem::UnclosedBracket)?;
+            host.parse::<Ipv6Addr>()
+                .map_err(|_| AddressProblem::InvalidIpv6(host.to_owned()))?;
+            let port = match rest {
+                "" => DEFAULT_PORT,
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => parse_port(port)?,
+                    None => return Err(AddressProblem::TrailingCharacters(rest.to_owned())),
+                },
+            };
+            return Ok(DeviceAddress {
+                host: host.to_owned(),
+                port,
+            });
+        }
+
+        if input.parse::<Ipv6Addr>().is_ok() {
+            return Ok(DeviceAddress {
+                host: input.to_owned(),
+                port: DEFAULT_PORT,
+            });
+        }
+
+        let (host, port) = match input.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => return Err(AddressProblem::UnbracketedIpv6),
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (input, DEFAULT_PORT),
+        };
+        validate_host(host)?;
+        Ok(DeviceAddress {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl DeviceAddress {
+    pub fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        Ok((self.host.as_str(), self.port).to_socket_addrs()?.collect())
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, AddressProblem> {
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(AddressProblem::InvalidPort(port.to_owned())),
+    }
+}
+
+fn validate_host(host: &str) -> Result<(), AddressProblem> {
+    if host.is_empty() {
+        return Err(AddressProblem::MissingHost);
+    }
+    if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return host
+            .parse::<Ipv4Addr>()
+            .map(|_| ())
+            .map_err(|_| AddressProblem::InvalidIpv4(host.to_owned()));
+    }
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if host.len() > 253 || !host.trim_end_matches('.').split('.').all(valid_label) {
+        return Err(AddressProblem::InvalidHostname(host.to_owned()));
+    }
+    Ok(())
+}
+
+fn resolve_address(address: &str) -> Result<Vec<SocketAddr>, SessionError> {
+    let address = address.parse::<DeviceAddress>()?;
+    match address.resolve() {
+        Ok(addresses) if !addresses.is_empty() => Ok(addresses),
+        Ok(_) => Err(SessionError::Resolve {
+            host: address.host,
+            source: None,
+        }),
+        Err(source) => Err(SessionError::Resolve {
+            host: address.host,
+            source: Some(source),
+        }),
+    }
+}
+
+fn start_session(
+    address: &str,
+    mut stream: TcpStream,
+    media_producer: Producer<i16>,
+    stats: SessionStats,
+    measure_latency: bool,
+) -> Result<SocketState, SessionError> {
     stream
         .set_read_timeout(Some(Duration::from_secs(10)))
-        .map_err(|_| SocketError::SetupError)?;
+        .map_err(|source| SessionError::Internal {
+            context: "setting up connection",
+            source,
+        })?;
 
+    let mut pending = Vec::new();
+    let mut measurement = None;
+    if measure_latency {
+        let mut answer = [0u8; 4];
+        stream
+            .write_all(&protocol::HELLO)
+            .and_then(|_| stream.read_exact(&mut answer))
+            .map_err(SessionError::stream)?;
+        if answer == protocol::MAGIC {
+            measurement = Some(Measurement {
+                meter: LatencyMeter::new(),
+                last_ping: None,
+            });
+        } else {
+            tracing::info!("Device doesn't support latency measurement");
+            pending.extend_from_slice(&answer);
+        }
+    }
+
+    let reader = stream
+        .try_clone()
+        .map_err(|source| SessionError::Internal {
+            context: "setting up connection",
+            source,
+        })?;
     Ok(SocketState {
         address: address.to_owned(),
-        stream,
-        media_producer,
+        reader: Box::new(reader),
+        stream: Some(stream),
+        media_producer: Some(media_producer),
         buffer: [0u8; BUFFER_SIZE],
+        pending,
+        monitor: None,
+        analysis: None,
+        gate: None,
+        measurement,
+        stats,
     })
 }
 
 pub struct SocketState {
     pub address: String,
-    stream: TcpStream,
-    media_producer: Producer<i16>,
+    reader: Box<dyn Read + Send>,
+    /// The connection, `None` when replaying a capture.
+    stream: Option<TcpStream>,
+    /// `None` while there is no output to play the samples.
+    media_producer: Option<Producer<i16>>,
     buffer: [u8; BUFFER_SIZE],
+    // bytes read while probing for the measurement protocol
+    pending: Vec<u8>,
+    monitor: Option<MonitorTap>,
+    analysis: Option<AnalysisTap>,
+    gate: Option<MicGate>,
+    measurement: Option<Measurement>,
+    stats: SessionStats,
+}
+
+struct Measurement {
+    meter: LatencyMeter,
+    last_ping: Option<Instant>,
 }
 
 impl SocketState {
-    pub fn seek(&mut self) -> Result<()> {
-        let media_producer = &mut self.media_producer;
-        for _ in 0..300 {
-            // avoid leaving function context
-            match (&mut self.stream).read_exact(&mut self.buffer) {
-                Ok(_) => {
-                    let current_data = &self.buffer[0..BUFFER_SIZE];
-                    let mut last_sample = 0_i16;
-                    for i in (0..current_data.len()).step_by(2) {
-                        let raw_value = i16::from_le_bytes([current_data[i], current_data[i + 1]]);
-                        let sample: i32 = raw_value as i32 + last_sample as i32;
-                        last_sample = (sample / 2) as i16;
-                        if media_producer.is_full() {
-                            eprintln!("Media producer full");
-                            break;
-                        }
-                        if media_producer.push(last_sample) == Err(last_sample) {
-                            eprintln!("Can't push item: {}", last_sample);
-                        };
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error seeking {:#?}", err);
-                    return Err(format_err!("Connection lost"));
+    /// Feeds a capture through the same decoding as a live connection.
+    pub fn replay(
+        path: &Path,
+        timing: ReplayTiming,
+        media_producer: Producer<i16>,
+        stats: SessionStats,
+    ) -> Result<SocketState, SessionError> {
+        let reader = ReplayReader::open(path, timing).map_err(|source| match source.kind() {
+            io::ErrorKind::InvalidData => SessionError::Protocol(source),
+            _ => SessionError::Internal {
+                context: "opening capture",
+                source,
+            },
+        })?;
+        let measurement = reader.framed().then(|| Measurement {
+            meter: LatencyMeter::new(),
+            last_ping: None,
+        });
+        Ok(SocketState {
+            address: path.display().to_string(),
+            reader: Box::new(reader),
+            stream: None,
+            media_producer: Some(media_producer),
+            buffer: [0u8; BUFFER_SIZE],
+            pending: Vec::new(),
+            monitor: None,
+            analysis: None,
+            gate: None,
+            measurement,
+            stats,
+        })
+    }
+
+    /// Records everything received from now on to `path`, see
+    /// [`crate::capture`].
+    pub fn start_capture(&mut self, path: &Path) -> Result<(), SessionError> {
+        let writer = CaptureWriter::create(path, self.measurement.is_some())
+            .and_then(|mut writer| {
+                // read while probing for the measurement protocol, but not
+                // decoded yet
+                if !self.pending.is_empty() {
+                    writer.record(&self.pending)?;
                 }
+                Ok(writer)
+            })
+            .map_err(|source| SessionError::Internal {
+                context: "creating capture",
+                source,
+            })?;
+        let reader = std::mem::replace(&mut self.reader, Box::new(io::empty()));
+        self.reader = Box::new(CapturingReader::new(reader, writer));
+        Ok(())
+    }
+
+    /// Copies the decoded samples to the monitor output.
+    pub fn set_monitor(&mut self, monitor: MonitorTap) {
+        self.monitor = Some(monitor);
+    }
+
+    /// Copies the samples to the analyzer, as received and as played.
+    pub fn set_analysis(&mut self, analysis: AnalysisTap) {
+        self.analysis = Some(analysis);
+    }
+
+    /// Counts the connection as lost after `timeout` without data.
+    pub fn set_read_timeout(&self, timeout: Duration) -> Result<(), SessionError> {
+        match self.stream.as_ref() {
+            Some(stream) => {
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .map_err(|source| SessionError::Internal {
+                        context: "setting up connection",
+                        source,
+                    })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Mutes the decoded samples whenever `gate` says so.
+    pub fn set_gate(&mut self, gate: MicGate) {
+        gate.reset();
+        self.gate = Some(gate);
+    }
+
+    /// Sends the following samples to a new buffer, e.g. after the output
+    /// stream was reopened, or drops them until the next one with `None`,
+    /// while nothing plays the buffer.
+    pub fn set_media_producer(&mut self, media_producer: Option<Producer<i16>>) {
+        self.media_producer = media_producer;
+    }
+
+    /// Reads and decodes a single chunk or frame.
+    pub fn receive(&mut self) -> Result<(), SessionError> {
+        let result = if self.measurement.is_some() {
+            self.read_frame()
+        } else {
+            self.read_raw()
+        };
+        match result {
+            Ok(()) => {
+                tracing::trace!("Received a chunk");
+                Ok(())
+            }
+            Err(err) => {
+                tracing::debug!("Error receiving: {:?}", err);
+                Err(SessionError::stream(err))
             }
         }
-        Ok(())
     }
 
-    pub fn disconnect(&mut self) -> Result<()> {
+    /// A handle that closes the connection from another thread, which makes
+    /// a blocked [`SocketState::receive`] return.
+    pub fn shutdown_handle(&self) -> Result<ShutdownHandle, SessionError> {
         self.stream
-            .shutdown(Shutdown::Both)
-            .map_err(|err| format_err!("Error shutting down socket: {}", err))
+            .as_ref()
+            .map(TcpStream::try_clone)
+            .transpose()
+            .map(ShutdownHandle)
+            .map_err(|source| SessionError::Internal {
+                context: "cloning socket",
+                source,
+            })
+    }
+
+    fn read_raw(&mut self) -> io::Result<()> {
+        let offset = self.pending.len();
+        self.buffer[..offset].copy_from_slice(&self.pending);
+        self.pending.clear();
+        self.reader.read_exact(&mut self.buffer[offset..])?;
+        decode_samples(
+            &self.buffer,
+            self.media_producer.as_mut(),
+            &self.stats,
+            self.gate.as_ref(),
+            self.monitor.as_ref(),
+            self.analysis.as_ref(),
+        );
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> io::Result<()> {
+        let measurement = self.measurement.as_mut().unwrap();
+        if let Some(stream) = self.stream.as_mut() {
+            if measurement
+                .last_ping
+                .is_none_or(|last_ping| last_ping.elapsed() >= PING_INTERVAL)
+            {
+                protocol::write_ping(stream, protocol::now_micros())?;
+                measurement.last_ping = Some(Instant::now());
+            }
+        }
+        match protocol::read_frame(&mut self.reader)? {
+            Frame::Audio {
+                captured_at,
+                payload,
+            } => {
+                measurement
+                    .meter
+                    .record_audio(captured_at, protocol::now_micros());
+                decode_samples(
+                    &payload,
+                    self.media_producer.as_mut(),
+                    &self.stats,
+                    self.gate.as_ref(),
+                    self.monitor.as_ref(),
+                    self.analysis.as_ref(),
+                );
+            }
+            Frame::Pong {
+                sent_at,
+                received_at,
+            } => {
+                measurement
+                    .meter
+                    .record_pong(sent_at, received_at, protocol::now_micros());
+            }
+        }
+        self.stats.set_latency_measurement(&measurement.meter);
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) -> Result<(), SessionError> {
+        self.stream.as_ref().map_or(Ok(()), shutdown)
     }
 }
 
-#[derive(Debug)]
-pub enum SocketError {
-    AddressError,
-    ConnectionError,
-    SetupError,
+pub struct ShutdownHandle(Option<TcpStream>);
+
+impl ShutdownHandle {
+    /// The phone's side of the connection, `None` when replaying.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.0.as_ref().and_then(|stream| stream.peer_addr().ok())
+    }
+
+    pub fn shutdown(&self) -> Result<(), SessionError> {
+        self.0.as_ref().map_or(Ok(()), shutdown)
+    }
+}
+
+fn shutdown(stream: &TcpStream) -> Result<(), SessionError> {
+    match stream.shutdown(Shutdown::Both) {
+        // the device may have closed the connection already
+        Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+        result => result.map_err(|source| SessionError::Internal {
+            context: "shutting down socket",
+            source,
+        }),
+    }
+}
+
+fn decode_samples(
+    data: &[u8],
+    media_producer: Option<&mut Producer<i16>>,
+    stats: &SessionStats,
+    gate: Option<&MicGate>,
+    monitor: Option<&MonitorTap>,
+    analysis: Option<&AnalysisTap>,
+) {
+    let raw: Vec<i16> = data
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    if let Some(analysis) = analysis {
+        analysis.push(Stage::Input, &raw);
+    }
+    let mut last_sample = 0_i16;
+    let mut decoded: Vec<i16> = raw
+        .iter()
+        .map(|&raw_value| {
+            let sample: i32 = raw_value as i32 + last_sample as i32;
+            last_sample = (sample / 2) as i16;
+            last_sample
+        })
+        .collect();
+    if let Some(gate) = gate {
+        gate.apply(&mut decoded);
+    }
+    if let Some(analysis) = analysis {
+        analysis.push(Stage::Processed, &decoded);
+    }
+    if let Some(media_producer) = media_producer {
+        for &sample in decoded.iter() {
+            if media_producer.is_full() {
+                // once per chunk while the output is stuck
+                tracing::debug!("Media producer full");
+                stats.record_overrun();
+                break;
+            }
+            if media_producer.push(sample) == Err(sample) {
+                tracing::error!("Can't push item: {}", sample);
+            };
+        }
+    }
+    if let Some(monitor) = monitor {
+        monitor.push(&decoded);
+    }
+    // after queueing, so whoever sees the count can play the samples
+    stats.record_chunk(data.len(), data.len() / 2);
 }
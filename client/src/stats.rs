@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::latency::LatencyMeter;
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Counters for the current session, shared between the event loop, the
+/// socket reader, the audio callback and the GUI.
+#[derive(Clone, Default)]
+pub struct SessionStats {
+    inner: Arc<StatsInner>,
+}
+
+struct StatsInner {
+    bytes_received: AtomicU64,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    reconnects: AtomicU64,
+    buffer_fill: AtomicUsize,
+    buffer_capacity: AtomicUsize,
+    output_sample_rate: AtomicU32,
+    // µs, u64::MAX until the device reports playback timestamps
+    device_latency: AtomicU64,
+    timing: Mutex<Timing>,
+}
+
+impl Default for StatsInner {
+    fn default() -> Self {
+        StatsInner {
+            bytes_received: Default::default(),
+            underruns: Default::default(),
+            overruns: Default::default(),
+            reconnects: Default::default(),
+            buffer_fill: Default::default(),
+            buffer_capacity: Default::default(),
+            output_sample_rate: Default::default(),
+            device_latency: AtomicU64::new(u64::MAX),
+            timing: Default::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Timing {
+    started_at: Option<Instant>,
+    window_start: Option<Instant>,
+    window_samples: u64,
+    measured_sample_rate: f64,
+    last_arrival: Option<Instant>,
+    last_interval: Option<f64>,
+    jitter: f64,
+    chunk_samples: usize,
+    clock_offset: Option<i64>,
+    round_trip: Option<i64>,
+    network_delay: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    pub session_seconds: f64,
+    pub bytes_received: u64,
+    pub measured_sample_rate: f64,
+    pub output_sample_rate: u32,
+    pub buffer_fill: usize,
+    pub buffer_capacity: usize,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub reconnects: u64,
+    pub estimated_latency_ms: f64,
+    pub jitter_ms: f64,
+    pub buffer_delay_ms: f64,
+    /// Only known when the device reports playback timestamps.
+    pub device_latency_ms: Option<f64>,
+    /// The fields below are only set in latency measurement mode.
+    pub network_delay_ms: Option<f64>,
+    pub round_trip_ms: Option<f64>,
+    pub clock_offset_ms: Option<f64>,
+    pub end_to_end_latency_ms: Option<f64>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Clears every counter, called when the user starts a new session.
+    pub fn reset(&self) {
+        let inner = &self.inner;
+        inner.bytes_received.store(0, Ordering::Relaxed);
+        inner.underruns.store(0, Ordering::Relaxed);
+        inner.overruns.store(0, Ordering::Relaxed);
+        inner.reconnects.store(0, Ordering::Relaxed);
+        inner.buffer_fill.store(0, Ordering::Relaxed);
+        inner.device_latency.store(u64::MAX, Ordering::Relaxed);
+        *inner.timing.lock().unwrap() = Timing {
+            started_at: Some(Instant::now()),
+            ..Default::default()
+        };
+    }
+
+    /// Records one chunk read from the socket and the samples decoded from it.
+    pub fn record_chunk(&self, bytes: usize, samples: usize) {
+        self.inner
+            .bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut timing = self.inner.timing.lock().unwrap();
+        timing.chunk_samples = samples;
+
+        let window_start = *timing.window_start.get_or_insert(now);
+        timing.window_samples += samples as u64;
+        let elapsed = now - window_start;
+        if elapsed >= RATE_WINDOW {
+            timing.measured_sample_rate = timing.window_samples as f64 / elapsed.as_secs_f64();
+            timing.window_start = Some(now);
+            timing.window_samples = 0;
+        }
+
+        // interarrival jitter as in RFC 3550, without sender timestamps
+        if let Some(last_arrival) = timing.last_arrival {
+            let interval = (now - last_arrival).as_secs_f64();
+            if let Some(last_interval) = timing.last_interval {
+                let deviation = (interval - last_interval).abs();
+                timing.jitter += (deviation - timing.jitter) / 16.0;
+            }
+            timing.last_interval = Some(interval);
+        }
+        timing.last_arrival = Some(now);
+    }
+
+    /// Forgets the arrival time of the last chunk, so a reconnection gap
+    /// doesn't count as jitter.
+    pub fn record_reconnect(&self) {
+        self.inner.reconnects.fetch_add(1, Ordering::Relaxed);
+        let mut timing = self.inner.timing.lock().unwrap();
+        timing.last_arrival = None;
+        timing.last_interval = None;
+    }
+
+    pub fn record_underrun(&self) {
+        self.inner.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_overrun(&self) {
+        self.inner.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the overrun count back to `overruns`, to forget the ones counted
+    /// while the output was being reopened.
+    pub fn rewind_overruns(&self, overruns: u64) {
+        self.inner.overruns.fetch_min(overruns, Ordering::Relaxed);
+    }
+
+    pub fn set_buffer_fill(&self, fill: usize) {
+        self.inner.buffer_fill.store(fill, Ordering::Relaxed);
+    }
+
+    pub fn set_buffer_capacity(&self, capacity: usize) {
+        self.inner
+            .buffer_capacity
+            .store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn set_device_latency(&self, latency: Duration) {
+        self.inner
+            .device_latency
+            .store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_latency_measurement(&self, meter: &LatencyMeter) {
+        let mut timing = self.inner.timing.lock().unwrap();
+        timing.clock_offset = meter.clock_offset();
+        timing.round_trip = meter.round_trip();
+        timing.network_delay = meter.network_delay();
+    }
+
+    pub fn set_output_sample_rate(&self, sample_rate: u32) {
+        self.inner
+            .output_sample_rate
+            .store(sample_rate, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let inner = &self.inner;
+        let timing = inner.timing.lock().unwrap();
+        let output_sample_rate = inner.output_sample_rate.load(Ordering::Relaxed);
+        let buffer_fill = inner.buffer_fill.load(Ordering::Relaxed);
+        let samples_to_ms = |samples: usize| {
+            if output_sample_rate > 0 {
+                samples as f64 * 1000.0 / output_sample_rate as f64
+            } else {
+                0.0
+            }
+        };
+        let buffer_delay_ms = samples_to_ms(buffer_fill);
+        let device_latency_ms = match inner.device_latency.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            latency => Some(latency as f64 / 1000.0),
+        };
+        let network_delay_ms = timing.network_delay.map(|delay| delay / 1000.0);
+        StatsSnapshot {
+            session_seconds: timing
+                .started_at
+                .map(|started_at| started_at.elapsed().as_secs_f64())
+                .unwrap_or_default(),
+            bytes_received: inner.bytes_received.load(Ordering::Relaxed),
+            measured_sample_rate: timing.measured_sample_rate,
+            output_sample_rate,
+            buffer_fill,
+            buffer_capacity: inner.buffer_capacity.load(Ordering::Relaxed),
+            underruns: inner.underruns.load(Ordering::Relaxed),
+            overruns: inner.overruns.load(Ordering::Relaxed),
+            reconnects: inner.reconnects.load(Ordering::Relaxed),
+            estimated_latency_ms: samples_to_ms(buffer_fill + timing.chunk_samples),
+            jitter_ms: timing.jitter * 1000.0,
+            buffer_delay_ms,
+            device_latency_ms,
+            network_delay_ms,
+            round_trip_ms: timing
+                .round_trip
+                .map(|round_trip| round_trip as f64 / 1000.0),
+            clock_offset_ms: timing.clock_offset.map(|offset| offset as f64 / 1000.0),
+            end_to_end_latency_ms: network_delay_ms
+                .map(|delay| delay + buffer_delay_ms + device_latency_ms.unwrap_or_default()),
+        }
+    }
+}
+
+impl StatsSnapshot {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+}
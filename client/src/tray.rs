@@ -0,0 +1,424 @@
+//! An icon in the system tray showing the state of the session, with a menu
+//! to connect, mute or quit without the window.
+//!
+//! On Linux it is a StatusNotifierItem, shown by KDE, most panels and GNOME
+//! with the AppIndicator extension. On Windows it sits in the notification
+//! area. macOS has no tray yet.
+
+use std::io;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+#[cfg(any(target_os = "linux", windows))]
+use std::thread;
+#[cfg(windows)]
+use std::{sync::mpsc, time::Duration};
+
+#[cfg(windows)]
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crossbeam_channel::Sender;
+
+use crate::{mute::MicState, session::SessionState};
+
+/// Pixels of the side of the icon.
+pub const ICON_SIZE: usize = 32;
+
+/// How long the notification area waits for a new status between handling
+/// its window messages.
+#[cfg(windows)]
+const NOTIFICATION_AREA_POLL: Duration = Duration::from_millis(50);
+
+/// The ids of the menu items in the notification area.
+#[cfg(windows)]
+const MENU_ITEMS: [(&str, TrayCommand); 5] = [
+    ("show", TrayCommand::ShowWindow),
+    ("connect", TrayCommand::Connect),
+    ("disconnect", TrayCommand::Disconnect),
+    ("mute", TrayCommand::ToggleMute),
+    ("quit", TrayCommand::Quit),
+];
+
+/// What the user picked in the tray.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayCommand {
+    /// Also sent when there is no tray to show the icon in.
+    ShowWindow,
+    Connect,
+    Disconnect,
+    ToggleMute,
+    Quit,
+}
+
+/// The color of the icon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayLight {
+    Off,
+    /// Connecting, waiting for the phone or reconnecting.
+    Waiting,
+    On,
+    Failed,
+}
+
+impl TrayLight {
+    /// As red, green and blue.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            TrayLight::Off => [128, 128, 128],
+            TrayLight::Waiting => [148, 120, 16],
+            TrayLight::On => [16, 148, 54],
+            TrayLight::Failed => [149, 1, 1],
+        }
+    }
+}
+
+/// What the tray shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrayStatus {
+    pub light: TrayLight,
+    pub description: String,
+    pub can_connect: bool,
+    pub muted: bool,
+    /// Otherwise the menu offers to show it.
+    pub window_shown: bool,
+}
+
+impl TrayStatus {
+    pub fn new(state: &SessionState, mic: &MicState, window_shown: bool) -> Self {
+        let (light, description) = match state {
+            SessionState::Idle => (TrayLight::Off, "Not connected".to_owned()),
+            SessionState::Connecting { .. } => (TrayLight::Waiting, "Connecting".to_owned()),
+            SessionState::Listening { address } => (
+                TrayLight::Waiting,
+                format!("Waiting for the phone on {}", address),
+            ),
+            SessionState::Connected { .. } => (TrayLight::On, "Connected".to_owned()),
+            SessionState::Reconnecting { .. } => (TrayLight::Waiting, "Reconnecting".to_owned()),
+            SessionState::Failed(err) => (TrayLight::Failed, err.to_string()),
+        };
+        TrayStatus {
+            light,
+            description,
+            can_connect: state.can_start(),
+            muted: mic.muted,
+            window_shown,
+        }
+    }
+}
+
+/// A disc of the light's color, a ring while muted, as ARGB32 in network
+/// byte order.
+pub fn icon_pixels(status: &TrayStatus) -> Vec<u8> {
+    let [red, green, blue] = status.light.color();
+    let center = ICON_SIZE as f32 / 2.0;
+    let radius = center - 1.0;
+    let mut pixels = Vec::with_capacity(ICON_SIZE * ICON_SIZE * 4);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f32 + 0.5 - center).hypot(y as f32 + 0.5 - center);
+            let inside = distance <= radius && !(status.muted && distance < radius / 2.0);
+            match inside {
+                true => pixels.extend_from_slice(&[255, red, green, blue]),
+                false => pixels.extend_from_slice(&[0, 0, 0, 0]),
+            }
+        }
+    }
+    pixels
+}
+
+/// Shows the icon until dropped.
+pub struct TrayIcon {
+    status: TrayStatus,
+    #[cfg(target_os = "linux")]
+    handle: ksni::Handle<StatusNotifier>,
+    /// To the thread of the icon, which exits once it is dropped.
+    #[cfg(windows)]
+    updates: Sender<TrayStatus>,
+}
+
+impl TrayIcon {
+    /// Sends what the user picks to `commands` and then calls `wake`, e.g. to
+    /// repaint the window that handles them.
+    pub fn start(
+        status: TrayStatus,
+        commands: Sender<TrayCommand>,
+        wake: impl Fn() + Send + Sync + 'static,
+    ) -> io::Result<TrayIcon> {
+        #[cfg(target_os = "linux")]
+        {
+            let notifier = StatusNotifier {
+                status: status.clone(),
+                commands,
+                wake: Arc::new(wake),
+            };
+            let fallback = notifier.clone();
+            let service = ksni::TrayService::new(notifier);
+            let handle = service.handle();
+            thread::spawn(move || {
+                if let Err(err) = service.run() {
+                    tracing::warn!("Cannot show tray icon: {}", err);
+                    fallback.send(TrayCommand::ShowWindow);
+                }
+            });
+            Ok(TrayIcon { status, handle })
+        }
+        #[cfg(windows)]
+        {
+            let (updates, receiver) = crossbeam_channel::unbounded();
+            let (started_sender, started) = mpsc::channel();
+            let area = NotificationArea {
+                commands,
+                wake: Box::new(wake),
+            };
+            let shown = status.clone();
+            thread::spawn(move || area.run(shown, receiver, started_sender));
+            started
+                .recv()
+                .unwrap_or_else(|_| Err(io::Error::other("The tray icon thread exited")))?;
+            Ok(TrayIcon { status, updates })
+        }
+        #[cfg(not(any(target_os = "linux", windows)))]
+        {
+            let _ = (status, commands, wake);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "No tray icon on this platform",
+            ))
+        }
+    }
+
+    /// Redraws the icon and the menu if `status` changed.
+    pub fn set_status(&mut self, status: TrayStatus) {
+        if status == self.status {
+            return;
+        }
+        self.status = status.clone();
+        #[cfg(target_os = "linux")]
+        self.handle.update(|notifier| notifier.status = status);
+        #[cfg(windows)]
+        self.updates.send(status).ok();
+    }
+}
+
+impl Drop for TrayIcon {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        self.handle.shutdown();
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+struct StatusNotifier {
+    status: TrayStatus,
+    commands: Sender<TrayCommand>,
+    wake: Arc<dyn Fn() + Send + Sync>,
+}
+
+#[cfg(target_os = "linux")]
+impl StatusNotifier {
+    fn send(&self, command: TrayCommand) {
+        if self.commands.send(command).is_ok() {
+            (self.wake)();
+        }
+    }
+
+    fn item(label: &str, command: TrayCommand) -> ksni::MenuItem<Self> {
+        ksni::menu::StandardItem {
+            label: label.to_owned(),
+            activate: Box::new(move |notifier: &mut Self| notifier.send(command)),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ksni::Tray for StatusNotifier {
+    fn id(&self) -> String {
+        "fast-mic".to_owned()
+    }
+
+    fn title(&self) -> String {
+        "Fast Mic".to_owned()
+    }
+
+    fn icon_pixmap(&self) -> Vec<ksni::Icon> {
+        vec![ksni::Icon {
+            width: ICON_SIZE as i32,
+            height: ICON_SIZE as i32,
+            data: icon_pixels(&self.status),
+        }]
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        ksni::ToolTip {
+            title: "Fast Mic".to_owned(),
+            description: self.status.description.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send(TrayCommand::ShowWindow);
+    }
+
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        let mut items = Vec::new();
+        if !self.status.window_shown {
+            items.push(Self::item("Show Fast Mic", TrayCommand::ShowWindow));
+        }
+        items.push(match self.status.can_connect {
+            true => Self::item("Connect", TrayCommand::Connect),
+            false => Self::item("Disconnect", TrayCommand::Disconnect),
+        });
+        items.push(
+            ksni::menu::CheckmarkItem {
+                label: "Mute".to_owned(),
+                checked: self.status.muted,
+                activate: Box::new(|notifier: &mut Self| notifier.send(TrayCommand::ToggleMute)),
+                ..Default::default()
+            }
+            .into(),
+        );
+        items.push(ksni::MenuItem::Separator);
+        items.push(Self::item("Quit", TrayCommand::Quit));
+        items
+    }
+
+    fn watcher_offine(&self) -> bool {
+        tracing::warn!("No system tray to show the icon in");
+        self.send(TrayCommand::ShowWindow);
+        // shows up if a tray starts later
+        true
+    }
+}
+
+#[cfg(windows)]
+struct NotificationArea {
+    commands: Sender<TrayCommand>,
+    wake: Box<dyn Fn() + Send>,
+}
+
+#[cfg(windows)]
+impl NotificationArea {
+    /// Shows the icon and handles its window messages on this thread, which
+    /// the icon can't leave, until `updates` is disconnected.
+    fn run(
+        self,
+        status: TrayStatus,
+        updates: Receiver<TrayStatus>,
+        started: mpsc::Sender<io::Result<()>>,
+    ) {
+        use tray_icon::{menu::MenuEvent, MouseButton, MouseButtonState, TrayIconEvent};
+        use windows_sys::Win32::UI::WindowsAndMessaging::{
+            DispatchMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE,
+        };
+
+        let icon = match build_notification_icon(&status) {
+            Ok(icon) => icon,
+            Err(err) => {
+                started.send(Err(err)).ok();
+                return;
+            }
+        };
+        started.send(Ok(())).ok();
+        loop {
+            let mut message = MSG::default();
+            // SAFETY: `message` lives through the calls, and a null window
+            // takes the messages of every window of this thread
+            unsafe {
+                while PeekMessageW(&mut message, std::ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+                    TranslateMessage(&message);
+                    DispatchMessageW(&message);
+                }
+            }
+            for event in MenuEvent::receiver().try_iter() {
+                if let Some(&(_, command)) = MENU_ITEMS.iter().find(|(id, _)| event.id == *id) {
+                    self.send(command);
+                }
+            }
+            for event in TrayIconEvent::receiver().try_iter() {
+                if let TrayIconEvent::Click {
+                    button: MouseButton::Left,
+                    button_state: MouseButtonState::Up,
+                    ..
+                } = event
+                {
+                    self.send(TrayCommand::ShowWindow);
+                }
+            }
+            match updates.recv_timeout(NOTIFICATION_AREA_POLL) {
+                Ok(status) => {
+                    if let Err(err) = update_notification_icon(&icon, &status) {
+                        tracing::warn!("Cannot update tray icon: {}", err);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn send(&self, command: TrayCommand) {
+        if self.commands.send(command).is_ok() {
+            (self.wake)();
+        }
+    }
+}
+
+#[cfg(windows)]
+fn build_notification_icon(status: &TrayStatus) -> io::Result<tray_icon::TrayIcon> {
+    tray_icon::TrayIconBuilder::new()
+        .with_icon(notification_icon(status)?)
+        .with_tooltip(notification_tool_tip(status))
+        .with_menu(Box::new(notification_menu(status)?))
+        // the left button shows the window, the right one the menu
+        .with_menu_on_left_click(false)
+        .build()
+        .map_err(io::Error::other)
+}
+
+#[cfg(windows)]
+fn update_notification_icon(icon: &tray_icon::TrayIcon, status: &TrayStatus) -> io::Result<()> {
+    icon.set_icon(Some(notification_icon(status)?))
+        .map_err(io::Error::other)?;
+    icon.set_tooltip(Some(notification_tool_tip(status)))
+        .map_err(io::Error::other)?;
+    icon.set_menu(Some(Box::new(notification_menu(status)?)));
+    Ok(())
+}
+
+#[cfg(windows)]
+fn notification_icon(status: &TrayStatus) -> io::Result<tray_icon::Icon> {
+    // RGBA instead of ARGB
+    let rgba = icon_pixels(status)
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[1], pixel[2], pixel[3], pixel[0]])
+        .collect();
+    tray_icon::Icon::from_rgba(rgba, ICON_SIZE as u32, ICON_SIZE as u32).map_err(io::Error::other)
+}
+
+#[cfg(windows)]
+fn notification_tool_tip(status: &TrayStatus) -> String {
+    format!("Fast Mic\n{}", status.description)
+}
+
+#[cfg(windows)]
+fn notification_menu(status: &TrayStatus) -> io::Result<tray_icon::menu::Menu> {
+    use tray_icon::menu::{CheckMenuItem, IsMenuItem, Menu, MenuItem, PredefinedMenuItem};
+
+    let show = MenuItem::with_id("show", "Show Fast Mic", true, None);
+    let connection = match status.can_connect {
+        true => MenuItem::with_id("connect", "Connect", true, None),
+        false => MenuItem::with_id("disconnect", "Disconnect", true, None),
+    };
+    let mute = CheckMenuItem::with_id("mute", "Mute", true, status.muted, None);
+    let separator = PredefinedMenuItem::separator();
+    let quit = MenuItem::with_id("quit", "Quit", true, None);
+    let mut items: Vec<&dyn IsMenuItem> = Vec::new();
+    if !status.window_shown {
+        items.push(&show);
+    }
+    items.extend([&connection as &dyn IsMenuItem, &mute, &separator, &quit]);
+    Menu::with_items(&items).map_err(io::Error::other)
+}
@@ -0,0 +1,95 @@
+//! Just enough of the WAV format for 16-bit PCM.
+
+use std::io::{self, Read, Write};
+
+pub struct Wav {
+    pub sample_rate: u32,
+    /// Mono, channels of the file are averaged.
+    pub samples: Vec<i16>,
+}
+
+pub fn read_wav(reader: &mut impl Read) -> io::Result<Wav> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(invalid("Not a WAV file".to_owned()));
+    }
+
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)?;
+        let length = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        // the declared length can't be trusted to allocate up front
+        let mut body = Vec::new();
+        reader
+            .by_ref()
+            .take((length + length % 2) as u64)
+            .read_to_end(&mut body)?;
+        if body.len() < length {
+            return Err(invalid(format!(
+                "Chunk declares {} bytes but only {} follow",
+                length,
+                body.len()
+            )));
+        }
+        match &chunk[..4] {
+            b"fmt " if length >= 16 => {
+                let field = |offset: usize| u16::from_le_bytes([body[offset], body[offset + 1]]);
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                format = Some((field(0), field(2), sample_rate, field(14)));
+            }
+            b"data" => {
+                let (encoding, channels, sample_rate, bits) =
+                    format.ok_or_else(|| invalid("Missing format chunk".to_owned()))?;
+                if encoding != 1 || bits != 16 || channels == 0 {
+                    return Err(invalid(format!(
+                        "Only 16-bit PCM is supported, got format {} with {} bits",
+                        encoding, bits
+                    )));
+                }
+                let samples = body[..length]
+                    .chunks_exact(2 * channels as usize)
+                    .map(|frame| {
+                        let sum: i32 = frame
+                            .chunks_exact(2)
+                            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as i32)
+                            .sum();
+                        (sum / channels as i32) as i16
+                    })
+                    .collect();
+                return Ok(Wav {
+                    sample_rate,
+                    samples,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes mono 16-bit samples.
+pub fn write_wav(writer: &mut impl Write, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_length = (samples.len() * 2) as u32;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_length).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_length.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,78 @@
+use fast_mic::socket::{AddressProblem, DeviceAddress, DEFAULT_PORT};
+
+fn parse(input: &str) -> Result<DeviceAddress, AddressProblem> {
+    input.parse()
+}
+
+fn address(host: &str, port: u16) -> DeviceAddress {
+    DeviceAddress {
+        host: host.to_owned(),
+        port,
+    }
+}
+
+#[test]
+fn parses_hosts_with_and_without_port() {
+    assert_eq!(
+        parse("192.168.0.10:4000"),
+        Ok(address("192.168.0.10", 4000))
+    );
+    assert_eq!(
+        parse(" 192.168.0.10 "),
+        Ok(address("192.168.0.10", DEFAULT_PORT))
+    );
+    assert_eq!(
+        parse("myphone.local"),
+        Ok(address("myphone.local", DEFAULT_PORT))
+    );
+    assert_eq!(
+        parse("myphone.local:50551"),
+        Ok(address("myphone.local", 50551))
+    );
+}
+
+#[test]
+fn parses_ipv6_addresses() {
+    assert_eq!(parse("fe80::1"), Ok(address("fe80::1", DEFAULT_PORT)));
+    assert_eq!(parse("[fe80::1]"), Ok(address("fe80::1", DEFAULT_PORT)));
+    assert_eq!(parse("[fe80::1]:4000"), Ok(address("fe80::1", 4000)));
+    assert_eq!(address("fe80::1", 4000).to_string(), "[fe80::1]:4000");
+}
+
+#[test]
+fn explains_invalid_addresses() {
+    assert_eq!(parse(""), Err(AddressProblem::Empty));
+    assert_eq!(parse(":4000"), Err(AddressProblem::MissingHost));
+    assert_eq!(parse("[fe80::1"), Err(AddressProblem::UnclosedBracket));
+    assert_eq!(
+        parse("[phone]:4000"),
+        Err(AddressProblem::InvalidIpv6("phone".to_owned()))
+    );
+    assert_eq!(
+        parse("fe80::1:4000:x"),
+        Err(AddressProblem::UnbracketedIpv6)
+    );
+    assert_eq!(
+        parse("192.168.0.300"),
+        Err(AddressProblem::InvalidIpv4("192.168.0.300".to_owned()))
+    );
+    assert_eq!(
+        parse("my_phone"),
+        Err(AddressProblem::InvalidHostname("my_phone".to_owned()))
+    );
+    assert_eq!(
+        parse("192.168.0.10:0"),
+        Err(AddressProblem::InvalidPort("0".to_owned()))
+    );
+    assert_eq!(
+        parse("192.168.0.10:port"),
+        Err(AddressProblem::InvalidPort("port".to_owned()))
+    );
+}
+
+#[test]
+fn resolves_localhost() {
+    let addresses = address("localhost", 4000).resolve().unwrap();
+    assert!(addresses.iter().all(|address| address.port() == 4000));
+    assert!(addresses.iter().any(|address| address.ip().is_loopback()));
+}
@@ -0,0 +1,129 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    analysis::{
+        band_edges, AnalysisTap, Analyzer, Scope, Stage, FFT_SIZE, WAVEFORM_DECIMATION,
+        WAVEFORM_POINTS,
+    },
+    simulator::{Simulator, SimulatorConfig, Source},
+    socket::socket_connect,
+    stats::SessionStats,
+};
+use ringbuf::RingBuffer;
+
+fn sine(frequency: f32, amplitude: f32, samples: usize) -> Vec<i16> {
+    (0..samples)
+        .map(|index| {
+            let phase = 2.0 * std::f32::consts::PI * frequency * index as f32 / 48000.0;
+            (phase.sin() * amplitude * 32767.0) as i16
+        })
+        .collect()
+}
+
+#[test]
+fn finds_the_tone_in_the_spectrum() {
+    // right on a bin of the FFT
+    let frequency = 44.0 * 48000.0 / FFT_SIZE as f32;
+    let mut scope = Scope::new();
+    assert!(!scope.push(&sine(frequency, 0.5, FFT_SIZE - 1)));
+    assert!(scope.snapshot().spectrum.is_empty());
+    assert!(scope.push(&sine(frequency, 0.5, 48000)));
+
+    let snapshot = scope.snapshot();
+    let (loudest, level) = snapshot
+        .spectrum
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap();
+    let edges = band_edges();
+    assert!(edges[loudest] <= frequency && frequency <= edges[loudest + 1]);
+    // a half scale sine is 6dB below full scale
+    assert!((level + 6.0).abs() < 1.5, "{}", level);
+    assert!(snapshot.spectrum[0] < -60.0);
+    assert!((snapshot.peak - 0.5).abs() < 0.01);
+    assert!((snapshot.rms - 0.354).abs() < 0.01);
+}
+
+#[test]
+fn keeps_a_decimated_waveform() {
+    let mut scope = Scope::new();
+    scope.push(&sine(200.0, 1.0, 10 * WAVEFORM_DECIMATION + 1));
+    assert_eq!(scope.snapshot().waveform.len(), 10);
+    // a 200Hz period fits in a point
+    let (low, high) = scope.snapshot().waveform[0];
+    assert!(low < -0.99 && high > 0.99);
+
+    scope.push(&vec![0; WAVEFORM_POINTS * WAVEFORM_DECIMATION]);
+    assert_eq!(scope.snapshot().waveform.len(), WAVEFORM_POINTS);
+    assert_eq!(scope.snapshot().waveform.last(), Some(&(0.0, 0.0)));
+}
+
+#[test]
+fn analyzes_both_stages_off_the_caller() {
+    let analyzer = Analyzer::start(|| {});
+    let tap = AnalysisTap::default();
+    tap.push(Stage::Input, &sine(440.0, 0.5, FFT_SIZE));
+    tap.attach(analyzer.input());
+    for _ in 0..4 {
+        tap.push(Stage::Input, &sine(440.0, 0.5, FFT_SIZE));
+        tap.push(Stage::Processed, &vec![0; FFT_SIZE]);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let points = 4 * FFT_SIZE / WAVEFORM_DECIMATION;
+    while analyzer.snapshot(Stage::Input).waveform.len() < points
+        || analyzer.snapshot(Stage::Processed).spectrum.is_empty()
+    {
+        assert!(Instant::now() < deadline, "Never analyzed");
+        thread::sleep(Duration::from_millis(5));
+    }
+    let input = analyzer.snapshot(Stage::Input);
+    assert!((input.peak - 0.5).abs() < 0.01);
+    // only what was pushed while attached
+    assert_eq!(input.waveform.len(), points);
+    assert_eq!(analyzer.snapshot(Stage::Processed).peak, 0.0);
+}
+
+#[test]
+fn shows_the_averaging_filter_muffle_high_tones() {
+    let simulator = Simulator::bind(
+        "127.0.0.1:0",
+        SimulatorConfig {
+            source: Source::Samples(Arc::new(sine(16000.0, 0.5, 48000))),
+            framing: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let address = simulator.local_addr().unwrap().to_string();
+    thread::spawn(move || simulator.serve_next());
+    let (producer, _consumer) = RingBuffer::<i16>::new(48000).split();
+    let mut socket = socket_connect(&address, producer, SessionStats::new(), false).unwrap();
+    let analyzer = Analyzer::start(|| {});
+    let tap = AnalysisTap::default();
+    tap.attach(analyzer.input());
+    socket.set_analysis(tap);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while analyzer.snapshot(Stage::Input).spectrum.is_empty()
+        || analyzer.snapshot(Stage::Processed).spectrum.is_empty()
+    {
+        assert!(Instant::now() < deadline, "Never analyzed");
+        socket.receive().unwrap();
+    }
+    let input = analyzer.snapshot(Stage::Input);
+    let processed = analyzer.snapshot(Stage::Processed);
+    assert!((input.rms - 0.354).abs() < 0.01, "{}", input.rms);
+    // the filter takes more than half of a 16kHz tone
+    assert!(
+        processed.rms < input.rms / 2.0,
+        "{} {}",
+        input.rms,
+        processed.rms
+    );
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    capture::{CaptureWriter, ReplayTiming},
+    error::ErrorKind,
+    simulator::{Faults, Simulator, SimulatorConfig},
+    socket::{socket_connect, SocketState},
+    stats::SessionStats,
+};
+use ringbuf::{Consumer, RingBuffer};
+
+fn capture_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fast-mic-{}-{}.fmcap", name, std::process::id()))
+}
+
+/// Receives until the stream ends and returns everything decoded.
+fn drain(socket: &mut SocketState, consumer: &mut Consumer<i16>) -> (Vec<i16>, ErrorKind) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut samples = Vec::new();
+    loop {
+        let result = socket.receive();
+        samples.extend(std::iter::from_fn(|| consumer.pop()));
+        if let Err(err) = result {
+            return (samples, err.kind());
+        }
+        assert!(Instant::now() < deadline, "Stream never ended");
+    }
+}
+
+/// Captures a whole session with the simulator and returns what was played.
+fn capture_session(path: &Path, framed: bool) -> Vec<i16> {
+    let simulator = Simulator::bind(
+        "127.0.0.1:0",
+        SimulatorConfig {
+            framing: framed,
+            faults: Faults {
+                disconnect_after: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let address = simulator.local_addr().unwrap().to_string();
+    thread::spawn(move || simulator.serve_next());
+
+    let (producer, mut consumer) = RingBuffer::<i16>::new(48000).split();
+    let mut socket = socket_connect(&address, producer, SessionStats::new(), framed).unwrap();
+    socket.start_capture(path).unwrap();
+    let (samples, kind) = drain(&mut socket, &mut consumer);
+    assert_eq!(kind, ErrorKind::ConnectionLost);
+    assert!(!samples.is_empty());
+    samples
+}
+
+fn replay(path: &Path, timing: ReplayTiming) -> (Vec<i16>, ErrorKind) {
+    let (producer, mut consumer) = RingBuffer::<i16>::new(48000).split();
+    let mut socket = SocketState::replay(path, timing, producer, SessionStats::new()).unwrap();
+    drain(&mut socket, &mut consumer)
+}
+
+#[test]
+fn replays_a_raw_session_identically() {
+    let path = capture_file("raw");
+    let played = capture_session(&path, false);
+    let (replayed, kind) = replay(&path, ReplayTiming::Immediate);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(replayed, played);
+    // the end of the capture reads like the phone hanging up
+    assert_eq!(kind, ErrorKind::ConnectionLost);
+}
+
+#[test]
+fn replays_a_framed_session_identically() {
+    let path = capture_file("framed");
+    let played = capture_session(&path, true);
+    let (replayed, kind) = replay(&path, ReplayTiming::Immediate);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(replayed, played);
+    assert_eq!(kind, ErrorKind::ConnectionLost);
+}
+
+#[test]
+fn keeps_the_original_timing() {
+    let path = capture_file("timing");
+    let chunk = vec![0u8; 3840];
+    let mut writer = CaptureWriter::create(&path, false).unwrap();
+    writer.record_at(Duration::ZERO, &chunk).unwrap();
+    writer
+        .record_at(Duration::from_millis(150), &chunk)
+        .unwrap();
+    drop(writer);
+
+    let started = Instant::now();
+    let (samples, _) = replay(&path, ReplayTiming::Original);
+    assert!(started.elapsed() >= Duration::from_millis(150));
+    assert_eq!(samples.len(), 2 * 1920);
+
+    let started = Instant::now();
+    replay(&path, ReplayTiming::Immediate);
+    assert!(started.elapsed() < Duration::from_millis(150));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_files_that_are_not_captures() {
+    let path = capture_file("invalid");
+    fs::write(&path, b"RIFF0000WAVE").unwrap();
+    let (producer, _consumer) = RingBuffer::<i16>::new(48000).split();
+    let result = SocketState::replay(
+        &path,
+        ReplayTiming::Immediate,
+        producer,
+        SessionStats::new(),
+    );
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        result.err().map(|err| err.kind()),
+        Some(ErrorKind::Protocol)
+    );
+}
@@ -0,0 +1,192 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crossbeam_channel::Receiver;
+use fast_mic::{
+    common::{LoopMessage, UserAction},
+    control::{generate_token, ControlServer},
+    mute::MicState,
+    stats::SessionStats,
+};
+
+const TOKEN: &str = "0123456789abcdef";
+
+fn start() -> (ControlServer, Receiver<UserAction>) {
+    let (actions, received) = crossbeam_channel::unbounded();
+    let server = ControlServer::start(
+        "127.0.0.1:0",
+        TOKEN.to_owned(),
+        actions,
+        SessionStats::new(),
+    )
+    .unwrap();
+    (server, received)
+}
+
+fn send_request(address: SocketAddr, request_line: &str, token: &str, body: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "{}\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+        request_line,
+        token,
+        body.len(),
+        body
+    )
+    .unwrap();
+    stream
+}
+
+fn request(address: SocketAddr, request_line: &str, token: &str, body: &str) -> String {
+    let mut response = String::new();
+    send_request(address, request_line, token, body)
+        .read_to_string(&mut response)
+        .unwrap();
+    response
+}
+
+#[test]
+fn generates_different_tokens() {
+    let token = generate_token();
+    assert_eq!(token.len(), 32);
+    assert!(token.bytes().all(|digit| digit.is_ascii_hexdigit()));
+    assert_ne!(token, generate_token());
+}
+
+#[test]
+fn rejects_requests_without_the_token() {
+    let (server, actions) = start();
+    for token in ["", "0123456789abcdee", "0123456789abcdef0"] {
+        let response = request(
+            server.local_addr(),
+            "POST /actions HTTP/1.1",
+            token,
+            r#"{"action":"disconnect"}"#,
+        );
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    }
+    assert!(actions.try_recv().is_err());
+}
+
+#[test]
+fn bounds_headers_and_connections() {
+    let (server, _actions) = start();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(stream, "GET /stats HTTP/1.1\r\n").unwrap();
+    // all read by the server, so it doesn't reset the connection
+    for _ in 0..101 {
+        write!(stream, "X-Filler: 1\r\n").unwrap();
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+
+    let idle: Vec<TcpStream> = (0..32)
+        .map(|_| TcpStream::connect(server.local_addr()).unwrap())
+        .collect();
+    // closed without an answer, maybe before the request is written
+    let mut response = String::new();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(
+        stream,
+        "GET /stats HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+        TOKEN
+    )
+    .ok();
+    stream.read_to_string(&mut response).ok();
+    assert_eq!(response, "");
+    drop(idle);
+    // the connections are counted until their threads see them closed
+    let response = (0..50)
+        .map(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+            request(server.local_addr(), "GET /stats HTTP/1.1", TOKEN, "")
+        })
+        .find(|response| response.starts_with("HTTP/1.1 200"));
+    assert!(response.is_some());
+}
+
+#[test]
+fn turns_commands_into_actions() {
+    let (server, actions) = start();
+    let response = request(
+        server.local_addr(),
+        "POST /actions HTTP/1.1",
+        TOKEN,
+        r#"{"action":"connect","address":"192.168.1.20"}"#,
+    );
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    match actions.recv_timeout(Duration::from_secs(5)).unwrap() {
+        UserAction::Connect(address) => assert_eq!(address, "192.168.1.20"),
+        action => panic!("unexpected {:?}", action),
+    }
+
+    request(
+        server.local_addr(),
+        "POST /actions HTTP/1.1",
+        TOKEN,
+        r#"{"action":"toggle_mute"}"#,
+    );
+    assert!(matches!(
+        actions.recv_timeout(Duration::from_secs(5)).unwrap(),
+        UserAction::ToggleMute
+    ));
+
+    let response = request(
+        server.local_addr(),
+        "POST /actions HTTP/1.1",
+        TOKEN,
+        r#"{"action":"explode"}"#,
+    );
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    let response = request(server.local_addr(), "GET /nothing HTTP/1.1", TOKEN, "");
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+}
+
+#[test]
+fn answers_stats() {
+    let (server, _actions) = start();
+    let response = request(server.local_addr(), "GET /stats HTTP/1.1", TOKEN, "");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let stats: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(stats["bytes_received"], 0);
+}
+
+#[test]
+fn streams_loop_messages() {
+    let (server, actions) = start();
+    let stream = send_request(server.local_addr(), "GET /events HTTP/1.1", TOKEN, "");
+    let subscriber = match actions.recv_timeout(Duration::from_secs(5)).unwrap() {
+        UserAction::Subscribe(subscriber) => subscriber,
+        action => panic!("unexpected {:?}", action),
+    };
+    subscriber
+        .send(LoopMessage::Mic(MicState {
+            muted: true,
+            ..Default::default()
+        }))
+        .unwrap();
+
+    let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+    assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+    let event = lines
+        .find(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str::<serde_json::Value>(&line).unwrap())
+        .unwrap();
+    assert_eq!(
+        event,
+        serde_json::json!({
+            "event": "mic",
+            "muted": true,
+            "push_to_talk": false,
+            "talking": false,
+        })
+    );
+}
@@ -0,0 +1,394 @@
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpListener},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    audio::{AudioRecoveryPolicy, AudioSink, DeviceSelector},
+    common::{Communicator, LoopMessage, UserAction},
+    error::ErrorKind,
+    event_loop::{start_event_loop, SessionConfig},
+    memory_sink::{MemorySink, MEMORY_DEVICE},
+    reconnect::ReconnectPolicy,
+    session::SessionState,
+    stats::SessionStats,
+};
+use ringbuf::RingBuffer;
+
+// one chunk as read by the socket
+const CHUNK_SAMPLES: usize = 1920;
+
+struct Harness {
+    comm: Communicator<UserAction, LoopMessage>,
+    sink: MemorySink,
+    monitor: MemorySink,
+    stats: SessionStats,
+}
+
+fn start(audio_recovery_policy: AudioRecoveryPolicy) -> Harness {
+    let (comm, loop_comm) = Communicator::create_pair();
+    let sink = MemorySink::new(48000);
+    let monitor = MemorySink::new(24000);
+    let stats = SessionStats::new();
+    start_event_loop(
+        loop_comm,
+        Box::new(sink.clone()),
+        Box::new(monitor.clone()),
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        },
+        audio_recovery_policy,
+        stats.clone(),
+        || {},
+    );
+    Harness {
+        comm,
+        sink,
+        monitor,
+        stats,
+    }
+}
+
+/// Accepts `connections` clients one after the other, sends each `chunks`
+/// chunks of `value` and closes the connection once `keep_open` passed.
+fn phone(connections: usize, chunks: usize, value: i16, keep_open: Duration) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for _ in 0..connections {
+            let (mut stream, _) = listener.accept().unwrap();
+            let data: Vec<u8> = std::iter::repeat_n(value.to_le_bytes(), chunks * CHUNK_SAMPLES)
+                .flatten()
+                .collect();
+            stream.write_all(&data).unwrap();
+            thread::sleep(keep_open);
+        }
+    });
+    address
+}
+
+impl Harness {
+    fn wait_for(&mut self, mut matches: impl FnMut(&LoopMessage) -> bool) -> LoopMessage {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let message = self
+                .comm
+                .receive_timeout(timeout)
+                .expect("Expected message never arrived");
+            if matches(&message) {
+                return message;
+            }
+        }
+    }
+
+    fn wait_for_state(&mut self, name: &str) -> SessionState {
+        match self.wait_for(|message| {
+            matches!(message, LoopMessage::State(state) if state.name() == name)
+        }) {
+            LoopMessage::State(state) => state,
+            _ => unreachable!(),
+        }
+    }
+
+    fn wait_for_bytes(&self, bytes: u64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while self.stats.snapshot().bytes_received < bytes {
+            assert!(Instant::now() < deadline, "Samples never arrived");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn connect(&mut self, address: SocketAddr) {
+        self.comm
+            .send(UserAction::Connect(address.to_string()))
+            .unwrap();
+        self.wait_for_state("connected");
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.comm.send(UserAction::Exit).ok();
+    }
+}
+
+#[test]
+fn plays_received_samples_on_the_sink() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.connect(phone(1, 2, 1000, Duration::from_secs(5)));
+    harness.wait_for_bytes(2 * CHUNK_SAMPLES as u64 * 2);
+
+    harness.sink.advance(Duration::from_millis(100));
+    let recording = harness.sink.recording();
+    assert_eq!(recording.len(), 4800);
+    // every chunk is decoded on its own
+    assert_eq!(&recording[..2], &[500, 750]);
+    assert_eq!(&recording[CHUNK_SAMPLES..CHUNK_SAMPLES + 2], &[500, 750]);
+    assert!(recording[2 * CHUNK_SAMPLES..].iter().all(|&sample| sample == 0));
+    let snapshot = harness.stats.snapshot();
+    assert_eq!(snapshot.underruns, 1);
+    assert_eq!(snapshot.buffer_fill, 0);
+    assert_eq!(snapshot.output_sample_rate, 48000);
+}
+
+#[test]
+fn disconnecting_stops_the_output() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+    assert_eq!(harness.sink.running(), 1);
+
+    harness.comm.send(UserAction::UserDisconnect).unwrap();
+    harness.wait_for_state("idle");
+    assert_eq!(harness.sink.running(), 0);
+}
+
+#[test]
+#[should_panic(expected = "2 outputs are running")]
+fn records_one_output_at_a_time() {
+    let sink = MemorySink::new(48000);
+    let (failures, _) = crossbeam_channel::unbounded();
+    let _outputs: Vec<_> = (0..2)
+        .map(|_| {
+            let (_, consumer) = RingBuffer::<i16>::new(4800).split();
+            sink.start(
+                consumer,
+                SessionStats::new(),
+                &DeviceSelector::Default,
+                failures.clone(),
+            )
+            .unwrap()
+        })
+        .collect();
+    sink.advance(Duration::from_millis(10));
+}
+
+#[test]
+fn reopens_the_device_after_a_failure() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+
+    harness.sink.fail("unplugged");
+    harness.wait_for(|message| matches!(message, LoopMessage::AudioDeviceLost(_)));
+    match harness.wait_for(|message| matches!(message, LoopMessage::AudioDeviceRestored(_))) {
+        LoopMessage::AudioDeviceRestored(device) => assert_eq!(device, MEMORY_DEVICE),
+        _ => unreachable!(),
+    }
+    assert_eq!(harness.sink.running(), 1);
+}
+
+#[test]
+fn fails_when_the_device_stays_lost() {
+    let mut harness = start(AudioRecoveryPolicy {
+        attempts: 2,
+        retry_delay: Duration::from_millis(10),
+        fall_back_to_default: false,
+    });
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+
+    harness.sink.set_available(false);
+    harness.sink.fail("unplugged");
+    match harness.wait_for_state("failed") {
+        SessionState::Failed(err) => assert_eq!(err.kind(), ErrorKind::AudioDeviceLost),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn reconnects_when_the_phone_drops() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.connect(phone(2, 1, 1000, Duration::from_millis(50)));
+
+    harness.wait_for_state("reconnecting");
+    harness.wait_for_state("connected");
+    assert_eq!(harness.stats.snapshot().reconnects, 1);
+}
+
+#[test]
+fn monitors_at_its_own_rate_and_volume() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness
+        .comm
+        .send(UserAction::SetMonitor(Some(DeviceSelector::Default)))
+        .unwrap();
+    harness.comm.send(UserAction::SetMonitorVolume(0.5)).unwrap();
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+    harness.wait_for_bytes(CHUNK_SAMPLES as u64 * 2);
+
+    harness.monitor.advance(Duration::from_millis(40));
+    let recording = harness.monitor.recording();
+    // half the samples of the main output, at half the level
+    assert_eq!(recording.len(), CHUNK_SAMPLES / 2);
+    assert_eq!(&recording[..2], &[0, 375]);
+    assert_eq!(recording[100], 500);
+    // the main output is unaffected
+    harness.sink.advance(Duration::from_millis(40));
+    assert_eq!(&harness.sink.recording()[..2], &[500, 750]);
+
+    harness.comm.send(UserAction::SetMonitor(None)).unwrap();
+    harness.comm.send(UserAction::UserDisconnect).unwrap();
+    harness.wait_for_state("idle");
+    assert_eq!(harness.monitor.running(), 0);
+}
+
+#[test]
+fn reports_a_failed_monitor() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness
+        .comm
+        .send(UserAction::SetMonitor(Some(DeviceSelector::Named(
+            "Headphones".to_owned(),
+        ))))
+        .unwrap();
+    harness.wait_for(|message| matches!(message, LoopMessage::MonitorFailed(_)));
+    assert_eq!(harness.monitor.running(), 0);
+}
+
+#[test]
+fn muting_keeps_the_connection() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.comm.send(UserAction::SetMuted(true)).unwrap();
+    match harness.wait_for(|message| matches!(message, LoopMessage::Mic(_))) {
+        LoopMessage::Mic(state) => assert!(state.muted),
+        _ => unreachable!(),
+    }
+    harness.connect(phone(1, 2, 1000, Duration::from_secs(5)));
+    harness.wait_for_bytes(2 * CHUNK_SAMPLES as u64 * 2);
+
+    harness.sink.advance(Duration::from_millis(80));
+    let recording = harness.sink.recording();
+    assert_eq!(recording.len(), 2 * CHUNK_SAMPLES);
+    assert!(recording.iter().all(|&sample| sample == 0));
+    assert_eq!(harness.sink.running(), 1);
+}
+
+#[test]
+fn subscribers_get_the_current_state_first() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+    let (subscriber, messages) = crossbeam_channel::unbounded();
+    harness.comm.send(UserAction::Subscribe(subscriber)).unwrap();
+    harness.comm.send(UserAction::ToggleMute).unwrap();
+
+    let received: Vec<_> = messages
+        .iter()
+        .take(3)
+        .map(|message| match message {
+            LoopMessage::State(state) => state.name().to_owned(),
+            LoopMessage::Mic(state) => format!("muted: {}", state.muted),
+            message => panic!("unexpected {:?}", message),
+        })
+        .collect();
+    assert_eq!(received, ["connected", "muted: false", "muted: true"]);
+}
+
+#[test]
+fn configures_the_next_session() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness
+        .comm
+        .send(UserAction::Configure(SessionConfig {
+            buffer_capacity: 4800,
+            ..Default::default()
+        }))
+        .unwrap();
+    harness.connect(phone(1, 1, 1000, Duration::from_secs(5)));
+    assert_eq!(harness.stats.snapshot().buffer_capacity, 4800);
+
+    harness.comm.send(UserAction::UserDisconnect).unwrap();
+    harness.wait_for_state("idle");
+    harness
+        .comm
+        .send(UserAction::Configure(SessionConfig {
+            device: DeviceSelector::Named("Speakers".to_owned()),
+            ..Default::default()
+        }))
+        .unwrap();
+    harness
+        .comm
+        .send(UserAction::Connect("127.0.0.1:9".to_owned()))
+        .unwrap();
+    match harness.wait_for_state("failed") {
+        SessionState::Failed(err) => assert_eq!(err.kind(), ErrorKind::AudioDeviceNotFound),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn reports_the_phone_it_connected_to() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    let address = phone(1, 1, 1000, Duration::from_secs(5));
+    harness.connect(address);
+    match harness.wait_for(|message| matches!(message, LoopMessage::Connected { .. })) {
+        LoopMessage::Connected {
+            address: connected_to,
+            peer,
+        } => {
+            assert_eq!(connected_to, address.to_string());
+            assert_eq!(peer, Some(address));
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn a_silent_phone_does_not_hold_up_the_loop() {
+    let mut harness = start(AudioRecoveryPolicy::default());
+    harness
+        .comm
+        .send(UserAction::SetLatencyMeasurement(true))
+        .unwrap();
+    harness
+        .comm
+        .send(UserAction::Listen("127.0.0.1:0".to_owned()))
+        .unwrap();
+    let address = match harness.wait_for_state("listening") {
+        SessionState::Listening { address } => address,
+        _ => unreachable!(),
+    };
+    // connects and never answers the handshake
+    let _phone = std::net::TcpStream::connect(address.as_str()).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let asked = Instant::now();
+    harness.comm.send(UserAction::UserDisconnect).unwrap();
+    harness.wait_for_state("idle");
+    assert!(asked.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn drops_samples_quietly_while_the_device_is_reopened() {
+    let mut harness = start(AudioRecoveryPolicy {
+        attempts: 100,
+        retry_delay: Duration::from_millis(50),
+        fall_back_to_default: false,
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // a chunk every 10ms for a second, as a phone streams
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let chunk: Vec<u8> = std::iter::repeat_n(1000i16.to_le_bytes(), CHUNK_SAMPLES)
+            .flatten()
+            .collect();
+        for _ in 0..100 {
+            if stream.write_all(&chunk).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+    harness.connect(address);
+
+    harness.sink.set_available(false);
+    harness.sink.fail("unplugged");
+    harness.wait_for(|message| matches!(message, LoopMessage::AudioDeviceLost(_)));
+    // far more than the buffer holds
+    thread::sleep(Duration::from_millis(300));
+    harness.sink.set_available(true);
+    harness.wait_for(|message| matches!(message, LoopMessage::AudioDeviceRestored(_)));
+    assert_eq!(harness.stats.snapshot().overruns, 0);
+}
@@ -0,0 +1,114 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    error::ErrorKind,
+    socket::{socket_listen, SocketListener, SocketState},
+    stats::SessionStats,
+};
+use ringbuf::{Consumer, RingBuffer};
+
+// bytes read by one receive
+const CHUNK_BYTES: usize = 3840;
+// fit in the buffer of `accept`
+const CHUNKS: usize = 4;
+
+fn send_samples(address: SocketAddr, value: i16) -> thread::JoinHandle<TcpStream> {
+    thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let data: Vec<u8> = std::iter::repeat_n(value.to_le_bytes(), CHUNKS * CHUNK_BYTES / 2)
+            .flatten()
+            .collect();
+        stream.write_all(&data).unwrap();
+        stream
+    })
+}
+
+fn receive_all(socket: &mut SocketState) {
+    for _ in 0..CHUNKS {
+        socket.receive().unwrap();
+    }
+}
+
+fn accept(listener: &SocketListener, stats: &SessionStats) -> (SocketState, Consumer<i16>) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let (producer, consumer) = RingBuffer::<i16>::new(10000).split();
+        if let Some(phone) = listener.accept().unwrap() {
+            return (
+                phone.start(producer, stats.clone(), false).unwrap(),
+                consumer,
+            );
+        }
+        assert!(Instant::now() < deadline, "No connection accepted");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn receives_samples_from_connecting_sender() {
+    let listener = socket_listen("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let stats = SessionStats::new();
+    let sender = send_samples(address, 1000);
+
+    let (mut socket, mut consumer) = accept(&listener, &stats);
+    receive_all(&mut socket);
+    sender.join().unwrap();
+
+    assert_eq!(
+        stats.snapshot().bytes_received,
+        (CHUNKS * CHUNK_BYTES) as u64
+    );
+    assert_eq!(consumer.pop(), Some(500));
+    assert_eq!(consumer.pop(), Some(750));
+}
+
+#[test]
+fn accepts_next_sender_after_disconnect() {
+    let listener = socket_listen("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let stats = SessionStats::new();
+
+    for value in [1000, -1000] {
+        let sender = send_samples(address, value);
+        let (mut socket, mut consumer) = accept(&listener, &stats);
+        receive_all(&mut socket);
+        drop(sender.join().unwrap());
+        assert_eq!(consumer.pop(), Some(value / 2));
+        assert_eq!(
+            socket.receive().unwrap_err().kind(),
+            ErrorKind::ConnectionLost
+        );
+        socket.disconnect().ok();
+    }
+    assert_eq!(
+        stats.snapshot().bytes_received,
+        2 * (CHUNKS * CHUNK_BYTES) as u64
+    );
+}
+
+#[test]
+fn rejects_senders_while_one_is_streaming() {
+    let listener = socket_listen("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let stats = SessionStats::new();
+    let sender = send_samples(address, 1000);
+    let (_socket, _consumer) = accept(&listener, &stats);
+
+    let mut intruder = TcpStream::connect(address).unwrap();
+    intruder
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while listener.reject_pending() == 0 {
+        assert!(Instant::now() < deadline, "Intruder was never rejected");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(intruder.read(&mut [0u8; 1]).unwrap(), 0);
+    sender.join().unwrap();
+}
@@ -0,0 +1,82 @@
+use std::fs;
+
+use fast_mic::{
+    logging::{LogLevel, Logging},
+    settings::LoggingSettings,
+};
+
+#[test]
+fn keeps_events_with_their_spans() {
+    let settings = LoggingSettings {
+        files: false,
+        ..LoggingSettings::default()
+    };
+    let (logging, subscriber) = Logging::new(&settings, None);
+    tracing::subscriber::with_default(subscriber, || {
+        let _session = tracing::info_span!("session", generation = 3).entered();
+        tracing::warn!(attempt = 2, "Reconnecting to {}", "10.0.0.5");
+        tracing::debug!("Not at the default level");
+    });
+
+    let lines = logging.lines().snapshot();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].level, LogLevel::Warn);
+    assert_eq!(lines[0].spans, "session{generation=3}: ");
+    assert_eq!(lines[0].message, "Reconnecting to 10.0.0.5 attempt=2");
+    assert_eq!(lines[0].target, "logging");
+    assert!(lines[0]
+        .to_string()
+        .ends_with(" WARN session{generation=3}: logging: Reconnecting to 10.0.0.5 attempt=2"));
+    assert_eq!(logging.directory(), None);
+}
+
+#[test]
+fn changes_the_level_while_running() {
+    let (logging, subscriber) = Logging::new(&LoggingSettings::default(), None);
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!("before");
+        logging.set_level(LogLevel::Error);
+        tracing::warn!("hidden");
+        logging.set_level(LogLevel::Trace);
+        tracing::trace!("after");
+    });
+    let messages: Vec<_> = logging
+        .lines()
+        .snapshot()
+        .into_iter()
+        .map(|line| line.message)
+        .collect();
+    assert_eq!(messages, ["before", "after"]);
+    logging.lines().clear();
+    assert!(logging.lines().snapshot().is_empty());
+}
+
+#[test]
+fn writes_log_files() {
+    let directory = std::env::temp_dir().join(format!("fast-mic-logs-{}", std::process::id()));
+    fs::remove_dir_all(&directory).ok();
+    let (logging, subscriber) = Logging::new(&LoggingSettings::default(), Some(&directory));
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::error!(device = "Speakers", "Stream failed");
+    });
+    assert_eq!(logging.directory(), Some(directory.as_path()));
+
+    let files: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_str().unwrap();
+    assert!(
+        name.starts_with("fast-mic.") && name.ends_with(".log"),
+        "{}",
+        name
+    );
+    let text = fs::read_to_string(&files[0]).unwrap();
+    assert!(
+        text.contains("ERROR") && text.contains("Stream failed device=\"Speakers\""),
+        "{}",
+        text
+    );
+    fs::remove_dir_all(&directory).ok();
+}
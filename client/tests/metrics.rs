@@ -0,0 +1,84 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use fast_mic::{
+    metrics::{render, MetricsFormat, MetricsServer},
+    stats::{SessionStats, StatsSnapshot},
+};
+
+fn get(address: SocketAddr, path: &str, accept: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n\r\n",
+        path, accept
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_the_session_statistics() {
+    let stats = SessionStats::new();
+    stats.reset();
+    stats.set_buffer_capacity(9600);
+    stats.set_buffer_fill(4800);
+    stats.record_chunk(3840, 1920);
+    stats.record_reconnect();
+    stats.record_underrun();
+    let server = MetricsServer::start("127.0.0.1:0", stats).unwrap();
+
+    let response = get(server.local_addr(), "/metrics", "text/plain");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    for line in [
+        "# TYPE fastmic_reconnects_total counter",
+        "fastmic_reconnects_total 1",
+        "fastmic_underruns_total 1",
+        "fastmic_overruns_total 0",
+        "fastmic_received_bytes_total 3840",
+        "# TYPE fastmic_buffer_fill_samples gauge",
+        "fastmic_buffer_fill_samples 4800",
+        "fastmic_buffer_capacity_samples 9600",
+    ] {
+        assert!(body.lines().any(|body_line| body_line == line), "{}", body);
+    }
+    assert!(body.contains("fastmic_bitrate_bits_per_second "));
+    // not measured without the handshake
+    assert!(!body.contains("fastmic_round_trip_seconds"));
+    assert!(!body.contains("# EOF"));
+
+    let response = get(
+        server.local_addr(),
+        "/metrics",
+        "application/openmetrics-text; version=1.0.0",
+    );
+    assert!(response.contains("Content-Type: application/openmetrics-text"));
+    assert!(response.contains("# TYPE fastmic_reconnects counter\n"));
+    assert!(response.contains("\nfastmic_reconnects_total 1\n"));
+    assert!(response.ends_with("# EOF\n"));
+
+    assert!(get(server.local_addr(), "/stats", "*/*").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn converts_latencies_to_seconds() {
+    let snapshot = StatsSnapshot {
+        estimated_latency_ms: 250.0,
+        round_trip_ms: Some(20.0),
+        ..Default::default()
+    };
+    let text = render(&snapshot, MetricsFormat::Prometheus);
+    assert!(text.contains("\nfastmic_estimated_latency_seconds 0.25\n"));
+    assert!(text.contains("\nfastmic_round_trip_seconds 0.02\n"));
+    assert!(text.contains("\nfastmic_bitrate_bits_per_second 0\n"));
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use fast_mic::mute::{MicGate, MicState};
+
+#[test]
+fn push_to_talk_only_opens_while_talking() {
+    let gate = MicGate::new();
+    assert!(gate.state().is_open());
+    gate.set_push_to_talk(true);
+    assert!(!gate.state().is_open());
+    gate.set_talking(true);
+    assert!(gate.state().is_open());
+    gate.set_muted(true);
+    assert_eq!(
+        gate.state(),
+        MicState {
+            muted: true,
+            push_to_talk: true,
+            talking: true,
+        }
+    );
+    assert!(!gate.state().is_open());
+}
+
+#[test]
+fn fades_instead_of_cutting() {
+    let gate = MicGate::new();
+    let mut samples = [10000i16; 1000];
+    gate.apply(&mut samples);
+    assert!(samples.iter().all(|&sample| sample == 10000));
+
+    gate.set_muted(true);
+    gate.apply(&mut samples);
+    assert!(samples.windows(2).all(|pair| pair[0] - pair[1] <= 21));
+    assert!(samples[0] > 9900);
+    assert!(samples[500..].iter().all(|&sample| sample == 0));
+
+    gate.set_muted(false);
+    let mut samples = [10000i16; 1000];
+    gate.apply(&mut samples);
+    assert!(samples.windows(2).all(|pair| pair[1] - pair[0] <= 21));
+    assert!(samples[0] < 100);
+    assert!(samples[500..].iter().all(|&sample| sample == 10000));
+}
+
+#[test]
+fn cuts_without_a_fade() {
+    let gate = MicGate::new();
+    gate.set_fade(Duration::ZERO);
+    gate.set_muted(true);
+    let mut samples = [10000i16; 10];
+    gate.apply(&mut samples);
+    assert!(samples.iter().all(|&sample| sample == 0));
+
+    gate.set_fade(Duration::from_millis(100));
+    gate.set_muted(false);
+    let mut samples = [10000i16; 2400];
+    gate.apply(&mut samples);
+    assert!(samples[2399] < 5100 && samples[2399] > 4900);
+}
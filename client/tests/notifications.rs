@@ -0,0 +1,144 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    common::LoopMessage,
+    error::SessionError,
+    notifications::{Notification, NotificationKind, Notifier},
+    reconnect::ReconnectPolicy,
+    session::{SessionEvent, SessionMachine, SessionState},
+    settings::NotificationSettings,
+};
+
+fn reconnecting(attempt: u32) -> SessionState {
+    SessionState::Reconnecting {
+        attempt,
+        max_attempts: Some(5),
+        retry_at: Instant::now(),
+    }
+}
+
+fn connected() -> SessionState {
+    SessionState::Connected {
+        listen_address: None,
+    }
+}
+
+/// Feeds `events` to `machine` and returns the notifications the window
+/// would show for the states it publishes.
+fn notified(
+    machine: &mut SessionMachine,
+    events: impl IntoIterator<Item = SessionEvent>,
+) -> Vec<NotificationKind> {
+    let now = Instant::now();
+    let mut kinds = Vec::new();
+    for event in events {
+        let previous = machine.state().clone();
+        machine.handle(event, now).unwrap();
+        let message = LoopMessage::State(machine.state().clone());
+        kinds.extend(Notification::for_message(&previous, &message).map(|n| n.kind));
+    }
+    kinds
+}
+
+fn lost() -> SessionError {
+    SessionError::ConnectionLost(io::ErrorKind::UnexpectedEof.into())
+}
+
+#[test]
+fn follows_the_session() {
+    let mut machine = SessionMachine::new(ReconnectPolicy {
+        max_attempts: Some(2),
+        ..Default::default()
+    });
+    assert_eq!(
+        notified(
+            &mut machine,
+            [SessionEvent::Connect, SessionEvent::Connected]
+        ),
+        []
+    );
+    // only once per drop out, however many attempts it takes
+    assert_eq!(
+        notified(
+            &mut machine,
+            [
+                SessionEvent::ConnectionLost(lost()),
+                SessionEvent::RetryDue,
+                SessionEvent::ConnectFailed(lost()),
+                SessionEvent::RetryDue,
+                SessionEvent::Connected,
+            ]
+        ),
+        [
+            NotificationKind::Reconnecting,
+            NotificationKind::Reconnected
+        ]
+    );
+    assert_eq!(
+        notified(
+            &mut machine,
+            [
+                SessionEvent::ConnectionLost(lost()),
+                SessionEvent::RetryDue,
+                SessionEvent::ConnectFailed(lost()),
+                SessionEvent::RetryDue,
+                SessionEvent::ConnectFailed(lost()),
+            ]
+        ),
+        [NotificationKind::Reconnecting, NotificationKind::Failed]
+    );
+    assert!(matches!(machine.state(), SessionState::Failed(_)));
+
+    // the user sees connecting fail in the window
+    assert_eq!(
+        notified(
+            &mut machine,
+            [SessionEvent::Connect, SessionEvent::ConnectFailed(lost())]
+        ),
+        []
+    );
+    assert_eq!(
+        Notification::for_message(
+            &connected(),
+            &LoopMessage::AudioDeviceLost("Speakers".to_owned())
+        )
+        .map(|notification| notification.kind),
+        Some(NotificationKind::AudioDeviceLost)
+    );
+}
+
+#[test]
+fn limits_each_kind() {
+    let shown = Arc::new(Mutex::new(Vec::new()));
+    let log = shown.clone();
+    let mut notifier = Notifier::new(move |notification| {
+        log.lock().unwrap().push(notification.kind);
+    });
+    let mut settings = NotificationSettings::default();
+    let drop_out =
+        Notification::for_message(&connected(), &LoopMessage::State(reconnecting(1))).unwrap();
+    let lost =
+        Notification::for_message(&connected(), &LoopMessage::AudioDeviceLost(String::new()))
+            .unwrap();
+
+    let start = Instant::now();
+    assert!(notifier.notify(&drop_out, &settings, start));
+    assert!(!notifier.notify(&drop_out, &settings, start + Duration::from_secs(29)));
+    assert!(notifier.notify(&lost, &settings, start + Duration::from_secs(29)));
+    assert!(notifier.notify(&drop_out, &settings, start + Duration::from_secs(30)));
+
+    settings.reconnecting = false;
+    assert!(!notifier.notify(&drop_out, &settings, start + Duration::from_secs(600)));
+    assert_eq!(
+        *shown.lock().unwrap(),
+        [
+            NotificationKind::Reconnecting,
+            NotificationKind::AudioDeviceLost,
+            NotificationKind::Reconnecting
+        ]
+    );
+}
@@ -0,0 +1,105 @@
+use std::{fs::File, io::Read, path::PathBuf};
+
+use fast_mic::{
+    audio::{write_pcm, AudioSink, DeviceSelector},
+    error::ErrorKind,
+    output::{OutputKind, PcmSink, PcmTarget},
+    stats::SessionStats,
+};
+use ringbuf::RingBuffer;
+
+#[test]
+fn parses_outputs() {
+    for (input, output) in [
+        ("device", OutputKind::Device),
+        ("stdout", OutputKind::Stdout),
+        (
+            "fifo:/tmp/fast-mic",
+            OutputKind::Fifo(PathBuf::from("/tmp/fast-mic")),
+        ),
+        ("jack", OutputKind::Jack { connect_to: None }),
+        (
+            "jack:system:playback_1",
+            OutputKind::Jack {
+                connect_to: Some("system:playback_1".to_owned()),
+            },
+        ),
+    ] {
+        assert_eq!(input.parse::<OutputKind>(), Ok(output.clone()));
+        assert_eq!(output.to_string(), input);
+    }
+    for input in ["", "speakers", "fifo", "fifo:", "stdout:x"] {
+        assert!(
+            input.parse::<OutputKind>().is_err(),
+            "{} was accepted",
+            input
+        );
+    }
+}
+
+#[test]
+fn writes_pcm_into_a_named_pipe() {
+    let path = std::env::temp_dir().join(format!("fast-mic-{}.pcm", std::process::id()));
+    let sink = PcmSink::new(PcmTarget::Fifo(path.clone()), 2);
+    let (mut producer, consumer) = RingBuffer::<i16>::new(4800).split();
+    for sample in [1, -2, 3] {
+        producer.push(sample).unwrap();
+    }
+    let (failures, _) = crossbeam_channel::unbounded();
+    let audio_state = sink
+        .start(
+            consumer,
+            SessionStats::new(),
+            &DeviceSelector::default(),
+            failures,
+        )
+        .unwrap();
+    assert_eq!(audio_state.device_name(), path.display().to_string());
+
+    let mut bytes = [0u8; 16];
+    File::open(&path).unwrap().read_exact(&mut bytes).unwrap();
+    audio_state.stop().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let samples: Vec<i16> = bytes
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect();
+    // every sample on both channels, then silence
+    assert_eq!(samples, [1, 1, -2, -2, 3, 3, 0, 0]);
+}
+
+#[test]
+fn only_reopens_the_same_output() {
+    let sink = PcmSink::new(PcmTarget::Stdout, 1);
+    let (_, consumer) = RingBuffer::<i16>::new(4800).split();
+    let (failures, _) = crossbeam_channel::unbounded();
+    let result = sink.start(
+        consumer,
+        SessionStats::new(),
+        &DeviceSelector::Named("CABLE Input".to_owned()),
+        failures,
+    );
+    assert_eq!(
+        result.err().map(|err| err.kind()),
+        Some(ErrorKind::AudioDeviceNotFound)
+    );
+}
+
+#[test]
+fn fills_byte_buffers_with_silence_after_the_samples() {
+    let (mut producer, mut consumer) = RingBuffer::<i16>::new(4800).split();
+    for sample in [1, -2] {
+        producer.push(sample).unwrap();
+    }
+    let stats = SessionStats::new();
+    // 3 stereo frames and a partial one
+    let mut bytes = [0xffu8; 14];
+    assert_eq!(write_pcm(&mut bytes, 2, &mut consumer, &stats), 3);
+    let samples: Vec<i16> = bytes[..12]
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect();
+    assert_eq!(samples, [1, 1, -2, -2, 0, 0]);
+    assert_eq!(bytes[12..], [0xff, 0xff]);
+    assert_eq!(stats.snapshot().underruns, 1);
+}
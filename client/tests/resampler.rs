@@ -0,0 +1,32 @@
+use fast_mic::resampler::Resampler;
+use proptest::prelude::*;
+
+#[test]
+fn interpolates_between_samples() {
+    let mut resampler = Resampler::new(48000, 96000);
+    let mut output = Vec::new();
+    resampler.process(&[100, 200], &mut output);
+    assert_eq!(output, [0, 50, 100, 150]);
+}
+
+proptest! {
+    #[test]
+    fn chunking_doesnt_change_the_output(
+        samples in prop::collection::vec(any::<i16>(), 1..2000),
+        split in 0usize..2000,
+        to_rate in prop::sample::select(vec![44100u32, 48000, 96000, 16000]),
+    ) {
+        let split = split.min(samples.len());
+        let mut whole = Vec::new();
+        Resampler::new(48000, to_rate).process(&samples, &mut whole);
+
+        let mut resampler = Resampler::new(48000, to_rate);
+        let mut chunked = Vec::new();
+        resampler.process(&samples[..split], &mut chunked);
+        resampler.process(&samples[split..], &mut chunked);
+        prop_assert_eq!(&chunked, &whole);
+
+        let expected = samples.len() as f64 * to_rate as f64 / 48000.0;
+        prop_assert!((whole.len() as f64 - expected).abs() <= 1.0);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    error::SessionError,
+    reconnect::ReconnectPolicy,
+    session::{SessionEvent, SessionMachine, SessionState},
+};
+use proptest::prelude::*;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Connect,
+    Listen,
+    Disconnect,
+    Connected,
+    ConnectFailed,
+    ConnectionLost,
+    RetryDue,
+    Failed,
+}
+
+fn event(step: &Step) -> SessionEvent {
+    let lost = || SessionError::ConnectionLost(io::ErrorKind::UnexpectedEof.into());
+    match step {
+        Step::Connect => SessionEvent::Connect,
+        Step::Listen => SessionEvent::Listen {
+            address: "0.0.0.0:50551".to_owned(),
+        },
+        Step::Disconnect => SessionEvent::Disconnect,
+        Step::Connected => SessionEvent::Connected,
+        Step::ConnectFailed => SessionEvent::ConnectFailed(lost()),
+        Step::ConnectionLost => SessionEvent::ConnectionLost(lost()),
+        Step::RetryDue => SessionEvent::RetryDue,
+        Step::Failed => SessionEvent::Failed(SessionError::AudioDeviceNotFound),
+    }
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        Just(Step::Connect),
+        Just(Step::Listen),
+        Just(Step::Disconnect),
+        Just(Step::Connected),
+        Just(Step::ConnectFailed),
+        Just(Step::ConnectionLost),
+        Just(Step::RetryDue),
+        Just(Step::Failed),
+    ]
+}
+
+fn policy() -> impl Strategy<Value = ReconnectPolicy> {
+    prop_oneof![
+        (0..4u32).prop_map(|max_attempts| ReconnectPolicy {
+            max_attempts: Some(max_attempts),
+            ..Default::default()
+        }),
+        Just(ReconnectPolicy::infinite()),
+    ]
+}
+
+fn assert_valid(state: &SessionState, policy: &ReconnectPolicy, now: Instant) {
+    let longest_delay = policy.max_delay.mul_f64(1.0 + policy.jitter);
+    match state {
+        SessionState::Idle | SessionState::Failed(_) => {}
+        SessionState::Connecting { attempt } => {
+            assert!(*attempt == 0 || policy.allows(*attempt));
+        }
+        SessionState::Reconnecting {
+            attempt,
+            max_attempts,
+            retry_at,
+        } => {
+            assert!(*attempt >= 1 && policy.allows(*attempt));
+            assert_eq!(*max_attempts, policy.max_attempts);
+            assert!(*retry_at <= now + longest_delay);
+        }
+        SessionState::Listening { address } => assert_eq!(address, "0.0.0.0:50551"),
+        SessionState::Connected { listen_address } => {
+            if let Some(address) = listen_address {
+                assert_eq!(address, "0.0.0.0:50551");
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn every_sequence_ends_in_a_valid_state(
+        policy in policy(),
+        steps in prop::collection::vec(step(), 0..64),
+    ) {
+        let now = Instant::now();
+        let mut machine = SessionMachine::new(policy.clone());
+        for step in &steps {
+            let before = format!("{:?}", machine.state());
+            if machine.handle(event(step), now).is_err() {
+                // rejected events leave the state alone
+                prop_assert_eq!(before, format!("{:?}", machine.state()));
+            }
+            assert_valid(machine.state(), &policy, now);
+        }
+    }
+
+    #[test]
+    fn the_user_can_always_stop_or_start(
+        policy in policy(),
+        steps in prop::collection::vec(step(), 0..64),
+    ) {
+        let now = Instant::now();
+        let mut machine = SessionMachine::new(policy);
+        for step in &steps {
+            machine.handle(event(step), now).ok();
+        }
+        if machine.state().can_start() {
+            prop_assert!(machine.handle(SessionEvent::Connect, now).is_ok());
+            prop_assert_eq!(machine.state().name(), "connecting");
+        } else {
+            prop_assert!(machine.handle(SessionEvent::Disconnect, now).is_ok());
+            prop_assert_eq!(machine.state().name(), "idle");
+        }
+    }
+
+    #[test]
+    fn reconnecting_gives_up_after_max_attempts(max_attempts in 0..6u32) {
+        let now = Instant::now();
+        let mut machine = SessionMachine::new(ReconnectPolicy {
+            max_attempts: Some(max_attempts),
+            ..Default::default()
+        });
+        for step in [Step::Connect, Step::Connected, Step::ConnectionLost] {
+            machine.handle(event(&step), now).unwrap();
+        }
+        let mut attempts = 0;
+        while let SessionState::Reconnecting { .. } = machine.state() {
+            attempts += 1;
+            machine.handle(SessionEvent::RetryDue, now).unwrap();
+            machine.handle(event(&Step::ConnectFailed), now).unwrap();
+        }
+        prop_assert_eq!(attempts, max_attempts);
+        let gave_up_after = match machine.state() {
+            SessionState::Failed(err) => match **err {
+                SessionError::ReconnectFailed { attempts, .. } => Some(attempts),
+                _ => None,
+            },
+            _ => None,
+        };
+        prop_assert_eq!(gave_up_after, Some(max_attempts));
+    }
+}
+
+#[test]
+fn listen_mode_waits_again_after_losing_the_phone() {
+    let now = Instant::now();
+    let mut machine = SessionMachine::new(ReconnectPolicy::default());
+    for step in [Step::Listen, Step::Connected, Step::ConnectionLost] {
+        machine.handle(event(&step), now).unwrap();
+    }
+    assert!(matches!(machine.state(), SessionState::Listening { .. }));
+    assert!(machine.handle(SessionEvent::Connect, now).is_err());
+    assert!(machine.handle(SessionEvent::RetryDue, now + Duration::from_secs(60)).is_err());
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use fast_mic::{
+    audio::DeviceSelector,
+    logging::LogLevel,
+    settings::{Overrides, Settings, SettingsError, DEFAULT_PROFILE, MAX_RECENTS, VERSION},
+};
+
+#[test]
+fn round_trips_through_toml() {
+    let mut settings = Settings::default();
+    settings.profile_mut().connection.address = "192.168.1.20".to_owned();
+    settings.duplicate("Podcast");
+    settings.profile_mut().latency.buffer_capacity = 4800;
+    settings.logging.level = LogLevel::Debug;
+    assert_eq!(Settings::parse(&settings.to_toml()).unwrap(), settings);
+}
+
+#[test]
+fn never_saves_the_command_line() {
+    let path = std::env::temp_dir().join(format!("fast-mic-settings-{}.toml", std::process::id()));
+    let mut settings = Settings::default();
+    settings.profile_mut().device.output = "device".to_owned();
+    settings.save(&path).unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+
+    let settings = Settings::load(&path).unwrap().unwrap();
+    let overrides = Overrides {
+        output: Some("stdout".to_owned()),
+        channels: Some(2),
+        control_address: Some("127.0.0.1:50552".to_owned()),
+    };
+    let device = overrides.device(&settings.profile().device);
+    assert_eq!((device.output.as_str(), device.channels), ("stdout", 2));
+    let control = overrides.control(&settings.control);
+    assert!(control.enabled);
+    assert_eq!(control.address, "127.0.0.1:50552");
+    assert_eq!(control.token, settings.control.token);
+    settings.save(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+    std::fs::remove_file(&path).ok();
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_user_reads_the_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("fast-mic-private-{}.toml", std::process::id()));
+    Settings::default().save(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn takes_over_the_eframe_storage() {
+    let storage = HashMap::from([("address", "192.168.1.20"), ("other", "ignored")]);
+    let settings =
+        Settings::from_storage(|key| storage.get(key).map(|value| value.to_string())).unwrap();
+    assert_eq!(settings.version, VERSION);
+    assert_eq!(settings.profile, DEFAULT_PROFILE);
+    let mut expected = Settings::default();
+    expected.profile_mut().connection.address = "192.168.1.20".to_owned();
+    assert_eq!(settings, expected);
+
+    let settings = Settings::from_storage(|_| None).unwrap();
+    assert_eq!(settings, Settings::default());
+}
+
+#[test]
+fn fills_in_what_is_missing() {
+    let settings = Settings::parse(
+        r#"
+        profile = "Desk"
+
+        [profiles.Podcast.connection]
+        address = "10.0.0.5"
+        reconnect_forever = true
+        "#,
+    )
+    .unwrap();
+    // the active profile doesn't exist
+    assert_eq!(settings.profile, "Podcast");
+    let config = settings.profile().session_config();
+    assert_eq!(
+        config.device,
+        DeviceSelector::Prefix("CABLE Input".to_owned())
+    );
+    assert_eq!(config.buffer_capacity, 10000);
+    assert_eq!(config.read_timeout, Duration::from_secs(10));
+    assert_eq!(config.reconnect_policy.max_attempts, None);
+
+    let settings = Settings::parse("").unwrap();
+    assert_eq!(settings, Settings::default());
+}
+
+#[test]
+fn keeps_the_read_timeout_usable() {
+    let read_timeout = |text: &str| {
+        Settings::parse(text)
+            .unwrap()
+            .profile()
+            .session_config()
+            .read_timeout
+    };
+    let profile = |timeout: u64| {
+        format!(
+            "[profiles.Default.connection]\nread_timeout_ms = {}",
+            timeout
+        )
+    };
+    assert_eq!(read_timeout(&profile(0)), Duration::from_millis(500));
+    assert_eq!(read_timeout(&profile(2000)), Duration::from_secs(2));
+    assert_eq!(
+        read_timeout(&profile(u64::MAX >> 1)),
+        Duration::from_secs(60)
+    );
+}
+
+#[test]
+fn refuses_newer_versions() {
+    let text = format!("version = {}", VERSION + 1);
+    assert!(matches!(
+        Settings::parse(&text),
+        Err(SettingsError::NewerVersion(version)) if version == VERSION + 1
+    ));
+    assert!(matches!(
+        Settings::parse("version = -1"),
+        Err(SettingsError::Parse(_))
+    ));
+}
+
+#[test]
+fn switches_between_profiles() {
+    let mut settings = Settings::default();
+    settings.duplicate("Podcast");
+    settings.profile_mut().device.device_prefix = String::new();
+    assert_eq!(
+        settings.profile().session_config().device,
+        DeviceSelector::Default
+    );
+    assert!(settings.select("Stage").is_err());
+    settings.select(DEFAULT_PROFILE).unwrap();
+    assert_eq!(settings.profile().device.device_prefix, "CABLE Input");
+
+    settings.remove();
+    assert_eq!(settings.profile, "Podcast");
+    settings.remove();
+    assert_eq!(settings.profiles.len(), 1);
+}
+
+#[test]
+fn remembers_recent_phones_and_favorites() {
+    let mut settings = Settings::default();
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    for (index, address) in (0..MAX_RECENTS + 2).map(|index| (index, format!("10.0.0.{}", index))) {
+        settings.record_connection(
+            &address,
+            address.clone(),
+            start + Duration::from_secs(index as u64),
+        );
+    }
+    settings.record_connection(
+        "10.0.0.5",
+        "10.0.0.5".to_owned(),
+        start + Duration::from_secs(60),
+    );
+    assert_eq!(settings.recents.len(), MAX_RECENTS);
+    assert_eq!(settings.recents[0].address, "10.0.0.5");
+    assert_eq!(settings.recents[0].last_seen, 1_700_000_060);
+    assert_eq!(settings.recents[1].address, "10.0.0.11");
+    assert!(settings
+        .recents
+        .iter()
+        .all(|recent| recent.address != "10.0.0.0"));
+
+    settings.pin("10.0.0.5");
+    settings.pin("10.0.0.5");
+    settings.favorites[0].name = "Work phone".to_owned();
+    assert_eq!(settings.favorite("10.0.0.5").unwrap().name, "Work phone");
+    assert_eq!(Settings::parse(&settings.to_toml()).unwrap(), settings);
+    settings.unpin("10.0.0.5");
+    assert!(settings.favorites.is_empty());
+}
+
+#[test]
+fn starts_from_the_last_phone() {
+    let mut settings = Settings::parse(
+        r#"
+        [startup]
+        minimized = true
+        "#,
+    )
+    .unwrap();
+    assert!(settings.startup.tray && settings.startup.minimized);
+    assert!(!settings.startup.auto_connect);
+
+    assert_eq!(settings.last_address(), None);
+    settings.profile_mut().connection.address = "192.168.1.20".to_owned();
+    assert_eq!(settings.last_address(), Some("192.168.1.20"));
+    settings.record_connection("10.0.0.5", String::new(), UNIX_EPOCH);
+    assert_eq!(settings.last_address(), Some("10.0.0.5"));
+}
@@ -0,0 +1,160 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use fast_mic::{
+    error::ErrorKind,
+    simulator::{Faults, Simulator, SimulatorConfig, Source},
+    socket::{socket_connect, SocketState},
+    stats::SessionStats,
+    wav::{read_wav, write_wav},
+};
+use ringbuf::{Consumer, RingBuffer};
+
+fn serve_once(config: SimulatorConfig) -> String {
+    let simulator = Simulator::bind("127.0.0.1:0", config).unwrap();
+    let address = simulator.local_addr().unwrap().to_string();
+    thread::spawn(move || simulator.serve_next());
+    address
+}
+
+fn connect(address: &str, measure_latency: bool) -> (SocketState, Consumer<i16>, SessionStats) {
+    let (producer, consumer) = RingBuffer::<i16>::new(48000).split();
+    let stats = SessionStats::new();
+    let socket = socket_connect(address, producer, stats.clone(), measure_latency).unwrap();
+    (socket, consumer, stats)
+}
+
+/// Receives until the connection ends, which has to happen within `limit`.
+fn receive_until_error(socket: &mut SocketState, limit: Duration) -> ErrorKind {
+    let deadline = Instant::now() + limit;
+    loop {
+        if let Err(err) = socket.receive() {
+            return err.kind();
+        }
+        assert!(Instant::now() < deadline, "Connection never ended");
+    }
+}
+
+#[test]
+fn streams_a_sweep_in_the_raw_format() {
+    let address = serve_once(SimulatorConfig {
+        framing: false,
+        ..Default::default()
+    });
+    let (mut socket, mut consumer, stats) = connect(&address, false);
+    for _ in 0..5 {
+        socket.receive().unwrap();
+    }
+    assert_eq!(stats.snapshot().bytes_received, 5 * 3840);
+    let samples: Vec<i16> = std::iter::from_fn(|| consumer.pop()).collect();
+    assert_eq!(samples.len(), 5 * 1920);
+    assert!(samples.iter().any(|&sample| sample.abs() > 1000));
+}
+
+#[test]
+fn answers_the_latency_handshake() {
+    let address = serve_once(SimulatorConfig {
+        source: Source::Silence,
+        ..Default::default()
+    });
+    let (mut socket, _consumer, stats) = connect(&address, true);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while stats.snapshot().round_trip_ms.is_none() {
+        socket.receive().unwrap();
+        assert!(Instant::now() < deadline, "No pong received");
+    }
+    // audio frames are timed once the clock offset is known
+    socket.receive().unwrap();
+    socket.receive().unwrap();
+    assert!(stats.snapshot().network_delay_ms.is_some());
+}
+
+#[test]
+fn corrupted_frames_break_the_connection() {
+    let address = serve_once(SimulatorConfig {
+        source: Source::Silence,
+        faults: Faults {
+            corrupt_every: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let (mut socket, _consumer, _stats) = connect(&address, true);
+    let kind = receive_until_error(&mut socket, Duration::from_secs(5));
+    assert_eq!(kind, ErrorKind::Protocol);
+}
+
+#[test]
+fn disconnects_after_the_configured_time() {
+    let address = serve_once(SimulatorConfig {
+        framing: false,
+        faults: Faults {
+            disconnect_after: Some(Duration::from_millis(100)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let (mut socket, _consumer, stats) = connect(&address, false);
+    let kind = receive_until_error(&mut socket, Duration::from_secs(5));
+    assert_eq!(kind, ErrorKind::ConnectionLost);
+    // 5 chunks of 20ms, read in chunks of 40ms
+    assert_eq!(stats.snapshot().bytes_received, 2 * 3840);
+}
+
+#[test]
+fn streams_wav_files() {
+    let path = std::env::temp_dir().join(format!("fast-mic-{}.wav", std::process::id()));
+    let samples: Vec<i16> = (0..4800).map(|i| (i % 100) as i16 * 100).collect();
+    write_wav(&mut BufWriter::new(File::create(&path).unwrap()), 48000, &samples).unwrap();
+    let wav = read_wav(&mut File::open(&path).unwrap()).unwrap();
+    assert_eq!(wav.sample_rate, 48000);
+    assert_eq!(wav.samples, samples);
+
+    let source = Source::wav(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let address = serve_once(SimulatorConfig {
+        source,
+        framing: false,
+        ..Default::default()
+    });
+    let (mut socket, mut consumer, _stats) = connect(&address, false);
+    socket.receive().unwrap();
+    // the client averages each sample with the previous one
+    assert_eq!(consumer.pop(), Some(0));
+    assert_eq!(consumer.pop(), Some(50));
+    assert_eq!(consumer.pop(), Some(125));
+}
+
+#[test]
+fn refuses_sources_it_cannot_play() {
+    let sweep = |from: f64| Source::Sweep {
+        from,
+        to: 8000.0,
+        period: Duration::from_secs(1),
+    };
+    for source in [Source::Samples(Arc::new(Vec::new())), sweep(0.0), sweep(f64::NAN)] {
+        let config = SimulatorConfig {
+            source,
+            ..Default::default()
+        };
+        let error = Simulator::bind("127.0.0.1:0", config).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+    assert!(sweep(20.0).validate().is_ok());
+}
+
+#[test]
+fn rejects_truncated_wav_files() {
+    let mut file = Vec::new();
+    write_wav(&mut file, 48000, &[1, 2, 3, 4]).unwrap();
+    // a data chunk declaring 4 GiB
+    let length = file.len();
+    file[length - 12..length - 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = read_wav(&mut file.as_slice()).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
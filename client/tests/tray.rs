@@ -0,0 +1,89 @@
+use std::time::Instant;
+
+use fast_mic::{
+    mute::MicState,
+    session::SessionState,
+    tray::{icon_pixels, TrayLight, TrayStatus, ICON_SIZE},
+};
+
+fn pixel(pixels: &[u8], x: usize, y: usize) -> &[u8] {
+    let start = (y * ICON_SIZE + x) * 4;
+    &pixels[start..start + 4]
+}
+
+#[test]
+fn shows_the_session_state() {
+    let mic = MicState::default();
+    let status = TrayStatus::new(&SessionState::Idle, &mic, true);
+    assert_eq!(status.light, TrayLight::Off);
+    assert!(status.can_connect);
+    assert!(status.window_shown);
+
+    let listening = SessionState::Listening {
+        address: "0.0.0.0:50500".to_owned(),
+    };
+    let status = TrayStatus::new(&listening, &mic, false);
+    assert_eq!(status.light, TrayLight::Waiting);
+    assert!(status.description.contains("0.0.0.0:50500"));
+    assert!(!status.can_connect);
+
+    let reconnecting = SessionState::Reconnecting {
+        attempt: 1,
+        max_attempts: None,
+        retry_at: Instant::now(),
+    };
+    assert_eq!(
+        TrayStatus::new(&reconnecting, &mic, false).light,
+        TrayLight::Waiting
+    );
+
+    let connected = SessionState::Connected {
+        listen_address: None,
+    };
+    let muted = MicState {
+        muted: true,
+        ..MicState::default()
+    };
+    let status = TrayStatus::new(&connected, &muted, false);
+    assert_eq!(status.light, TrayLight::On);
+    assert!(status.muted && !status.can_connect);
+}
+
+#[test]
+fn draws_a_ring_while_muted() {
+    let connected = SessionState::Connected {
+        listen_address: None,
+    };
+    let mut status = TrayStatus::new(&connected, &MicState::default(), true);
+    let [red, green, blue] = TrayLight::On.color();
+    let center = ICON_SIZE / 2;
+
+    let pixels = icon_pixels(&status);
+    assert_eq!(pixels.len(), ICON_SIZE * ICON_SIZE * 4);
+    assert_eq!(pixel(&pixels, center, center), [255, red, green, blue]);
+    assert_eq!(pixel(&pixels, 2, center), [255, red, green, blue]);
+    assert_eq!(pixel(&pixels, 0, 0), [0, 0, 0, 0]);
+
+    status.muted = true;
+    let pixels = icon_pixels(&status);
+    assert_eq!(pixel(&pixels, center, center), [0, 0, 0, 0]);
+    assert_eq!(pixel(&pixels, 2, center), [255, red, green, blue]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn asks_for_the_window_without_a_tray() {
+    use std::time::Duration;
+
+    use fast_mic::tray::{TrayCommand, TrayIcon};
+
+    // nothing to show the icon in
+    std::env::set_var("DBUS_SESSION_BUS_ADDRESS", "unix:path=/nonexistent/bus");
+    let (sender, commands) = crossbeam_channel::unbounded();
+    let status = TrayStatus::new(&SessionState::Idle, &MicState::default(), false);
+    let _icon = TrayIcon::start(status, sender, || {}).unwrap();
+    assert_eq!(
+        commands.recv_timeout(Duration::from_secs(5)),
+        Ok(TrayCommand::ShowWindow)
+    );
+}